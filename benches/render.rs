@@ -0,0 +1,61 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use multi_output_viewer::State;
+use std::io::{sink, Sink};
+
+const MAX_LINES: usize = 10;
+
+fn with_secondary_outputs(state: &mut State<Sink>, count: usize, lines: usize, expanded: bool) {
+    for i in 0..count {
+        let id = state.new_secondary_output(format!("output {i}"));
+        for line in 0..lines {
+            state
+                .handle_secondary_bytes(&id, format!("line {line}\r\n").as_bytes())
+                .unwrap();
+        }
+        if expanded {
+            state.move_cursor_to(i).toggle_current_selection_expanded();
+        }
+    }
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+
+    group.bench_function("primary_only_1kb", |b| {
+        let primary_bytes = "0123456789abcde\n".repeat(64);
+        let mut output = sink();
+        let mut state = State::new(&mut output, MAX_LINES);
+        b.iter(|| {
+            state
+                .handle_primary_bytes(primary_bytes.as_bytes())
+                .render()
+                .unwrap()
+        });
+    });
+
+    group.bench_function("10_collapsed", |b| {
+        let mut output = sink();
+        let mut state = State::new(&mut output, MAX_LINES);
+        with_secondary_outputs(&mut state, 10, 0, false);
+        b.iter(|| state.render().unwrap());
+    });
+
+    group.bench_function("10_expanded_100_lines", |b| {
+        let mut output = sink();
+        let mut state = State::new(&mut output, MAX_LINES);
+        with_secondary_outputs(&mut state, 10, 100, true);
+        b.iter(|| state.render().unwrap());
+    });
+
+    group.bench_function("1000_collapsed", |b| {
+        let mut output = sink();
+        let mut state = State::new(&mut output, MAX_LINES);
+        with_secondary_outputs(&mut state, 1000, 0, false);
+        b.iter(|| state.render().unwrap());
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
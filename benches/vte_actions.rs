@@ -0,0 +1,29 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use multi_output_viewer::VteActionParser;
+
+fn vte_actions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("vte_actions");
+    let bytes = "0123456789\x1b[1;31mabcde\x1b[0m\r\n".repeat(1024);
+
+    group.bench_function("parse_bytes_32kb", |b| {
+        let mut parser = VteActionParser::new();
+        b.iter(|| black_box(parser.parse_bytes(bytes.as_bytes())));
+    });
+
+    group.bench_function("parse_bytes_streaming_32kb", |b| {
+        let mut parser = VteActionParser::new();
+        b.iter(|| {
+            let mut count = 0;
+            parser.parse_bytes_streaming(bytes.as_bytes(), |action| {
+                black_box(action);
+                count += 1;
+            });
+            count
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, vte_actions);
+criterion_main!(benches);
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use multi_output_viewer::VteAction::{
+    CursorBackward, CursorDown, CursorForward, CursorNextLine, CursorPreviousLine, CursorUp,
+};
+use multi_output_viewer::VteActionParser;
+
+fuzz_target!(|data: &[u8]| {
+    let mut parser = VteActionParser::new();
+    for action in parser.parse_bytes(data) {
+        // Movement counts of 0 are defined to mean 1, so should never make it out of the parser
+        assert!(
+            !matches!(
+                action,
+                CursorUp(0)
+                    | CursorDown(0)
+                    | CursorForward(0)
+                    | CursorBackward(0)
+                    | CursorNextLine(0)
+                    | CursorPreviousLine(0)
+            ),
+            "Unexpected zero count in {action:?}"
+        );
+    }
+});
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use multi_output_viewer::State;
+
+fuzz_target!(|data: &[u8]| {
+    let mut output = Vec::new();
+    let mut state = State::new(&mut output, 3);
+    let id = state.new_secondary_output("fuzz".into());
+    state.toggle_current_selection_expanded();
+
+    // Split the input so sequences can straddle renders
+    let (first, second) = data.split_at(data.len() / 2);
+    state
+        .handle_primary_bytes(first)
+        .handle_secondary_bytes(&id, second)
+        .unwrap()
+        .render()
+        .unwrap();
+    state
+        .handle_primary_bytes(second)
+        .handle_secondary_bytes(&id, first)
+        .unwrap()
+        .render()
+        .unwrap();
+});
@@ -0,0 +1,197 @@
+//! Writing and reading recordings in the
+//! [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) format, ie a JSON header line
+//! followed by a JSON array per event.
+
+use crate::state::Instant;
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+use std::io::{BufRead, Result, Write};
+use std::time::Duration;
+
+/// Something which happened during a recorded session, see [crate::State::replay_from_asciicast].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayEvent {
+    /// Seconds since the start of the recording.
+    pub timestamp: f64,
+    pub kind: ReplayEventKind,
+}
+
+/// Secondary output isn't part of what a terminal recording captures, so all of the events are
+/// primary output.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ReplayEventKind {
+    Primary(Vec<u8>),
+}
+
+/// Output along with when it happened, relative to when the log was started.
+pub(crate) struct ByteLog {
+    start: Instant,
+    chunks: Vec<(Duration, Vec<u8>)>,
+}
+
+impl ByteLog {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            start: now,
+            chunks: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8], now: Instant) {
+        self.chunks
+            .push((now.duration_since(self.start), bytes.to_vec()));
+    }
+
+    pub(crate) fn write_events(&self, writer: &mut impl Write) -> Result<()> {
+        write_output_events(
+            writer,
+            self.chunks
+                .iter()
+                .map(|(time, bytes)| (*time, bytes.as_slice())),
+        )
+    }
+}
+
+pub(crate) fn write_header(writer: &mut impl Write, width: u16, height: u16) -> Result<()> {
+    writeln!(
+        writer,
+        "{{\"version\": 2, \"width\": {width}, \"height\": {height}}}"
+    )
+}
+
+/// Writes `chunks` of output as events, keeping any multi-byte characters which are split
+/// between chunks together (events have to be valid UTF-8).
+fn write_output_events<'a>(
+    writer: &mut impl Write,
+    chunks: impl IntoIterator<Item = (Duration, &'a [u8])>,
+) -> Result<()> {
+    let mut pending = Vec::new();
+    for (time, bytes) in chunks {
+        pending.extend_from_slice(bytes);
+        let complete = match std::str::from_utf8(&pending) {
+            Ok(_) => pending.len(),
+            // Hold on to the start of a character which is finished in the next chunk
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(_) => pending.len(),
+        };
+        if complete == 0 {
+            continue;
+        }
+        let data = String::from_utf8_lossy(&pending[..complete]);
+        writeln!(
+            writer,
+            "[{:.6}, \"o\", {}]",
+            time.as_secs_f64(),
+            Value::from(data)
+        )?;
+        pending.drain(..complete);
+    }
+    Ok(())
+}
+
+/// The terminal size from the header, and the output events, of an asciicast v2 recording.
+/// Other kinds of events (eg input) are skipped.
+pub(crate) fn read_recording(
+    reader: impl BufRead,
+) -> anyhow::Result<((u16, u16), Vec<ReplayEvent>)> {
+    let mut lines = reader.lines();
+    let header = lines.next().context("Recording is empty")??;
+    let header: Value = serde_json::from_str(&header).context("Malformed header")?;
+    let version = header.get("version").and_then(Value::as_f64);
+    if version != Some(2.0) {
+        bail!("Only version 2 recordings are supported");
+    }
+    let dimension = |name| {
+        header
+            .get(name)
+            .and_then(Value::as_u64)
+            .map(|value| value as u16)
+            .ok_or_else(|| anyhow!("Header is missing the {name}"))
+    };
+    let size = (dimension("width")?, dimension("height")?);
+
+    let mut events = Vec::new();
+    for (line_number, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|event| match event.as_array()?.as_slice() {
+                [timestamp, Value::String(code), Value::String(data), ..] => {
+                    Some((timestamp.as_f64()?, code.clone(), data.clone()))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("Malformed event on line {}", line_number + 2))?;
+        let (timestamp, code, data) = event;
+        if code == "o" {
+            events.push(ReplayEvent {
+                timestamp,
+                kind: ReplayEventKind::Primary(data.into_bytes()),
+            });
+        }
+    }
+    Ok((size, events))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_recordings() {
+        let recording = "{\"version\": 2, \"width\": 80, \"height\": 24, \"env\": {}}\n\
+                         [0.5, \"o\", \"abc\\r\\n\"]\n\
+                         [0.75, \"i\", \"q\"]\n\
+                         \n\
+                         [1.25, \"o\", \"\\u001b[1mdef\"]\n";
+        let (size, events) = read_recording(recording.as_bytes()).unwrap();
+        assert_eq!(size, (80, 24));
+        assert_eq!(
+            events,
+            vec![
+                ReplayEvent {
+                    timestamp: 0.5,
+                    kind: ReplayEventKind::Primary(b"abc\r\n".to_vec()),
+                },
+                ReplayEvent {
+                    timestamp: 1.25,
+                    kind: ReplayEventKind::Primary(b"\x1b[1mdef".to_vec()),
+                },
+            ]
+        );
+
+        let error = read_recording("{\"version\": 1}".as_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "Only version 2 recordings are supported");
+        let error = read_recording(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.5, \"o\"]".as_bytes(),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Malformed event on line 2");
+    }
+
+    #[test]
+    fn keeps_split_characters_together() {
+        let mut output = Vec::new();
+        let rocket = "🚀".as_bytes();
+        write_output_events(
+            &mut output,
+            [
+                (Duration::from_millis(100), &b"a"[..]),
+                (Duration::from_millis(200), &rocket[..1]),
+                (Duration::from_millis(300), &rocket[1..3]),
+                (Duration::from_millis(400), &[rocket[3], b'b'][..]),
+                (Duration::from_millis(500), &b"\xffc"[..]),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[0.100000, \"o\", \"a\"]\n\
+             [0.400000, \"o\", \"🚀b\"]\n\
+             [0.500000, \"o\", \"\u{fffd}c\"]\n"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use crate::frame;
+use crossterm::cursor::{Hide, MoveDown, MoveTo, MoveToColumn, MoveUp, Show};
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::style::{Attribute, Color, PrintStyledContent, SetAttribute, Stylize};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+use std::io::{Result, Write};
+
+/// Which implementation to use when writing escape sequences to the output.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum RenderBackend {
+    /// Queue crossterm commands on the output.
+    #[default]
+    Crossterm,
+    /// Write plain ANSI escape sequences, for programs which use a different terminal library.
+    Ansi,
+}
+
+impl RenderBackend {
+    pub(crate) fn implementation(self) -> &'static dyn Backend {
+        match self {
+            RenderBackend::Crossterm => &CrosstermBackend,
+            RenderBackend::Ansi => &AnsiBackend,
+        }
+    }
+}
+
+/// Everything rendering needs to do to the output besides writing bytes through. Implementations
+/// are shared statics, so they must be [Sync] for [State](crate::State) to be [Send].
+pub(crate) trait Backend: Sync {
+    fn move_up(&self, output: &mut dyn Write, rows: u16) -> Result<()>;
+    /// Stops at the bottom of the screen rather than scrolling.
+    fn move_down(&self, output: &mut dyn Write, rows: u16) -> Result<()>;
+    /// Columns are 0-based.
+    fn move_to_column(&self, output: &mut dyn Write, column: u16) -> Result<()>;
+    fn clear_down(&self, output: &mut dyn Write) -> Result<()>;
+    /// Clears the whole screen, leaving the cursor where it was.
+    fn clear_all(&self, output: &mut dyn Write) -> Result<()>;
+    fn hide_cursor(&self, output: &mut dyn Write) -> Result<()>;
+    fn show_cursor(&self, output: &mut dyn Write) -> Result<()>;
+    /// Moves to an absolute position on the screen. Columns and rows are 0-based.
+    fn move_to(&self, output: &mut dyn Write, column: u16, row: u16) -> Result<()>;
+    fn print(&self, output: &mut dyn Write, text: &str) -> Result<()> {
+        output.write_all(text.as_bytes())
+    }
+    fn print_styled(&self, output: &mut dyn Write, text: &str, color: Color) -> Result<()>;
+    fn print_dimmed(&self, output: &mut dyn Write, text: &str) -> Result<()>;
+    fn reset_style(&self, output: &mut dyn Write) -> Result<()>;
+    /// Turns on every mouse reporting mode, in SGR's format.
+    fn enable_mouse_capture(&self, output: &mut dyn Write) -> Result<()>;
+    /// Turns off every mouse reporting mode, including ones the primary output turned on.
+    fn disable_mouse_capture(&self, output: &mut dyn Write) -> Result<()>;
+    /// Turns a private mode (`CSI ? mode h`) on or off, eg one of the mouse reporting modes.
+    fn set_private_mode(&self, output: &mut dyn Write, mode: u16, enabled: bool) -> Result<()> {
+        write!(output, "\x1b[?{mode}{}", if enabled { 'h' } else { 'l' })
+    }
+    fn flush(&self, output: &mut dyn Write) -> Result<()> {
+        output.flush()
+    }
+}
+
+struct CrosstermBackend;
+
+impl Backend for CrosstermBackend {
+    fn move_up(&self, output: &mut dyn Write, rows: u16) -> Result<()> {
+        output.queue(MoveUp(rows))?;
+        Ok(())
+    }
+
+    fn move_down(&self, output: &mut dyn Write, rows: u16) -> Result<()> {
+        output.queue(MoveDown(rows))?;
+        Ok(())
+    }
+
+    fn move_to_column(&self, output: &mut dyn Write, column: u16) -> Result<()> {
+        output.queue(MoveToColumn(column))?;
+        Ok(())
+    }
+
+    fn clear_down(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(Clear(ClearType::FromCursorDown))?;
+        Ok(())
+    }
+
+    fn clear_all(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(Clear(ClearType::All))?;
+        Ok(())
+    }
+
+    fn hide_cursor(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(Hide)?;
+        Ok(())
+    }
+
+    fn show_cursor(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(Show)?;
+        Ok(())
+    }
+
+    fn move_to(&self, output: &mut dyn Write, column: u16, row: u16) -> Result<()> {
+        output.queue(MoveTo(column, row))?;
+        Ok(())
+    }
+
+    fn print_styled(&self, output: &mut dyn Write, text: &str, color: Color) -> Result<()> {
+        output.queue(PrintStyledContent(text.with(color)))?;
+        Ok(())
+    }
+
+    fn print_dimmed(&self, output: &mut dyn Write, text: &str) -> Result<()> {
+        output.queue(PrintStyledContent(text.dim()))?;
+        Ok(())
+    }
+
+    fn reset_style(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    fn enable_mouse_capture(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(EnableMouseCapture)?;
+        Ok(())
+    }
+
+    fn disable_mouse_capture(&self, output: &mut dyn Write) -> Result<()> {
+        output.queue(DisableMouseCapture)?;
+        Ok(())
+    }
+}
+
+/// Writes the same sequences crossterm does on non-Windows platforms, without going through it.
+struct AnsiBackend;
+
+impl Backend for AnsiBackend {
+    fn move_up(&self, output: &mut dyn Write, rows: u16) -> Result<()> {
+        write!(output, "\x1b[{rows}A")
+    }
+
+    fn move_down(&self, output: &mut dyn Write, rows: u16) -> Result<()> {
+        write!(output, "\x1b[{rows}B")
+    }
+
+    fn move_to_column(&self, output: &mut dyn Write, column: u16) -> Result<()> {
+        write!(output, "\x1b[{}G", column.saturating_add(1))
+    }
+
+    fn clear_down(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[J")
+    }
+
+    fn clear_all(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[2J")
+    }
+
+    fn hide_cursor(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[?25l")
+    }
+
+    fn show_cursor(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[?25h")
+    }
+
+    fn move_to(&self, output: &mut dyn Write, column: u16, row: u16) -> Result<()> {
+        write!(
+            output,
+            "\x1b[{};{}H",
+            row.saturating_add(1),
+            column.saturating_add(1)
+        )
+    }
+
+    fn print_styled(&self, output: &mut dyn Write, text: &str, color: Color) -> Result<()> {
+        write!(output, "\x1b[{}m{text}\x1b[39m", ansi_foreground(color))
+    }
+
+    fn print_dimmed(&self, output: &mut dyn Write, text: &str) -> Result<()> {
+        // Crossterm resets everything after text with attributes
+        write!(output, "\x1b[2m{text}\x1b[0m")
+    }
+
+    fn reset_style(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[0m")
+    }
+
+    fn enable_mouse_capture(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[?1000h\x1b[?1002h\x1b[?1003h\x1b[?1015h\x1b[?1006h")
+    }
+
+    fn disable_mouse_capture(&self, output: &mut dyn Write) -> Result<()> {
+        output.write_all(b"\x1b[?1006l\x1b[?1015l\x1b[?1003l\x1b[?1002l\x1b[?1000l")
+    }
+}
+
+/// The SGR parameters for a foreground color, using the same encoding as crossterm.
+fn ansi_foreground(color: Color) -> String {
+    match frame::Color::from_crossterm(color) {
+        None => "39".to_string(),
+        Some(frame::Color::Indexed(idx)) => format!("38;5;{idx}"),
+        Some(frame::Color::Rgb(r, g, b)) => format!("38;2;{r};{g};{b}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ansi_colors_match_crossterm() {
+        let colors = [
+            Color::Reset,
+            Color::Black,
+            Color::DarkGrey,
+            Color::Red,
+            Color::DarkRed,
+            Color::Green,
+            Color::DarkGreen,
+            Color::Yellow,
+            Color::DarkYellow,
+            Color::Blue,
+            Color::DarkBlue,
+            Color::Magenta,
+            Color::DarkMagenta,
+            Color::Cyan,
+            Color::DarkCyan,
+            Color::White,
+            Color::Grey,
+            Color::AnsiValue(123),
+            Color::Rgb { r: 1, g: 2, b: 3 },
+        ];
+        for color in colors {
+            let mut crossterm_output = Vec::new();
+            CrosstermBackend
+                .print_styled(&mut crossterm_output, "text", color)
+                .unwrap();
+            let mut ansi_output = Vec::new();
+            AnsiBackend
+                .print_styled(&mut ansi_output, "text", color)
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(ansi_output).unwrap(),
+                String::from_utf8(crossterm_output).unwrap(),
+                "{color:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ansi_mouse_capture_matches_crossterm() {
+        let write = |backend: &dyn Backend| {
+            let mut output = Vec::new();
+            backend.enable_mouse_capture(&mut output).unwrap();
+            backend.disable_mouse_capture(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(write(&AnsiBackend), write(&CrosstermBackend));
+    }
+}
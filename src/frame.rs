@@ -0,0 +1,122 @@
+//! An in-memory version of the secondary outputs, for drawing them with something other than
+//! [State::render](crate::State::render), eg as part of a TUI.
+
+use crate::state::SecondaryOutputId;
+use std::ops::Range;
+
+/// A color, using the same numbering as terminals' 256 color palette for the indexed colors.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Color {
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Converts using the same numbering crossterm does, returning `None` for the default color.
+    pub(crate) fn from_crossterm(color: crossterm::style::Color) -> Option<Self> {
+        use crossterm::style::Color as C;
+        let idx = match color {
+            C::Reset => return None,
+            C::Black => 0,
+            C::DarkRed => 1,
+            C::DarkGreen => 2,
+            C::DarkYellow => 3,
+            C::DarkBlue => 4,
+            C::DarkMagenta => 5,
+            C::DarkCyan => 6,
+            C::Grey => 7,
+            C::DarkGrey => 8,
+            C::Red => 9,
+            C::Green => 10,
+            C::Yellow => 11,
+            C::Blue => 12,
+            C::Magenta => 13,
+            C::Cyan => 14,
+            C::White => 15,
+            C::AnsiValue(idx) => idx,
+            C::Rgb { r, g, b } => return Some(Color::Rgb(r, g, b)),
+        };
+        Some(Color::Indexed(idx))
+    }
+}
+
+/// How a span of text is styled. `None` colors mean the terminal's default.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Style {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+impl Style {
+    pub(crate) fn foreground(color: Color) -> Self {
+        Self {
+            foreground: Some(color),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// A single row of the frame, made up of differently styled spans.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Line {
+    pub spans: Vec<Span>,
+}
+
+impl Line {
+    /// Adds `text` to the end of the line, merging it into the last span if the style matches.
+    pub(crate) fn push(&mut self, text: &str, style: Style) {
+        if text.is_empty() {
+            return;
+        }
+        match self.spans.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(text),
+            _ => self.spans.push(Span {
+                text: text.to_string(),
+                style,
+            }),
+        }
+    }
+
+    /// The line's text without any styling.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|span| span.text.as_str()).collect()
+    }
+}
+
+/// Where the last [State::render](crate::State::render) drew each output, see
+/// [State::last_frame_layout](crate::State::last_frame_layout). Rows count down from just below
+/// the primary output, so row 0 is the first row drawn.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct FrameLayout {
+    /// The outputs drawn, from top to bottom.
+    pub outputs: Vec<OutputLayout>,
+    /// How many rows were laid out in all, including any combined tail or summary line below the
+    /// outputs.
+    pub extra_lines: usize,
+}
+
+/// Where one output was drawn, see [FrameLayout].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OutputLayout {
+    pub id: SecondaryOutputId,
+    pub header_row: usize,
+    /// The rows of expanded content below the header, which is empty if the output wasn't
+    /// expanded.
+    pub content_rows: Range<usize>,
+}
+
+/// The secondary outputs' headers and any expanded content, one entry per row.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub lines: Vec<Line>,
+}
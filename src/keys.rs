@@ -0,0 +1,432 @@
+//! Which keys do what, so they can be remapped rather than hardcoded. Terminals don't agree on
+//! how some chords are sent (particularly with Alt), so users need to be able to pick their own.
+
+use anyhow::{anyhow, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Something the user can do to the secondary outputs from the keyboard.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum UiAction {
+    CursorUp,
+    CursorDown,
+    ToggleExpand,
+    ExpandAll,
+    CollapseAll,
+    /// Scrolls the selected output's expanded view up by a line.
+    ScrollUp,
+    /// Scrolls the selected output's expanded view down by a line.
+    ScrollDown,
+    SelectNextFailed,
+    Quit,
+}
+
+impl UiAction {
+    /// Every action, in the order they're listed in help text.
+    pub const ALL: &'static [UiAction] = &[
+        UiAction::CursorUp,
+        UiAction::CursorDown,
+        UiAction::ToggleExpand,
+        UiAction::ExpandAll,
+        UiAction::CollapseAll,
+        UiAction::ScrollUp,
+        UiAction::ScrollDown,
+        UiAction::SelectNextFailed,
+        UiAction::Quit,
+    ];
+
+    /// What the action is called when binding keys to it, eg `cursor_down`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            UiAction::CursorUp => "cursor_up",
+            UiAction::CursorDown => "cursor_down",
+            UiAction::ToggleExpand => "toggle_expand",
+            UiAction::ExpandAll => "expand_all",
+            UiAction::CollapseAll => "collapse_all",
+            UiAction::ScrollUp => "scroll_up",
+            UiAction::ScrollDown => "scroll_down",
+            UiAction::SelectNextFailed => "select_next_failed",
+            UiAction::Quit => "quit",
+        }
+    }
+}
+
+impl fmt::Display for UiAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for UiAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        UiAction::ALL
+            .iter()
+            .find(|action| action.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown action {s:?}"))
+    }
+}
+
+/// A key along with the modifiers held while pressing it, eg `alt+j`.
+///
+/// Shift is folded into letters rather than kept as a modifier, since terminals report it
+/// inconsistently, so `shift+j` is the same chord as `J`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct KeyChord {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => Self {
+                code: KeyCode::Char(c.to_ascii_uppercase()),
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            _ => Self { code, modifiers },
+        }
+    }
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+}
+
+/// The names of keys which aren't just a single character.
+const KEY_NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::Up, "up"),
+    (KeyCode::Down, "down"),
+    (KeyCode::Left, "left"),
+    (KeyCode::Right, "right"),
+    (KeyCode::PageUp, "pageup"),
+    (KeyCode::PageDown, "pagedown"),
+    (KeyCode::Home, "home"),
+    (KeyCode::End, "end"),
+    (KeyCode::Enter, "enter"),
+    (KeyCode::Esc, "esc"),
+    (KeyCode::Tab, "tab"),
+    (KeyCode::BackTab, "backtab"),
+    (KeyCode::Backspace, "backspace"),
+    (KeyCode::Delete, "delete"),
+    (KeyCode::Insert, "insert"),
+    (KeyCode::Char(' '), "space"),
+];
+
+const MODIFIER_NAMES: &[(KeyModifiers, &str)] = &[
+    (KeyModifiers::CONTROL, "ctrl"),
+    (KeyModifiers::ALT, "alt"),
+    (KeyModifiers::SHIFT, "shift"),
+    (KeyModifiers::SUPER, "super"),
+];
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (modifier, name) in MODIFIER_NAMES {
+            if self.modifiers.contains(*modifier) {
+                write!(f, "{name}+")?;
+            }
+        }
+        match KEY_NAMES.iter().find(|(code, _)| *code == self.code) {
+            Some((_, name)) => f.write_str(name),
+            None => match self.code {
+                KeyCode::Char(c) => write!(f, "{c}"),
+                KeyCode::F(n) => write!(f, "f{n}"),
+                code => write!(f, "{code:?}"),
+            },
+        }
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = anyhow::Error;
+
+    /// Parses chords like `alt+j`, `ctrl+shift+up`, or `f5`. Names are case insensitive, except
+    /// for single characters.
+    fn from_str(s: &str) -> Result<Self> {
+        // The key itself can be a plus, eg `alt++`
+        let (modifier_names, key) = match s.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => (rest, "+"),
+            _ => match s.rsplit_once('+') {
+                Some((modifier_names, key)) => (modifier_names, key),
+                None => ("", s),
+            },
+        };
+        let mut modifiers = KeyModifiers::NONE;
+        for name in modifier_names.split('+').filter(|name| !name.is_empty()) {
+            let (modifier, _) = MODIFIER_NAMES
+                .iter()
+                .find(|(_, modifier_name)| modifier_name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| anyhow!("Unknown modifier {name:?} in key {s:?}"))?;
+            modifiers |= *modifier;
+        }
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match KEY_NAMES
+                .iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(key))
+            {
+                Some((code, _)) => *code,
+                None => key
+                    .strip_prefix(['f', 'F'])
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (1..=24).contains(n))
+                    .map(KeyCode::F)
+                    .ok_or_else(|| anyhow!("Unknown key {key:?} in {s:?}"))?,
+            },
+        };
+        Ok(KeyChord::new(code, modifiers))
+    }
+}
+
+/// The bindings [KeyBindings::default] starts with. These all hold Alt so they don't get in the way
+/// of typing into the primary output.
+const DEFAULT_BINDINGS: &[(&str, UiAction)] = &[
+    ("alt+up", UiAction::CursorUp),
+    ("alt+k", UiAction::CursorUp),
+    ("alt+down", UiAction::CursorDown),
+    ("alt+j", UiAction::CursorDown),
+    ("alt+enter", UiAction::ToggleExpand),
+    ("alt+space", UiAction::ToggleExpand),
+    ("alt+e", UiAction::ExpandAll),
+    ("alt+c", UiAction::CollapseAll),
+    ("alt+pageup", UiAction::ScrollUp),
+    ("alt+pagedown", UiAction::ScrollDown),
+    ("alt+f", UiAction::SelectNextFailed),
+    ("alt+q", UiAction::Quit),
+];
+
+/// Which [UiAction] each [KeyChord] does. Each chord does at most one action, but an action can
+/// have any number of chords.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyBindings {
+    bindings: HashMap<KeyChord, UiAction>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        DEFAULT_BINDINGS
+            .iter()
+            .fold(Self::empty(), |bindings, (chord, action)| {
+                bindings
+                    .bind(chord.parse().expect("Default chords should parse"), *action)
+                    .expect("Default bindings shouldn't conflict")
+            })
+    }
+}
+
+impl KeyBindings {
+    /// Bindings without any chords bound.
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Binds `chord` to `action`. Fails if the chord is already bound to a different action, which
+    /// has to be unbound first (see [KeyBindings::unbind]).
+    pub fn bind(mut self, chord: KeyChord, action: UiAction) -> Result<Self> {
+        match self.bindings.get(&chord) {
+            Some(existing) if *existing != action => Err(anyhow!(
+                "Can't bind {chord} to {action}, it's already bound to {existing}"
+            )),
+            _ => {
+                self.bindings.insert(chord, action);
+                Ok(self)
+            }
+        }
+    }
+
+    /// Removes whatever `chord` is bound to, if anything.
+    pub fn unbind(mut self, chord: &KeyChord) -> Self {
+        self.bindings.remove(chord);
+        self
+    }
+
+    /// Splits a binding like `alt+j=cursor_down` into its chord and action, eg from a `--bind`
+    /// command line argument.
+    pub fn parse_binding(spec: &str) -> Result<(KeyChord, UiAction)> {
+        // Split on the last `=`, so `=` can be bound too
+        let (chord, action) = spec
+            .rsplit_once('=')
+            .filter(|(chord, _)| !chord.is_empty())
+            .ok_or_else(|| anyhow!("Expected a binding like alt+j=cursor_down, got {spec:?}"))?;
+        Ok((chord.parse()?, action.parse()?))
+    }
+
+    /// Applies user bindings like `alt+j=cursor_down` (see [KeyBindings::parse_binding]) on top of
+    /// these ones. Each replaces whatever its chord was bound to before, but binding the same chord
+    /// to two different actions is an error.
+    pub fn with_overrides<'s>(self, specs: impl IntoIterator<Item = &'s str>) -> Result<Self> {
+        let mut overrides = Self::empty();
+        for spec in specs {
+            let (chord, action) = Self::parse_binding(spec)?;
+            overrides = overrides.bind(chord, action)?;
+        }
+        let mut bindings = self;
+        for (chord, action) in overrides.bindings {
+            bindings = bindings.unbind(&chord).bind(chord, action)?;
+        }
+        Ok(bindings)
+    }
+
+    /// What a key press does, if anything. Key releases never do anything.
+    pub fn action_for(&self, event: &KeyEvent) -> Option<UiAction> {
+        if event.kind == KeyEventKind::Release {
+            return None;
+        }
+        self.bindings.get(&KeyChord::from(*event)).copied()
+    }
+
+    /// The chords bound to `action`, sorted by how they're written.
+    pub fn chords_for(&self, action: UiAction) -> Vec<KeyChord> {
+        let mut chords = self
+            .bindings
+            .iter()
+            .filter(|(_, bound)| **bound == action)
+            .map(|(chord, _)| *chord)
+            .collect::<Vec<_>>();
+        chords.sort_by_key(|chord| chord.to_string());
+        chords
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chord(s: &str) -> KeyChord {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_chords() {
+        assert_eq!(
+            chord("alt+j"),
+            KeyChord::new(KeyCode::Char('j'), KeyModifiers::ALT)
+        );
+        assert_eq!(
+            chord("Ctrl+Shift+Up"),
+            KeyChord::new(KeyCode::Up, KeyModifiers::CONTROL | KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            chord("f5"),
+            KeyChord::new(KeyCode::F(5), KeyModifiers::NONE)
+        );
+        assert_eq!(chord("alt++").code, KeyCode::Char('+'));
+        assert_eq!(chord("+").code, KeyCode::Char('+'));
+        // Shift is folded into letters
+        assert_eq!(chord("shift+j"), chord("J"));
+        assert_ne!(chord("j"), chord("J"));
+
+        for bad in ["", "alt+", "hyper+j", "alt+nope", "f0"] {
+            assert!(bad.parse::<KeyChord>().is_err(), "{bad:?} parsed");
+        }
+    }
+
+    #[test]
+    fn displays_chords_as_parsed() {
+        for s in [
+            "alt+j",
+            "ctrl+alt+pagedown",
+            "alt+space",
+            "f12",
+            "alt++",
+            "J",
+        ] {
+            assert_eq!(chord(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parses_bindings() {
+        assert_eq!(
+            KeyBindings::parse_binding("alt+j=cursor_down").unwrap(),
+            (chord("alt+j"), UiAction::CursorDown)
+        );
+        assert_eq!(
+            KeyBindings::parse_binding("alt+==expand_all").unwrap(),
+            (chord("alt+="), UiAction::ExpandAll)
+        );
+        assert_eq!(
+            KeyBindings::parse_binding("alt+j").unwrap_err().to_string(),
+            "Expected a binding like alt+j=cursor_down, got \"alt+j\""
+        );
+        assert_eq!(
+            KeyBindings::parse_binding("alt+j=jump")
+                .unwrap_err()
+                .to_string(),
+            "Unknown action \"jump\""
+        );
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let bindings = KeyBindings::empty()
+            .bind(chord("alt+j"), UiAction::CursorDown)
+            .unwrap();
+        // Binding the same thing again is fine
+        let bindings = bindings.bind(chord("alt+j"), UiAction::CursorDown).unwrap();
+        assert_eq!(
+            bindings
+                .clone()
+                .bind(chord("alt+j"), UiAction::Quit)
+                .unwrap_err()
+                .to_string(),
+            "Can't bind alt+j to quit, it's already bound to cursor_down"
+        );
+        let bindings = bindings
+            .unbind(&chord("alt+j"))
+            .bind(chord("alt+j"), UiAction::Quit)
+            .unwrap();
+        assert_eq!(bindings.chords_for(UiAction::Quit), vec![chord("alt+j")]);
+    }
+
+    #[test]
+    fn overrides_defaults() {
+        let bindings = KeyBindings::default()
+            .with_overrides(["alt+j=quit", "alt+n=cursor_down"])
+            .unwrap();
+        assert_eq!(
+            bindings.chords_for(UiAction::CursorDown),
+            vec![chord("alt+down"), chord("alt+n")]
+        );
+        assert_eq!(
+            bindings.chords_for(UiAction::Quit),
+            vec![chord("alt+j"), chord("alt+q")]
+        );
+        assert!(KeyBindings::default()
+            .with_overrides(["alt+n=quit", "alt+n=cursor_down"])
+            .is_err());
+    }
+
+    #[test]
+    fn defaults_cover_every_action() {
+        let bindings = KeyBindings::default();
+        for action in UiAction::ALL {
+            assert!(
+                !bindings.chords_for(*action).is_empty(),
+                "{action} isn't bound"
+            );
+            assert_eq!(action.name().parse::<UiAction>().unwrap(), *action);
+        }
+    }
+
+    #[test]
+    fn looks_up_key_events() {
+        let bindings = KeyBindings::default();
+        let mut event = KeyEvent::new(KeyCode::Char('j'), KeyModifiers::ALT);
+        assert_eq!(bindings.action_for(&event), Some(UiAction::CursorDown));
+        event.kind = KeyEventKind::Release;
+        assert_eq!(bindings.action_for(&event), None);
+        let event = KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE);
+        assert_eq!(bindings.action_for(&event), None);
+    }
+}
@@ -28,7 +28,33 @@ Then IO
 
 
 */
+#[cfg(feature = "asciicast")]
+mod asciicast;
+mod backend;
+pub mod frame;
+mod keys;
+mod mouse;
+mod search;
+mod secondary_output;
+mod sgr;
 mod state;
+mod symbols;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod text;
 mod vte_actions;
 
-pub use state::State;
+#[cfg(feature = "asciicast")]
+pub use asciicast::{ReplayEvent, ReplayEventKind};
+pub use backend::RenderBackend;
+pub use keys::{KeyBindings, KeyChord, UiAction};
+pub use mouse::MouseOutcome;
+pub use search::MatchLocation;
+pub use secondary_output::{CarriageReturnMode, SecondaryMode, SecondaryOutputStatus};
+pub use state::{
+    DebugState, DurationStyle, ElapsedPrecision, EofIndicatorPosition, PrimaryOutputId,
+    PrimaryQuery, SecondaryOutputDebugState, SecondaryOutputId, SecondaryOutputInfo, State,
+    StateBuilder, StateEvent, StateInvariantViolation, TakenOutput,
+};
+pub use symbols::UNICODE_SUPPORTED;
+pub use vte_actions::{IgnoredKind, VteAction, VteActionParser};
@@ -0,0 +1,147 @@
+//! Turning mouse events from the terminal into actions on the secondary outputs, or passing them
+//! on to the primary output if it asked for them.
+
+use crossterm::event::{KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+
+/// How many lines a notch of the mouse wheel scrolls an expanded output by.
+pub(crate) const MOUSE_SCROLL_LINES: usize = 3;
+
+/// What came of a mouse event, see [State::handle_mouse_event](crate::State::handle_mouse_event).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MouseOutcome {
+    /// The event changed the secondary outputs, so they should be rendered again.
+    Handled,
+    /// The event was outside the secondary outputs and the primary output asked for mouse events,
+    /// so these bytes should be sent to its input.
+    Forward(Vec<u8>),
+    /// Nothing needs doing.
+    Ignored,
+}
+
+/// Which mouse reporting modes the primary output has turned on, from `CSI ?1000h` and friends.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MouseModes {
+    /// 1000 (presses and releases), 1002 (drags too) or 1003 (all motion), if any.
+    pub(crate) reporting: Option<u16>,
+    /// Mode 1006, reporting in SGR's format rather than the original one-byte-per-value one.
+    pub(crate) sgr: bool,
+}
+
+impl MouseModes {
+    /// Applies a [VteAction::SetMouseMode](crate::VteAction::SetMouseMode).
+    pub(crate) fn set(&mut self, mode: u16, enabled: bool) {
+        match (mode, enabled) {
+            (1006, _) => self.sgr = enabled,
+            (_, true) => self.reporting = Some(mode),
+            (_, false) => {
+                if self.reporting == Some(mode) {
+                    self.reporting = None;
+                }
+            }
+        }
+    }
+
+    /// How the event would be reported to the primary output, if it asked for events like it.
+    pub(crate) fn encode(&self, event: &MouseEvent) -> Option<Vec<u8>> {
+        let reporting = self.reporting?;
+        let (mut button, released) = match event.kind {
+            MouseEventKind::Down(button) => (button_code(button), false),
+            MouseEventKind::Up(button) => (button_code(button), true),
+            MouseEventKind::Drag(button) if reporting >= 1002 => (button_code(button) + 32, false),
+            MouseEventKind::Moved if reporting >= 1003 => (35, false),
+            MouseEventKind::ScrollUp => (64, false),
+            MouseEventKind::ScrollDown => (65, false),
+            MouseEventKind::Drag(_) | MouseEventKind::Moved => return None,
+        };
+        for (modifier, code) in [
+            (KeyModifiers::SHIFT, 4),
+            (KeyModifiers::ALT, 8),
+            (KeyModifiers::CONTROL, 16),
+        ] {
+            if event.modifiers.contains(modifier) {
+                button += code;
+            }
+        }
+        if self.sgr {
+            let end = if released { 'm' } else { 'M' };
+            return Some(
+                format!("\x1b[<{button};{};{}{end}", event.column + 1, event.row + 1).into_bytes(),
+            );
+        }
+        // The original format can't say which button was released, and each value is a single
+        // byte offset by 32, so positions past 222 can't be reported
+        if released {
+            button = 3 + (button & !3);
+        }
+        let value = |n: u16| u8::try_from(n + 33).ok();
+        Some(vec![
+            0x1b,
+            b'[',
+            b'M',
+            u8::try_from(button + 32).ok()?,
+            value(event.column)?,
+            value(event.row)?,
+        ])
+    }
+}
+
+fn button_code(button: MouseButton) -> u16 {
+    match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(kind: MouseEventKind, column: u16, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn encodes_for_requested_modes() {
+        let mut modes = MouseModes::default();
+        let click = event(MouseEventKind::Down(MouseButton::Left), 4, 9);
+        assert_eq!(modes.encode(&click), None);
+
+        modes.set(1000, true);
+        assert_eq!(modes.encode(&click), Some(b"\x1b[M %*".to_vec()));
+        // Drags need mode 1002
+        let drag = event(MouseEventKind::Drag(MouseButton::Left), 5, 9);
+        assert_eq!(modes.encode(&drag), None);
+        modes.set(1002, true);
+        assert_eq!(modes.encode(&drag), Some(b"\x1b[M@&*".to_vec()));
+
+        modes.set(1006, true);
+        let release = MouseEvent {
+            modifiers: KeyModifiers::CONTROL,
+            ..event(MouseEventKind::Up(MouseButton::Right), 300, 9)
+        };
+        assert_eq!(modes.encode(&release), Some(b"\x1b[<18;301;10m".to_vec()));
+
+        // Turning off a mode that isn't on doesn't turn off the others
+        modes.set(1000, false);
+        assert!(modes.encode(&click).is_some());
+        modes.set(1002, false);
+        assert_eq!(modes.encode(&click), None);
+    }
+
+    #[test]
+    fn leaves_out_positions_the_original_format_cant_report() {
+        let mut modes = MouseModes::default();
+        modes.set(1000, true);
+        assert_eq!(
+            modes.encode(&event(MouseEventKind::ScrollUp, 222, 0)),
+            Some(b"\x1b[M`\xff!".to_vec())
+        );
+        assert_eq!(modes.encode(&event(MouseEventKind::ScrollUp, 223, 0)), None);
+    }
+}
@@ -0,0 +1,191 @@
+//! Finding text in an output's content rows, which can contain escape sequences for styling.
+
+use crate::frame::{Line, Style};
+use std::ops::Range;
+
+const INVERSE_ON: &[u8] = b"\x1b[7m";
+const INVERSE_OFF: &[u8] = b"\x1b[27m";
+
+/// Where some text was found in an output's content, see
+/// [State::search_secondary_output](crate::State::search_secondary_output).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MatchLocation {
+    /// Which row of content it's in, counting from the oldest row still kept.
+    pub row: usize,
+    /// Where in the row it starts, in characters.
+    pub start: usize,
+    /// Where in the row it ends (exclusive), in characters.
+    pub end: usize,
+}
+
+/// Calls `f` with each piece of `row`, and whether that piece is an escape sequence rather than
+/// text.
+fn for_each_piece(row: &[u8], mut f: impl FnMut(&[u8], bool)) {
+    let mut i = 0;
+    while i < row.len() {
+        if row[i] != b'\x1b' {
+            let end = row[i..]
+                .iter()
+                .position(|b| *b == b'\x1b')
+                .map_or(row.len(), |len| i + len);
+            f(&row[i..end], false);
+            i = end;
+            continue;
+        }
+        let end = match row.get(i + 1) {
+            // A CSI sequence runs until its final byte
+            Some(b'[') => row[i + 2..]
+                .iter()
+                .position(|b| (0x40..=0x7e).contains(b))
+                .map_or(row.len(), |len| i + 2 + len + 1),
+            Some(_) => i + 2,
+            None => row.len(),
+        };
+        f(&row[i..end], true);
+        i = end;
+    }
+}
+
+/// The text of a formatted row, with any escape sequences removed.
+pub(crate) fn plain_text(row: &[u8]) -> String {
+    let mut text = Vec::with_capacity(row.len());
+    for_each_piece(row, |piece, escape| {
+        if !escape {
+            text.extend_from_slice(piece);
+        }
+    });
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+/// The non-overlapping occurrences of `needle` in `text`, as ranges of characters.
+pub(crate) fn find_matches(text: &str, needle: &str, case_insensitive: bool) -> Vec<Range<usize>> {
+    let chars_match =
+        |a: char, b: char| a == b || (case_insensitive && a.to_lowercase().eq(b.to_lowercase()));
+    let text = text.chars().collect::<Vec<_>>();
+    let needle = needle.chars().collect::<Vec<_>>();
+    let mut matches = Vec::new();
+    if needle.is_empty() {
+        return matches;
+    }
+    let mut start = 0;
+    while start + needle.len() <= text.len() {
+        let end = start + needle.len();
+        if text[start..end]
+            .iter()
+            .zip(&needle)
+            .all(|(a, b)| chars_match(*a, *b))
+        {
+            matches.push(start..end);
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+/// Shows the characters in `matches` in inverse video. Any escape sequences in the middle of a
+/// match could turn it back off, so it's turned back on after each of them.
+pub(crate) fn highlight_row(row: &[u8], matches: &[Range<usize>]) -> Vec<u8> {
+    let mut highlighted = Vec::with_capacity(row.len());
+    let mut inverse = false;
+    let mut char_idx = 0;
+    for_each_piece(row, |piece, escape| {
+        if escape {
+            highlighted.extend_from_slice(piece);
+            if inverse {
+                highlighted.extend_from_slice(INVERSE_ON);
+            }
+            return;
+        }
+        for byte in piece {
+            // Only check at the start of each character, not in the middle of one
+            let is_continuation = (0x80..0xc0).contains(byte);
+            if !is_continuation {
+                let in_match = matches.iter().any(|range| range.contains(&char_idx));
+                if in_match != inverse {
+                    inverse = in_match;
+                    highlighted.extend_from_slice(if inverse { INVERSE_ON } else { INVERSE_OFF });
+                }
+                char_idx += 1;
+            }
+            highlighted.push(*byte);
+        }
+    });
+    if inverse {
+        highlighted.extend_from_slice(INVERSE_OFF);
+    }
+    highlighted
+}
+
+/// Same as [highlight_row], but for a styled [Line].
+pub(crate) fn highlight_line(line: &Line, matches: &[Range<usize>]) -> Line {
+    let mut highlighted = Line::default();
+    let mut char_idx = 0;
+    for span in &line.spans {
+        for c in span.text.chars() {
+            let style = Style {
+                inverse: span.style.inverse
+                    || matches.iter().any(|range| range.contains(&char_idx)),
+                ..span.style
+            };
+            highlighted.push(c.encode_utf8(&mut [0; 4]), style);
+            char_idx += 1;
+        }
+    }
+    highlighted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strips_escape_sequences() {
+        assert_eq!(plain_text(b"plain"), "plain");
+        assert_eq!(
+            plain_text(b"\x1b[31mred\x1b[0m and \x1b[1;4mbold\x1b[m"),
+            "red and bold"
+        );
+        assert_eq!(plain_text(b"unfinished\x1b[3"), "unfinished");
+    }
+
+    #[test]
+    fn finds_matches() {
+        assert_eq!(
+            find_matches("test FAILED, other FAILED", "FAILED", false),
+            vec![5..11, 19..25]
+        );
+        assert_eq!(find_matches("Failed failed", "FAILED", false), vec![]);
+        assert_eq!(
+            find_matches("Failed failed", "FAILED", true),
+            vec![0..6, 7..13]
+        );
+        // Matches don't overlap
+        assert_eq!(find_matches("aaaa", "aa", false), vec![0..2, 2..4]);
+        // Offsets are in characters rather than bytes
+        assert_eq!(find_matches("テスト test", "test", false), vec![4..8]);
+        assert_eq!(find_matches("anything", "", false), vec![]);
+    }
+
+    #[test]
+    fn highlights_around_escape_sequences() {
+        assert_eq!(
+            highlight_row(
+                b"a match here",
+                &find_matches("a match here", "match", false)
+            ),
+            b"a \x1b[7mmatch\x1b[27m here"
+        );
+        // A reset in the middle of the match doesn't end the highlight, and adjacent matches run
+        // together
+        assert_eq!(
+            highlight_row(b"\x1b[31mma\x1b[0mtch", &[0..2, 2..5]),
+            b"\x1b[31m\x1b[7mma\x1b[0m\x1b[7mtch\x1b[27m"
+        );
+        assert_eq!(
+            highlight_row("テスト".as_bytes(), &find_matches("テスト", "ス", false)),
+            "テ\x1b[7mス\x1b[27mト".as_bytes()
+        );
+    }
+}
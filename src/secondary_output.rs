@@ -0,0 +1,923 @@
+use crate::frame::{Color, Line, Style};
+use crate::state::{Instant, SecondaryOutputId};
+use crate::text::truncate_to_width;
+use crate::vte_actions::{resync_point, VteAction, VteActionParser};
+#[cfg(test)]
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How bytes sent to a secondary output are interpreted.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SecondaryMode {
+    /// Interpret the bytes as a terminal would, including cursor movement and styling.
+    Terminal,
+    /// Drop any control sequences and just keep the most recent lines of text.
+    PlainLines(CarriageReturnMode),
+}
+
+/// How the process behind a secondary output is doing.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum SecondaryOutputStatus {
+    #[default]
+    Running,
+    /// Finished, with its exit code if known. Any exit code other than 0 is a failure.
+    Finished { exit_code: Option<i32> },
+}
+
+impl SecondaryOutputStatus {
+    /// Whether the output finished with a known exit code other than 0. Outputs which are still
+    /// running, or which finished without an exit code, haven't failed.
+    pub fn failed(&self) -> bool {
+        matches!(self, Self::Finished { exit_code: Some(code) } if *code != 0)
+    }
+}
+
+/// How a bare carriage return is handled in [SecondaryMode::PlainLines].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CarriageReturnMode {
+    /// Text after the carriage return replaces the current line, eg for progress updates.
+    Overwrite,
+    /// The carriage return ends the current line, same as a newline.
+    LineSeparator,
+}
+
+pub(crate) struct SecondaryOutputState {
+    pub(crate) id: SecondaryOutputId,
+    pub(crate) title: String,
+    pub(crate) timer: ElapsedTimer,
+    pub(crate) status: SecondaryOutputStatus,
+    /// Whether the output is pinned to the top of the list.
+    pub(crate) pinned: bool,
+    /// Text to highlight wherever it appears in the expanded content.
+    pub(crate) highlight: Option<String>,
+    /// Only used while the combined tail is shown, see [crate::State::set_combined_tail].
+    pub(crate) line_splitter: Option<LineSplitter>,
+    expanded: bool,
+    /// How long the output is expected to take, if a time bar should be shown in the header.
+    pub(crate) time_bar_max_duration: Option<Duration>,
+    /// Total bytes sent to the output, counted as they arrive rather than when they're parsed.
+    pub(crate) bytes_received: usize,
+    /// How many of the bytes received were dropped for going over the byte budget.
+    pub(crate) dropped_bytes: usize,
+    /// Whether the terminal's line above the cursor is a truncation marker, with no line ended
+    /// since it was written.
+    truncation_marker_above: bool,
+    /// How many lines up from the bottom of the content the expanded view is scrolled.
+    pub(crate) scroll_offset: usize,
+    /// When the output last rang the terminal bell, if ever.
+    pub(crate) last_bell: Option<Instant>,
+    /// When the output was created, which line timestamps are relative to.
+    pub(crate) created: Instant,
+    /// When the output last received any bytes, or when it was created if it hasn't yet.
+    pub(crate) last_output: Instant,
+    /// Whether [crate::StateEvent::OutputStalled] has been sent since the output last received
+    /// any bytes.
+    pub(crate) stall_notified: bool,
+    /// Whether to show a scrollbar to the right of the content when expanded.
+    pub(crate) show_scrollbar: bool,
+    /// Saved scroll positions, as labels and the content row at the top of the view.
+    pub(crate) bookmarks: Vec<(String, usize)>,
+    /// Labelled spans of content rows, in the order they were added, which is what their indexes
+    /// refer to. Like bookmarks, they're dropped when the buffer is cleared unless persisted.
+    pub(crate) named_ranges: Vec<NamedRange>,
+    /// Whether to scroll to each named range as it's added.
+    pub(crate) auto_follow_ranges: bool,
+    /// Whether to mark rows in named ranges with a border to the left of the content.
+    pub(crate) show_named_ranges: bool,
+    /// Only used to spot bells, since the buffer doesn't parse bytes as they arrive.
+    bell_parser: vte::Parser,
+    buffer: SecondaryBuffer,
+    /// How many columns wide the buffer's screen is.
+    screen_cols: u16,
+    /// Bumped whenever the content changes, to know when the cached rows are stale.
+    generation: u64,
+    row_cache: RefCell<Option<RowCache>>,
+    /// How many times the rows have actually been formatted, rather than coming from the cache.
+    #[cfg(test)]
+    pub(crate) format_count: Cell<usize>,
+}
+
+/// How long an output has been running, not counting any time it was paused.
+pub(crate) struct ElapsedTimer {
+    /// When the timer was started, aligned to whole seconds since the reference start time.
+    pub(crate) started: Instant,
+    /// The time from earlier running spans.
+    accumulated: Duration,
+    /// The same, but counting whole seconds of each span from its aligned start.
+    accumulated_aligned: Duration,
+    /// When the current running span started, both aligned to whole seconds since the reference
+    /// start time (so different outputs tick to the next second together) and as it actually
+    /// happened (for when we're showing sub-second precision). `None` while paused.
+    running_since: Option<(Instant, Instant)>,
+}
+
+impl ElapsedTimer {
+    pub(crate) fn start(aligned: Instant, now: Instant) -> Self {
+        Self {
+            started: aligned,
+            accumulated: Duration::ZERO,
+            accumulated_aligned: Duration::ZERO,
+            running_since: Some((aligned, now)),
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.running_since.is_none()
+    }
+
+    pub(crate) fn pause(&mut self, now: Instant) {
+        if let Some((aligned, unaligned)) = self.running_since.take() {
+            self.accumulated += now - unaligned;
+            self.accumulated_aligned += Duration::from_secs((now - aligned).as_secs());
+        }
+    }
+
+    pub(crate) fn resume(&mut self, aligned: Instant, now: Instant) {
+        if self.running_since.is_none() {
+            self.running_since = Some((aligned, now));
+        }
+    }
+
+    /// With `aligned`, only whole seconds are meaningful.
+    pub(crate) fn elapsed(&self, now: Instant, aligned: bool) -> Duration {
+        match (self.running_since, aligned) {
+            (None, false) => self.accumulated,
+            (None, true) => self.accumulated_aligned,
+            (Some((_, since)), false) => self.accumulated + (now - since),
+            (Some((since, _)), true) => self.accumulated_aligned + (now - since),
+        }
+    }
+}
+
+/// A span of content rows, from `start` up to (but not including) `end`.
+pub(crate) struct NamedRange {
+    pub(crate) name: String,
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+/// Formatted rows from a previous render, along with everything they depend on.
+struct RowCache {
+    generation: u64,
+    width: u16,
+    strip_colors: bool,
+    ellipsis: String,
+    rows: Arc<Vec<Vec<u8>>>,
+}
+
+/// See [SecondaryOutputState::debug_at].
+pub(crate) struct SecondaryOutputDebug<'a> {
+    secondary_state: &'a SecondaryOutputState,
+    now: Instant,
+}
+
+impl fmt::Debug for SecondaryOutputDebug<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secondary_state = self.secondary_state;
+        f.debug_struct("SecondaryOutputState")
+            .field("id", &secondary_state.id)
+            .field("title", &secondary_state.title)
+            .field("expanded", &secondary_state.expanded)
+            .field(
+                "elapsed_ms",
+                &secondary_state.timer.elapsed(self.now, false).as_millis(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecondaryOutputState {
+    /// Something to print the output with [fmt::Debug], with the elapsed time as of `now` (eg from
+    /// a [crate::State]'s clock). Leaves out the buffer, which would otherwise print the entire
+    /// screen grid.
+    pub(crate) fn debug_at(&self, now: Instant) -> SecondaryOutputDebug<'_> {
+        SecondaryOutputDebug {
+            secondary_state: self,
+            now,
+        }
+    }
+
+    pub(crate) fn new(
+        id: SecondaryOutputId,
+        title: String,
+        timer: ElapsedTimer,
+        now: Instant,
+        max_lines: usize,
+    ) -> Self {
+        Self {
+            id,
+            title,
+            timer,
+            status: SecondaryOutputStatus::Running,
+            pinned: false,
+            highlight: None,
+            line_splitter: None,
+            expanded: false,
+            time_bar_max_duration: None,
+            bytes_received: 0,
+            dropped_bytes: 0,
+            truncation_marker_above: false,
+            scroll_offset: 0,
+            last_bell: None,
+            created: now,
+            last_output: now,
+            stall_notified: false,
+            show_scrollbar: false,
+            bookmarks: Vec::new(),
+            named_ranges: Vec::new(),
+            auto_follow_ranges: false,
+            show_named_ranges: false,
+            bell_parser: vte::Parser::new(),
+            buffer: SecondaryBuffer::new(SecondaryMode::Terminal, max_lines),
+            screen_cols: DEFAULT_SCREEN_COLS,
+            generation: 0,
+            row_cache: RefCell::new(None),
+            #[cfg(test)]
+            format_count: Cell::new(0),
+        }
+    }
+
+    /// Returns whether the bytes rang the terminal bell. Only the last `byte_budget` bytes are
+    /// kept, with a line marking where any were dropped (see
+    /// [crate::state::State::set_secondary_output_byte_budget]).
+    pub(crate) fn handle_bytes(
+        &mut self,
+        bytes: &[u8],
+        now: Instant,
+        byte_budget: usize,
+        ellipsis: &str,
+    ) -> bool {
+        self.generation += 1;
+        self.bytes_received += bytes.len();
+        self.last_output = now;
+        self.stall_notified = false;
+        let mut bell_counter = BellCounter(0);
+        for byte in bytes {
+            self.bell_parser.advance(&mut bell_counter, *byte);
+        }
+        let rang_bell = bell_counter.0 > 0;
+        if rang_bell {
+            self.last_bell = Some(now);
+        }
+        // The buffers already keep a bounded amount across calls, except for a line which never
+        // ends, but a single huge chunk would all be held on to (or parsed) at once
+        let kept = within_byte_budget(bytes, byte_budget);
+        let dropped = bytes.len() - kept.len();
+        self.dropped_bytes += dropped;
+        let marker = || format!("{ellipsis} output truncated {ellipsis}");
+        match &mut self.buffer {
+            SecondaryBuffer::Terminal(terminal) => {
+                if dropped > 0 {
+                    // Whatever's on the current line was cut off, so replace it with the marker,
+                    // unless the line above already is one
+                    terminal.handle_bytes(b"\r\x1b[K");
+                    if !self.truncation_marker_above {
+                        terminal.handle_bytes(format!("{}\r\n", marker()).as_bytes());
+                        self.truncation_marker_above = true;
+                    }
+                }
+                if kept.contains(&b'\n') {
+                    self.truncation_marker_above = false;
+                }
+                terminal.handle_bytes(kept);
+            }
+            SecondaryBuffer::PlainLines(lines) => {
+                let evicted_before = lines.evicted;
+                if dropped > 0 {
+                    lines.push_marker(marker(), now);
+                }
+                lines.handle_bytes(kept, now);
+                let trimmed = lines.trim_current_line(byte_budget);
+                if trimmed > 0 {
+                    self.dropped_bytes += trimmed;
+                    lines.push_marker(marker(), now);
+                }
+                // Keep bookmarks on the same lines as earlier ones drop out of the history.
+                // Bookmarks on lines which have dropped out go to the oldest line left.
+                let evicted = lines.evicted - evicted_before;
+                for (_, row) in &mut self.bookmarks {
+                    *row = row.saturating_sub(evicted);
+                }
+            }
+        }
+        rang_bell
+    }
+
+    pub(crate) fn expanded(&self) -> bool {
+        self.expanded
+    }
+
+    pub(crate) fn set_expanded(&mut self, expanded: bool) {
+        self.expanded = expanded;
+        if expanded {
+            if let SecondaryBuffer::Terminal(terminal) = &mut self.buffer {
+                terminal.ensure_parsed();
+            }
+        }
+    }
+
+    /// Roughly how many bytes of content are being held on to. See
+    /// [crate::state::SecondaryOutputDebugState::buffer_size].
+    pub(crate) fn buffer_size(&self) -> usize {
+        match &self.buffer {
+            SecondaryBuffer::Terminal(terminal) => terminal.buffered_len(),
+            SecondaryBuffer::PlainLines(lines) => lines.lines().map(str::len).sum(),
+        }
+    }
+
+    /// Where the next text received would go, as a (row, column) within the content.
+    pub(crate) fn buffer_cursor_position(&self) -> (u16, u16) {
+        match &self.buffer {
+            SecondaryBuffer::Terminal(terminal) => {
+                terminal.with_screen(|screen| screen.cursor_position())
+            }
+            SecondaryBuffer::PlainLines(lines) => (
+                lines.lines.len() as u16,
+                lines.current_line.chars().count() as u16,
+            ),
+        }
+    }
+
+    /// When each row of content was last written to, if that's tracked. It's only tracked for
+    /// [SecondaryMode::PlainLines], since a terminal can go back and change earlier rows.
+    pub(crate) fn line_times(&self) -> Option<Vec<Instant>> {
+        match &self.buffer {
+            SecondaryBuffer::Terminal(_) => None,
+            SecondaryBuffer::PlainLines(lines) => Some(lines.line_times().collect()),
+        }
+    }
+
+    pub(crate) fn has_line_times(&self) -> bool {
+        matches!(self.buffer, SecondaryBuffer::PlainLines(_))
+    }
+
+    /// Switches how bytes are interpreted. Anything received so far is discarded.
+    pub(crate) fn set_mode(&mut self, mode: SecondaryMode, max_lines: usize) {
+        self.buffer = SecondaryBuffer::new(mode, max_lines);
+        self.buffer.set_screen_cols(self.screen_cols);
+        self.generation += 1;
+        self.set_expanded(self.expanded);
+    }
+
+    /// Changes how wide the screen which output is drawn on is, eg to match the terminal. Output
+    /// which has already been parsed onto the screen isn't rewrapped.
+    pub(crate) fn set_screen_cols(&mut self, cols: u16) {
+        let cols = cols.max(1);
+        if cols == self.screen_cols {
+            return;
+        }
+        self.screen_cols = cols;
+        self.buffer.set_screen_cols(cols);
+        self.generation += 1;
+    }
+
+    /// Changes how much history is kept from here on. See
+    /// [crate::state::State::set_global_secondary_output_max_lines].
+    pub(crate) fn set_max_lines(&mut self, max_lines: usize) {
+        self.buffer.set_max_lines(max_lines);
+    }
+
+    /// Discards anything received so far, keeping the same mode.
+    pub(crate) fn clear_buffer(&mut self, max_lines: usize) {
+        self.set_mode(self.buffer.mode(), max_lines);
+        self.scroll_offset = 0;
+    }
+
+    /// All of the rows of content, without any trailing blank rows, truncated to `width` columns.
+    /// Reuses the rows from last time if nothing has changed since.
+    pub(crate) fn content_rows(
+        &self,
+        width: u16,
+        strip_colors: bool,
+        ellipsis: &str,
+    ) -> Arc<Vec<Vec<u8>>> {
+        let mut row_cache = self.row_cache.borrow_mut();
+        if let Some(cache) = row_cache.as_ref() {
+            if cache.generation == self.generation
+                && cache.width == width
+                && cache.strip_colors == strip_colors
+                && cache.ellipsis == ellipsis
+            {
+                return cache.rows.clone();
+            }
+        }
+        let rows = Arc::new(self.format_rows(width, strip_colors, ellipsis));
+        *row_cache = Some(RowCache {
+            generation: self.generation,
+            width,
+            strip_colors,
+            ellipsis: ellipsis.to_string(),
+            rows: rows.clone(),
+        });
+        rows
+    }
+
+    /// Same as [SecondaryOutputState::content_rows], but as styled lines rather than bytes.
+    pub(crate) fn content_lines(
+        &self,
+        width: u16,
+        strip_colors: bool,
+        ellipsis: &str,
+    ) -> Vec<Line> {
+        match &self.buffer {
+            SecondaryBuffer::Terminal(terminal) => terminal.content_lines(width, strip_colors),
+            SecondaryBuffer::PlainLines(lines) => lines
+                .lines()
+                .map(|line| {
+                    let mut content_line = Line::default();
+                    content_line.push(
+                        &truncate_to_width(line, width as usize, ellipsis),
+                        Style::default(),
+                    );
+                    content_line
+                })
+                .collect(),
+        }
+    }
+
+    fn format_rows(&self, width: u16, strip_colors: bool, ellipsis: &str) -> Vec<Vec<u8>> {
+        #[cfg(test)]
+        self.format_count.set(self.format_count.get() + 1);
+        match &self.buffer {
+            SecondaryBuffer::Terminal(terminal) => terminal.content_rows(width, strip_colors),
+            SecondaryBuffer::PlainLines(lines) => lines
+                .lines()
+                .map(|line| {
+                    truncate_to_width(line, width as usize, ellipsis)
+                        .into_owned()
+                        .into_bytes()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counts the bells in whatever's parsed, ignoring everything else. Unlike just looking for the
+/// byte, this knows when it's ending an OSC sequence rather than ringing the bell.
+struct BellCounter(usize);
+
+impl vte::Perform for BellCounter {
+    fn execute(&mut self, byte: u8) {
+        if byte == 0x07 {
+            self.0 += 1;
+        }
+    }
+}
+
+// There's only one of these per output, so not worth boxing the plain lines parser
+#[allow(clippy::large_enum_variant)]
+enum SecondaryBuffer {
+    Terminal(TerminalBuffer),
+    PlainLines(PlainLines),
+}
+
+impl SecondaryBuffer {
+    fn new(mode: SecondaryMode, max_lines: usize) -> Self {
+        let history = history_for(max_lines);
+        match mode {
+            SecondaryMode::Terminal => Self::Terminal(TerminalBuffer::new(history)),
+            SecondaryMode::PlainLines(carriage_return) => {
+                Self::PlainLines(PlainLines::new(carriage_return, history))
+            }
+        }
+    }
+
+    /// Only affects what's kept of future output. A parsed terminal's scrollback can't be resized,
+    /// so it keeps the history it was parsed with.
+    fn set_max_lines(&mut self, max_lines: usize) {
+        let history = history_for(max_lines);
+        match self {
+            Self::Terminal(terminal) => terminal.history = history,
+            Self::PlainLines(lines) => lines.capacity = history,
+        }
+    }
+
+    fn set_screen_cols(&mut self, cols: u16) {
+        match self {
+            Self::Terminal(terminal) => terminal.set_cols(cols),
+            // Lines are kept whole, so there's nothing to wrap
+            Self::PlainLines(_) => {}
+        }
+    }
+
+    fn mode(&self) -> SecondaryMode {
+        match self {
+            Self::Terminal(_) => SecondaryMode::Terminal,
+            Self::PlainLines(lines) => SecondaryMode::PlainLines(lines.carriage_return),
+        }
+    }
+}
+
+/// How many lines of history to keep when showing `max_lines` lines.
+fn history_for(max_lines: usize) -> usize {
+    // Keep some extra history around
+    max_lines.max(1) * 3
+}
+
+/// How big an output's screen is until the terminal is resized, see
+/// [crate::state::State::handle_resize].
+const DEFAULT_SCREEN_COLS: u16 = 50;
+const SCREEN_ROWS: u16 = 50;
+
+/// Roughly how many bytes to keep per line of history before the output is first expanded.
+const RAW_BYTES_PER_LINE: usize = 256;
+/// How many bytes an expanded output can hold on to before parsing them, even if it isn't
+/// rendered, so they don't pile up forever.
+const PENDING_BYTES_CAP: usize = 64 * 1024;
+
+/// Terminal emulation for an output. Most outputs are never expanded, so until one is, this only
+/// keeps a bounded ring buffer of the most recent raw bytes rather than a full cell grid.
+struct TerminalBuffer {
+    history: usize,
+    cols: u16,
+    raw_bytes: VecDeque<u8>,
+    /// Parsing is deferred until rendering needs the screen, which only has shared access.
+    parsed: Option<RefCell<ParsedTerminal>>,
+}
+
+struct ParsedTerminal {
+    parser: Box<vt100::Parser>,
+    pending: Vec<u8>,
+}
+
+impl ParsedTerminal {
+    fn process_pending(&mut self) {
+        self.parser.process(&self.pending);
+        self.pending.clear();
+    }
+}
+
+impl TerminalBuffer {
+    fn new(history: usize) -> Self {
+        Self {
+            history,
+            cols: DEFAULT_SCREEN_COLS,
+            raw_bytes: VecDeque::new(),
+            parsed: None,
+        }
+    }
+
+    fn raw_bytes_capacity(&self) -> usize {
+        self.history * RAW_BYTES_PER_LINE
+    }
+
+    /// Bytes which haven't been parsed into the screen yet.
+    fn buffered_len(&self) -> usize {
+        match &self.parsed {
+            Some(parsed) => parsed.borrow().pending.len(),
+            None => self.raw_bytes.len(),
+        }
+    }
+
+    fn handle_bytes(&mut self, bytes: &[u8]) {
+        if let Some(parsed) = &mut self.parsed {
+            let parsed = parsed.get_mut();
+            parsed.pending.extend_from_slice(bytes);
+            if parsed.pending.len() > PENDING_BYTES_CAP {
+                parsed.process_pending();
+            }
+            return;
+        }
+        let capacity = self.raw_bytes_capacity();
+        // Only the tail of a big chunk could ever fit
+        let bytes = &bytes[bytes.len().saturating_sub(capacity)..];
+        let overflow = (self.raw_bytes.len() + bytes.len()).saturating_sub(capacity);
+        self.raw_bytes.drain(..overflow);
+        self.raw_bytes.extend(bytes);
+    }
+
+    fn parse_raw_bytes(&self) -> Box<vt100::Parser> {
+        let mut parser = Box::new(vt100::Parser::new(SCREEN_ROWS, self.cols, self.history));
+        let (front, back) = self.raw_bytes.as_slices();
+        parser.process(front);
+        parser.process(back);
+        parser
+    }
+
+    fn set_cols(&mut self, cols: u16) {
+        self.cols = cols;
+        if let Some(parsed) = &mut self.parsed {
+            let parsed = parsed.get_mut();
+            // Anything received before the resize was written at the old size
+            parsed.process_pending();
+            parsed.parser.set_size(SCREEN_ROWS, cols);
+        }
+    }
+
+    /// Switches over to keeping all bytes for parsing, replaying whatever's been buffered so far.
+    fn ensure_parsed(&mut self) {
+        if self.parsed.is_none() {
+            self.parsed = Some(RefCell::new(ParsedTerminal {
+                parser: self.parse_raw_bytes(),
+                pending: Vec::new(),
+            }));
+            self.raw_bytes = VecDeque::new();
+        }
+    }
+
+    fn with_screen<T>(&self, f: impl FnOnce(&vt100::Screen) -> T) -> T {
+        match &self.parsed {
+            Some(cell) => {
+                let mut parsed = cell.borrow_mut();
+                parsed.process_pending();
+                f(parsed.parser.screen())
+            }
+            // Outputs are parsed once they're expanded, so this is just a fallback
+            None => f(self.parse_raw_bytes().screen()),
+        }
+    }
+
+    fn content_rows(&self, width: u16, strip_colors: bool) -> Vec<Vec<u8>> {
+        self.with_screen(|screen| {
+            let mut rows = if strip_colors {
+                screen
+                    .rows(0, width)
+                    .map(String::into_bytes)
+                    .collect::<Vec<_>>()
+            } else {
+                screen.rows_formatted(0, width).collect::<Vec<_>>()
+            };
+            rows.truncate(num_content_rows(screen, width));
+            rows
+        })
+    }
+
+    fn content_lines(&self, width: u16, strip_colors: bool) -> Vec<Line> {
+        self.with_screen(|screen| {
+            let (_, cols) = screen.size();
+            let width = width.min(cols);
+            (0..num_content_rows(screen, width) as u16)
+                .map(|row| {
+                    let cells = (0..width)
+                        .filter_map(|col| screen.cell(row, col))
+                        .collect::<Vec<_>>();
+                    // Trailing blank cells aren't part of the content
+                    let len = cells
+                        .iter()
+                        .rposition(|cell| cell.has_contents())
+                        .map_or(0, |last| last + 1);
+                    let mut line = Line::default();
+                    for cell in &cells[..len] {
+                        if cell.is_wide_continuation() {
+                            continue;
+                        }
+                        let style = if strip_colors {
+                            Style::default()
+                        } else {
+                            cell_style(cell)
+                        };
+                        let contents = cell.contents();
+                        line.push(if contents.is_empty() { " " } else { &contents }, style);
+                    }
+                    line
+                })
+                .collect()
+        })
+    }
+}
+
+/// How many rows of the screen have content, ignoring trailing blank rows.
+fn num_content_rows(screen: &vt100::Screen, width: u16) -> usize {
+    let (cursor_row, cursor_col) = screen.cursor_position();
+    // If we're at the beginning of the row, assume trailing newline, remove it. At the very start
+    // there's no row at all
+    let cursor_row = if cursor_col == 0 {
+        (cursor_row as usize).checked_sub(1)
+    } else {
+        Some(cursor_row as usize)
+    };
+    // Probably don't technically need this since we're nominally not handling
+    // terminal control sequences
+    let last_non_empty_row = screen
+        .rows(0, width)
+        .enumerate()
+        .filter(|(_, row)| !row.is_empty())
+        .last()
+        .map(|(idx, _)| idx);
+    max(cursor_row, last_non_empty_row).map_or(0, |end_idx| end_idx + 1)
+}
+
+fn cell_style(cell: &vt100::Cell) -> Style {
+    let color = |color| match color {
+        vt100::Color::Default => None,
+        vt100::Color::Idx(idx) => Some(Color::Indexed(idx)),
+        vt100::Color::Rgb(r, g, b) => Some(Color::Rgb(r, g, b)),
+    };
+    Style {
+        foreground: color(cell.fgcolor()),
+        background: color(cell.bgcolor()),
+        bold: cell.bold(),
+        italic: cell.italic(),
+        underline: cell.underline(),
+        inverse: cell.inverse(),
+    }
+}
+
+/// The end of `bytes` which fits in `byte_budget`, starting at the next line, escape sequence or
+/// character after the cut rather than partway through one.
+fn within_byte_budget(bytes: &[u8], byte_budget: usize) -> &[u8] {
+    &bytes[resync_point(bytes, bytes.len().saturating_sub(byte_budget))..]
+}
+
+/// Splits output into lines of plain text as each one is completed, for
+/// [crate::State::set_combined_tail].
+pub(crate) struct LineSplitter(PlainLines);
+
+impl LineSplitter {
+    pub(crate) fn new() -> Self {
+        Self(PlainLines::new(CarriageReturnMode::Overwrite, usize::MAX))
+    }
+
+    /// Returns the lines which `bytes` completed. Like the outputs themselves, at most
+    /// `byte_budget` bytes are handled at once, and a line which never ends keeps only its last
+    /// `byte_budget` bytes.
+    pub(crate) fn handle_bytes(
+        &mut self,
+        bytes: &[u8],
+        now: Instant,
+        byte_budget: usize,
+    ) -> Vec<String> {
+        self.0
+            .handle_bytes(within_byte_budget(bytes, byte_budget), now);
+        self.0.trim_current_line(byte_budget);
+        self.0.line_times.clear();
+        self.0.lines.drain(..).collect()
+    }
+}
+
+/// A ring buffer of the most recent lines of text, with any control sequences dropped.
+struct PlainLines {
+    parser: VteActionParser,
+    carriage_return: CarriageReturnMode,
+    lines: VecDeque<String>,
+    /// When each of `lines` was last written to.
+    line_times: VecDeque<Instant>,
+    current_line: String,
+    current_line_time: Option<Instant>,
+    capacity: usize,
+    /// How many lines have been dropped from the front for going over the capacity.
+    evicted: usize,
+    /// Whether the last action was a carriage return, which affects what comes next.
+    after_carriage_return: bool,
+}
+
+impl PlainLines {
+    fn new(carriage_return: CarriageReturnMode, capacity: usize) -> Self {
+        Self {
+            parser: VteActionParser::new(),
+            carriage_return,
+            lines: VecDeque::new(),
+            line_times: VecDeque::new(),
+            current_line: String::new(),
+            current_line_time: None,
+            capacity,
+            evicted: 0,
+            after_carriage_return: false,
+        }
+    }
+
+    fn handle_bytes(&mut self, bytes: &[u8], now: Instant) {
+        for action in self.parser.parse_bytes(bytes) {
+            // Styling (eg colors) and sequences with no action of their own shouldn't interrupt a
+            // "\r\n"
+            if matches!(action, VteAction::Ignored { .. } | VteAction::SetStyle(_)) {
+                continue;
+            }
+            let after_carriage_return = self.after_carriage_return;
+            self.after_carriage_return = false;
+            match action {
+                VteAction::Text(c) => {
+                    if after_carriage_return
+                        && self.carriage_return == CarriageReturnMode::Overwrite
+                    {
+                        self.current_line.clear();
+                    }
+                    self.current_line.push(c);
+                    self.current_line_time = Some(now);
+                }
+                VteAction::Tab => {
+                    let spaces = 8 - (self.current_line.chars().count() % 8);
+                    self.current_line.push_str(&" ".repeat(spaces));
+                    self.current_line_time = Some(now);
+                }
+                // A "\r\n" has already ended the line if carriage returns separate lines
+                VteAction::LineFeed
+                    if after_carriage_return
+                        && self.carriage_return == CarriageReturnMode::LineSeparator => {}
+                VteAction::LineFeed => self.end_line(now),
+                VteAction::CarriageReturn => {
+                    if self.carriage_return == CarriageReturnMode::LineSeparator {
+                        self.end_line(now);
+                    }
+                    self.after_carriage_return = true;
+                }
+                // Cursor movement doesn't make sense for plain lines
+                _ => {}
+            }
+        }
+    }
+
+    fn end_line(&mut self, now: Instant) {
+        self.lines.push_back(std::mem::take(&mut self.current_line));
+        self.line_times
+            .push_back(self.current_line_time.take().unwrap_or(now));
+        // Could be more than one line over if the capacity was lowered
+        while self.lines.len() > self.capacity {
+            self.lines.pop_front();
+            self.line_times.pop_front();
+            self.evicted += 1;
+        }
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &str> {
+        let current_line = Some(self.current_line.as_str()).filter(|line| !line.is_empty());
+        self.lines.iter().map(String::as_str).chain(current_line)
+    }
+
+    /// Adds a line marking where output was dropped, in place of the current line which was cut
+    /// off. Does nothing if the last line is already a marker, eg when the same long line is
+    /// trimmed again.
+    fn push_marker(&mut self, marker: String, now: Instant) {
+        if self.lines.back() == Some(&marker) {
+            return;
+        }
+        // The current line is kept if it comes after what was dropped
+        let current_line = std::mem::take(&mut self.current_line);
+        let current_line_time = self.current_line_time.take();
+        self.current_line = marker;
+        self.end_line(now);
+        self.current_line = current_line;
+        self.current_line_time = current_line_time;
+    }
+
+    /// Drops the start of the current line if it's grown past `max_bytes`, returning how many bytes
+    /// were dropped.
+    fn trim_current_line(&mut self, max_bytes: usize) -> usize {
+        let excess = self.current_line.len().saturating_sub(max_bytes);
+        if excess == 0 {
+            return 0;
+        }
+        let mut start = excess;
+        while !self.current_line.is_char_boundary(start) {
+            start += 1;
+        }
+        self.current_line.drain(..start);
+        start
+    }
+
+    /// When each of [PlainLines::lines] was last written to.
+    fn line_times(&self) -> impl Iterator<Item = Instant> + '_ {
+        let current_line_time = self
+            .current_line_time
+            .filter(|_| !self.current_line.is_empty());
+        self.line_times.iter().copied().chain(current_line_time)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn collapsed_outputs_only_buffer_raw_bytes() {
+        let mut terminal = TerminalBuffer::new(3);
+        for i in 0..10_000 {
+            terminal.handle_bytes(format!("line {i}\r\n").as_bytes());
+        }
+        assert!(terminal.parsed.is_none());
+        assert_eq!(terminal.raw_bytes.len(), terminal.raw_bytes_capacity());
+
+        terminal.ensure_parsed();
+        assert!(terminal.parsed.is_some());
+        assert_eq!(terminal.raw_bytes.capacity(), 0);
+    }
+
+    #[test]
+    fn expanded_outputs_parse_when_rendered() {
+        let mut terminal = TerminalBuffer::new(3);
+        terminal.ensure_parsed();
+        terminal.handle_bytes(b"some\r\noutput\r\n");
+        let pending =
+            |terminal: &TerminalBuffer| terminal.parsed.as_ref().unwrap().borrow().pending.len();
+        assert_eq!(pending(&terminal), 14);
+
+        assert_eq!(
+            terminal.content_rows(50, true),
+            vec![b"some".to_vec(), b"output".to_vec()]
+        );
+        assert_eq!(pending(&terminal), 0);
+
+        // Parses anyway once enough bytes pile up
+        for _ in 0..PENDING_BYTES_CAP / 10 {
+            terminal.handle_bytes(b"0123456789");
+        }
+        assert_eq!(pending(&terminal), PENDING_BYTES_CAP / 10 * 10);
+        terminal.handle_bytes(b"0123456789");
+        assert_eq!(pending(&terminal), 0);
+    }
+}
@@ -0,0 +1,152 @@
+//! Keeping track of the styling the primary output has turned on, so it can be put back after
+//! drawing the secondary outputs, which reset it.
+
+use std::collections::BTreeMap;
+
+/// Groups of SGR parameters where each replaces whatever the last one in the group set, eg any
+/// foreground color replaces the last foreground color.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+enum Attribute {
+    Bold,
+    Faint,
+    Italic,
+    Underline,
+    Blink,
+    Inverse,
+    Hidden,
+    Strikethrough,
+    Foreground,
+    Background,
+    Overline,
+    UnderlineColor,
+}
+
+/// Which SGR parameters are in effect, from [VteAction::SetStyle](crate::VteAction::SetStyle).
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub(crate) struct SgrState {
+    /// The parameter (as it was sent) which set each attribute that isn't at its default.
+    attributes: BTreeMap<Attribute, String>,
+}
+
+impl SgrState {
+    /// Applies the parameters of an SGR sequence, each along with its sub-parameters.
+    pub(crate) fn apply(&mut self, params: &[Vec<u16>]) {
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            let Some(&code) = param.first() else {
+                continue;
+            };
+            let mut text = join(param, ":");
+            // Extended colors' arguments can be separate parameters rather than sub-parameters
+            if matches!(code, 38 | 48 | 58) && param.len() == 1 {
+                let count = match params.clone().next().and_then(|param| param.first()) {
+                    Some(5) => 2,
+                    Some(2) => 4,
+                    _ => 0,
+                };
+                for arg in params.by_ref().take(count) {
+                    text.push(';');
+                    text.push_str(&join(arg, ":"));
+                }
+            }
+            let (set, reset): (Option<Attribute>, &[Attribute]) = match code {
+                0 => {
+                    self.attributes.clear();
+                    continue;
+                }
+                1 => (Some(Attribute::Bold), &[]),
+                2 => (Some(Attribute::Faint), &[]),
+                3 => (Some(Attribute::Italic), &[]),
+                // `4:0` turns underlining off
+                4 if param.get(1) == Some(&0) => (None, &[Attribute::Underline]),
+                4 | 21 => (Some(Attribute::Underline), &[]),
+                5 | 6 => (Some(Attribute::Blink), &[]),
+                7 => (Some(Attribute::Inverse), &[]),
+                8 => (Some(Attribute::Hidden), &[]),
+                9 => (Some(Attribute::Strikethrough), &[]),
+                22 => (None, &[Attribute::Bold, Attribute::Faint]),
+                23 => (None, &[Attribute::Italic]),
+                24 => (None, &[Attribute::Underline]),
+                25 => (None, &[Attribute::Blink]),
+                27 => (None, &[Attribute::Inverse]),
+                28 => (None, &[Attribute::Hidden]),
+                29 => (None, &[Attribute::Strikethrough]),
+                30..=38 | 90..=97 => (Some(Attribute::Foreground), &[]),
+                39 => (None, &[Attribute::Foreground]),
+                40..=48 | 100..=107 => (Some(Attribute::Background), &[]),
+                49 => (None, &[Attribute::Background]),
+                53 => (Some(Attribute::Overline), &[]),
+                55 => (None, &[Attribute::Overline]),
+                58 => (Some(Attribute::UnderlineColor), &[]),
+                59 => (None, &[Attribute::UnderlineColor]),
+                // Anything else (eg fonts) isn't worth putting back
+                _ => continue,
+            };
+            for attribute in reset {
+                self.attributes.remove(attribute);
+            }
+            if let Some(attribute) = set {
+                self.attributes.insert(attribute, text);
+            }
+        }
+    }
+
+    /// The sequence to turn the same styling back on after a reset, if there's any to turn on.
+    pub(crate) fn sequence(&self) -> Option<String> {
+        if self.attributes.is_empty() {
+            return None;
+        }
+        let params = self.attributes.values().cloned().collect::<Vec<_>>();
+        Some(format!("\x1b[{}m", params.join(";")))
+    }
+}
+
+fn join(values: &[u16], separator: &str) -> String {
+    values
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sequence_after(sequences: &[&[u16]]) -> Option<String> {
+        let mut state = SgrState::default();
+        for params in sequences {
+            let params = params.iter().map(|param| vec![*param]).collect::<Vec<_>>();
+            state.apply(&params);
+        }
+        state.sequence()
+    }
+
+    #[test]
+    fn later_parameters_replace_earlier_ones() {
+        assert_eq!(sequence_after(&[]), None);
+        assert_eq!(
+            sequence_after(&[&[1, 31], &[4], &[32]]),
+            Some("\x1b[1;4;32m".into())
+        );
+        assert_eq!(
+            sequence_after(&[&[1, 2, 31], &[22]]),
+            Some("\x1b[31m".into())
+        );
+        assert_eq!(sequence_after(&[&[1, 31], &[0, 7]]), Some("\x1b[7m".into()));
+        assert_eq!(sequence_after(&[&[41], &[49], &[10]]), None);
+    }
+
+    #[test]
+    fn keeps_extended_colors_as_they_were_sent() {
+        assert_eq!(
+            sequence_after(&[&[38, 5, 196, 1], &[48, 2, 1, 2, 3]]),
+            Some("\x1b[1;38;5;196;48;2;1;2;3m".into())
+        );
+        let mut state = SgrState::default();
+        state.apply(&[vec![38, 2, 0, 1, 2, 3], vec![4, 3]]);
+        assert_eq!(state.sequence(), Some("\x1b[4:3;38:2:0:1:2:3m".into()));
+        state.apply(&[vec![4, 0]]);
+        assert_eq!(state.sequence(), Some("\x1b[38:2:0:1:2:3m".into()));
+    }
+}
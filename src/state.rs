@@ -1,22 +1,40 @@
-use crate::vte_actions::{VteAction, VteActionParser};
+use crate::backend::{Backend, RenderBackend};
+use crate::frame::{self, Frame, FrameLayout, Line, OutputLayout, Style};
+use crate::keys::UiAction;
+use crate::mouse::{MouseModes, MouseOutcome, MOUSE_SCROLL_LINES};
+use crate::search::{self, MatchLocation};
+use crate::secondary_output::{
+    ElapsedTimer, LineSplitter, NamedRange, SecondaryMode, SecondaryOutputState,
+    SecondaryOutputStatus,
+};
+use crate::sgr::SgrState;
+use crate::symbols::Symbols;
+use crate::text::{display_width, truncate_to_width};
+use crate::vte_actions::{resync_point, VteAction, VteActionParser};
 use anyhow::{anyhow, Result};
-use crossterm::cursor::{MoveDown, MoveRight, MoveToColumn, MoveUp};
-use crossterm::queue;
-use crossterm::style::{Color, Print, PrintStyledContent, Stylize};
-use crossterm::terminal::Clear;
-use crossterm::terminal::ClearType::FromCursorDown;
-use std::cmp::max;
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+use crossterm::style::Color;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::io::Write;
-use std::time::Duration;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use unicode_width::UnicodeWidthChar;
 
 #[cfg(test)]
-use mock_instant::Instant;
+pub(crate) use mock_instant::Instant;
 
 #[cfg(not(test))]
-use std::time::Instant;
+pub(crate) use std::time::Instant;
 
-// TODO - Make this non-copy/clone?
-#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+/// Identifies a secondary output within the [State] which created it.
+///
+/// The [Default] ID is the one given to the first output a [State] creates. IDs are ordered by
+/// when their outputs were created, and never reused.
+///
+/// IDs are [Copy], so copies can outlive their output. Using one after its output is removed is an
+/// error.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct SecondaryOutputId(u32);
 
 impl SecondaryOutputId {
@@ -27,457 +45,7475 @@ impl SecondaryOutputId {
     }
 }
 
-struct SecondaryOutputState {
-    id: SecondaryOutputId,
-    title: String,
-    start: Instant,
-    expanded: bool,
-    // If we don't end up using this, move the dep back to test-only
-    buffer: vt100::Parser,
+impl std::fmt::Display for SecondaryOutputId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parses the [std::fmt::Display] format, ie just the number.
+impl std::str::FromStr for SecondaryOutputId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let id = s
+            .parse()
+            .map_err(|error| anyhow!("Invalid output ID {s:?}: {error}"))?;
+        Ok(SecondaryOutputId(id))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SecondaryOutputId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SecondaryOutputId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer).map(SecondaryOutputId)
+    }
+}
+
+/// Identifies a primary output within the [State] which created it.
+///
+/// The [Default] ID is the primary output every [State] starts with, which
+/// [State::handle_primary_bytes] is for.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PrimaryOutputId(u32);
+
+impl PrimaryOutputId {
+    fn next_id(&mut self) -> Self {
+        let id = self.0;
+        self.0 += 1;
+        PrimaryOutputId(id)
+    }
+}
+
+/// How precisely to show how long secondary outputs have been running.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ElapsedPrecision {
+    /// eg `12s`
+    Seconds,
+    /// eg `12.3s`
+    Tenths,
+    /// eg `12345ms`
+    Milliseconds,
+}
+
+impl ElapsedPrecision {
+    /// When `padded`, pads to a minimum width so headers line up. `style` is only used for
+    /// [ElapsedPrecision::Seconds].
+    fn format(&self, style: DurationStyle, elapsed: Duration, padded: bool) -> String {
+        match (self, padded) {
+            (ElapsedPrecision::Seconds, true) => {
+                format!("{: >1$}", style.format(elapsed), style.padded_width())
+            }
+            (ElapsedPrecision::Seconds, false) => style.format(elapsed),
+            (ElapsedPrecision::Tenths, true) => format!("{: >5.1}s", elapsed.as_secs_f64()),
+            (ElapsedPrecision::Tenths, false) => format!("{:.1}s", elapsed.as_secs_f64()),
+            (ElapsedPrecision::Milliseconds, true) => format!("{: >6}ms", elapsed.as_millis()),
+            (ElapsedPrecision::Milliseconds, false) => format!("{}ms", elapsed.as_millis()),
+        }
+    }
+}
+
+/// How to show whole seconds of elapsed time once they get large.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum DurationStyle {
+    /// Always in seconds, eg `3725s`
+    #[default]
+    Seconds,
+    /// eg `59s`, then `01:00`, then `1:00:00`
+    Clock,
+    /// eg `59s`, then `1m00s`, then `1h00m`
+    Compact,
+}
+
+impl DurationStyle {
+    fn format(&self, elapsed: Duration) -> String {
+        let seconds = elapsed.as_secs();
+        let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+        match self {
+            DurationStyle::Seconds => format!("{seconds}s"),
+            _ if seconds < 60 => format!("{seconds}s"),
+            DurationStyle::Clock if hours == 0 => format!("{minutes:02}:{:02}", seconds % 60),
+            DurationStyle::Clock => format!("{hours}:{minutes:02}:{:02}", seconds % 60),
+            DurationStyle::Compact if hours == 0 => format!("{minutes}m{:02}s", seconds % 60),
+            DurationStyle::Compact => format!("{hours}h{minutes:02}m"),
+        }
+    }
+
+    /// How wide to pad to so that headers don't jump around as times cross from seconds to
+    /// minutes to hours. Only runs longer than 10 hours go past it.
+    fn padded_width(&self) -> usize {
+        match self {
+            DurationStyle::Seconds => 4,
+            DurationStyle::Clock => 7,
+            DurationStyle::Compact => 6,
+        }
+    }
+}
+
+/// Where to show that the primary output has finished (see [State::handle_primary_eof]).
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum EofIndicatorPosition {
+    /// After wherever the primary output left the cursor, or on the next line if it doesn't fit.
+    #[default]
+    EndOfLine,
+    /// On a line of its own, unless the primary output left the cursor on an empty line.
+    OwnLine,
+    Hidden,
+}
+
+/// A query the primary output sent to the terminal, which should be answered based on where the
+/// primary output thinks the cursor is rather than wherever rendering has left it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PrimaryQuery {
+    /// `CSI 6n`, with the cursor's (0-based) column and how many lines up from the bottom of the
+    /// primary output it was at the time.
+    CursorPosition { column: u16, lines_from_bottom: u16 },
+}
+
+impl PrimaryQuery {
+    /// The bytes to send back to the primary program, assuming the bottom of its output is the
+    /// last of the `rows` rows it believes its terminal has.
+    pub fn reply(&self, rows: u16) -> Vec<u8> {
+        match self {
+            PrimaryQuery::CursorPosition {
+                column,
+                lines_from_bottom,
+            } => {
+                let row = rows.saturating_sub(*lines_from_bottom).max(1);
+                format!("\x1b[{};{}R", row, column.saturating_add(1)).into_bytes()
+            }
+        }
+    }
+}
+
+/// Something wrong with a [State]'s internals, see [State::validate].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StateInvariantViolation {
+    /// The selected output is past the end of the list.
+    SelectionOutOfBounds { index: usize, len: usize },
+    /// More than one output has the same ID.
+    DuplicateId(SecondaryOutputId),
+    /// The number of lines the last render is recorded as drawing doesn't match how many it laid
+    /// out, so the next render would clear the wrong lines.
+    ExtraLinesMismatch { recorded: u16, laid_out: usize },
+}
+
+/// Something which happened to the outputs, see [State::set_event_handler].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StateEvent {
+    OutputAdded {
+        id: SecondaryOutputId,
+        title: String,
+    },
+    OutputRemoved {
+        id: SecondaryOutputId,
+    },
+    /// The output's status changed from running to finished.
+    OutputFinished {
+        id: SecondaryOutputId,
+        status: SecondaryOutputStatus,
+    },
+    /// The output is still running but hasn't received anything for a while (see
+    /// [State::set_stall_threshold]). Only sent once until it receives something again.
+    OutputStalled {
+        id: SecondaryOutputId,
+    },
+    /// The cursor moved to a different output, or `None` once there are no outputs left.
+    SelectionChanged {
+        id: Option<SecondaryOutputId>,
+    },
+}
+
+/// A summary of a secondary output, see [State::secondary_output_metadata]. More fields may be
+/// added over time.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct SecondaryOutputInfo {
+    pub id: SecondaryOutputId,
+    pub title: String,
+    /// How long the output has been running for (not counting time spent paused), unrounded.
+    pub elapsed: Duration,
+    pub expanded: bool,
+    /// Whether the output's status is [SecondaryOutputStatus::Finished].
+    pub completed: bool,
+    pub exit_code: Option<i32>,
+    /// How many rows of content the output has, at the width it would be rendered at.
+    pub line_count: usize,
+    /// Total bytes sent to the output.
+    pub byte_count: usize,
+    /// How long it's been since the output last received any bytes, or since it was created if it
+    /// hasn't yet.
+    pub since_last_output: Duration,
+    /// Whether the output is left out of the list. Outputs can't currently be hidden, so this is
+    /// always false.
+    pub hidden: bool,
+    /// How many bytes sent to the output were dropped for going over its budget (see
+    /// [State::set_secondary_output_byte_budget]).
+    pub dropped_bytes: usize,
+}
+
+/// What an output had in it when it was removed, see [State::take_secondary_output].
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TakenOutput {
+    pub title: String,
+    /// How long the output had been running for (not counting time spent paused), unrounded.
+    pub elapsed: Duration,
+    pub status: SecondaryOutputStatus,
+    /// Every row of content still kept, one per line, including any escape sequences for styling.
+    /// Rows with styling end by resetting it, so none of it carries over to whatever's printed
+    /// after them.
+    pub contents: String,
+}
+
+/// A snapshot of a [State]'s internals, for including in bug reports (eg from a `--debug` flag).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DebugState {
+    /// Primary output which hasn't been written out yet.
+    pub primary_bytes_len: usize,
+    /// How far from the left and bottom (respectively) of the primary output the cursor is.
+    pub primary_cursor_offset: (u16, u16),
+    /// How many lines the last render drew below the primary output.
+    pub previous_render_extra_lines: u16,
+    pub secondary_output_count: usize,
+    /// How many times [State::render] has got as far as drawing the secondary outputs.
+    pub render_count: u64,
+    pub secondary_outputs: Vec<SecondaryOutputDebugState>,
+}
+
+/// The part of a [DebugState] for a single secondary output.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SecondaryOutputDebugState {
+    pub id: SecondaryOutputId,
+    pub title: String,
+    pub expanded: bool,
+    pub bytes_received: usize,
+    /// Roughly how many bytes of content are being held on to. Once an output has been expanded
+    /// its content is parsed into a screen, which isn't counted, so this is only the bytes which
+    /// haven't been parsed yet.
+    pub buffer_size: usize,
+}
+
+const TIME_BAR_WIDTH: usize = 8;
+
+/// The top and bottom rows of the primary output's scroll region, as lines up from the bottom of
+/// the terminal (like the tracked cursor offset). Without knowing how tall the terminal is, there's
+/// no telling where the region is, so this is `None` and the whole screen is assumed to scroll.
+fn scroll_region_offsets(
+    terminal_size: Option<(u16, u16)>,
+    scroll_region: Option<(u16, u16)>,
+) -> Option<(u16, u16)> {
+    let ((_, rows), (top, bottom)) = terminal_size.zip(scroll_region)?;
+    let bottom = if bottom == 0 { rows } else { bottom.min(rows) };
+    Some((rows.saturating_sub(top), rows - bottom))
+}
+
+/// The (0-based) columns which tabs in the primary output stop at. The default stops every 8
+/// columns aren't stored, only how the stops differ from them.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct TabStops {
+    /// Whether the default stops apply, until all the stops are cleared or replaced.
+    defaults: bool,
+    /// Stops which aren't default ones.
+    added: BTreeSet<u16>,
+    /// Default stops which have been cleared.
+    removed: BTreeSet<u16>,
+}
+
+impl Default for TabStops {
+    fn default() -> Self {
+        Self {
+            defaults: true,
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+        }
+    }
+}
+
+impl TabStops {
+    fn is_default_stop(&self, column: u16) -> bool {
+        self.defaults && column > 0 && column % 8 == 0
+    }
+
+    /// The first stop after `column`, if there is one.
+    fn next_after(&self, column: u16) -> Option<u16> {
+        let mut next_default = None;
+        if self.defaults {
+            let mut stop = column.checked_add(8 - column % 8);
+            while let Some(removed) = stop.filter(|stop| self.removed.contains(stop)) {
+                stop = removed.checked_add(8);
+            }
+            next_default = stop;
+        }
+        let next_added = self.added.range(column.saturating_add(1)..).next().copied();
+        match (next_default, next_added) {
+            (Some(default), Some(added)) => Some(default.min(added)),
+            (default, added) => default.or(added),
+        }
+    }
+
+    fn set(&mut self, column: u16) {
+        if self.is_default_stop(column) {
+            self.removed.remove(&column);
+        } else {
+            self.added.insert(column);
+        }
+    }
+
+    fn clear(&mut self, column: u16) {
+        if self.is_default_stop(column) {
+            self.removed.insert(column);
+        } else {
+            self.added.remove(&column);
+        }
+    }
+
+    /// Replaces all the stops, including the default ones.
+    fn replace(&mut self, stops: impl IntoIterator<Item = u16>) {
+        *self = Self {
+            defaults: false,
+            added: stops.into_iter().collect(),
+            removed: BTreeSet::new(),
+        };
+    }
+}
+
+/// A bar like `[===     ] ` which fills up as `elapsed` approaches `max_duration`.
+fn format_time_bar(elapsed: Duration, max_duration: Duration) -> String {
+    let proportion = if max_duration.is_zero() {
+        1.0
+    } else {
+        (elapsed.as_secs_f64() / max_duration.as_secs_f64()).min(1.0)
+    };
+    let filled = (proportion * TIME_BAR_WIDTH as f64) as usize;
+    format!(
+        "[{}{}] ",
+        "=".repeat(filled),
+        " ".repeat(TIME_BAR_WIDTH - filled)
+    )
+}
+
+/// A timestamp like `[012.3s] `, always [LINE_TIMESTAMP_WIDTH] wide. Tenths are dropped once
+/// there's no room for them, and anything too long to show at all is capped.
+fn format_line_timestamp(since_created: Duration) -> String {
+    const DIGITS: usize = LINE_TIMESTAMP_DIGITS;
+    // The point and tenths take up two of the characters, and anything which would round up
+    // past the rest is too wide to show tenths
+    let max_with_tenths = 10f64.powi(DIGITS as i32 - 2) - 0.05;
+    let secs = since_created.as_secs_f64();
+    if secs < max_with_tenths {
+        format!("[{secs:0DIGITS$.1}s] ")
+    } else {
+        let max_whole = 10u64.pow(DIGITS as u32) - 1;
+        format!("[{:0DIGITS$}s] ", since_created.as_secs().min(max_whole))
+    }
+}
+
+/// How many primary bytes to buffer between renders if not otherwise configured.
+const DEFAULT_PRIMARY_FLUSH_THRESHOLD: usize = 1024 * 1024;
+
+/// How many bytes each inactive primary output holds on to if not otherwise configured.
+const DEFAULT_PRIMARY_HOLD_BUDGET: usize = 1024 * 1024;
+
+/// Shown (dimmed) once the primary output has finished.
+const EOF_INDICATOR: &str = "[done]";
+
+/// How long to mark an output which rang its bell if not otherwise configured.
+const DEFAULT_BELL_INDICATOR_DURATION: Duration = Duration::from_secs(3);
+
+/// How many characters the number in each line's timestamp takes up, eg "012.3", or "01234" once
+/// there's no room for tenths.
+const LINE_TIMESTAMP_DIGITS: usize = 5;
+/// The width of the timestamp shown before each line when enabled, eg "[012.3s] ".
+const LINE_TIMESTAMP_WIDTH: u16 = LINE_TIMESTAMP_DIGITS as u16 + "[s] ".len() as u16;
+
+const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// How much each output can hold on to before it's been shown, if not otherwise configured.
+const DEFAULT_SECONDARY_OUTPUT_BYTE_BUDGET: usize = 256 * 1024;
+
+/// How many lines of each expanded output to show if not otherwise configured.
+const DEFAULT_SECONDARY_OUTPUT_MAX_LINES: usize = 10;
+
+/// Configuration for creating a [State], eg from the environment.
+#[derive(Clone)]
+pub struct StateBuilder {
+    secondary_output_max_lines: usize,
+    strip_secondary_colors: bool,
+    symbols: Symbols,
+    virtual_list_viewport: Option<u16>,
+    render_backend: RenderBackend,
+    duration_style: DurationStyle,
+    clock: Clock,
+}
+
+/// Where a [State] gets the current time from.
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+impl std::fmt::Debug for StateBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StateBuilder")
+            .field(
+                "secondary_output_max_lines",
+                &self.secondary_output_max_lines,
+            )
+            .field("strip_secondary_colors", &self.strip_secondary_colors)
+            .field("symbols", &self.symbols)
+            .field("virtual_list_viewport", &self.virtual_list_viewport)
+            .field("render_backend", &self.render_backend)
+            .field("duration_style", &self.duration_style)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for StateBuilder {
+    fn default() -> Self {
+        Self {
+            secondary_output_max_lines: DEFAULT_SECONDARY_OUTPUT_MAX_LINES,
+            strip_secondary_colors: false,
+            symbols: Symbols::default(),
+            virtual_list_viewport: None,
+            render_backend: RenderBackend::default(),
+            duration_style: DurationStyle::default(),
+            clock: Arc::new(Instant::now),
+        }
+    }
+}
+
+impl StateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads configuration from the environment:
+    /// - `MULTI_OUTPUT_VIEWER_MAX_LINES`: how many lines of each expanded output to show
+    /// - `MULTI_OUTPUT_VIEWER_NO_COLOR`: strip colors from secondary outputs
+    /// - `MULTI_OUTPUT_VIEWER_NO_UNICODE`: only use ASCII symbols, also implied by `TERM=dumb`
+    /// - `MULTI_OUTPUT_VIEWER_MAX_SECONDARY`: how many outputs to show at once
+    ///
+    /// Malformed values are logged and ignored.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut builder = Self::new().symbols(Symbols::for_term(var("TERM").as_deref()));
+        if let Some(max_lines) = parse_var(&var, "MULTI_OUTPUT_VIEWER_MAX_LINES", parse_number) {
+            builder = builder.secondary_output_max_lines(max_lines);
+        }
+        if let Some(no_color) = parse_var(&var, "MULTI_OUTPUT_VIEWER_NO_COLOR", parse_flag) {
+            builder = builder.strip_secondary_colors(no_color);
+        }
+        if let Some(true) = parse_var(&var, "MULTI_OUTPUT_VIEWER_NO_UNICODE", parse_flag) {
+            builder = builder.symbols(Symbols::ASCII);
+        }
+        if let Some(max_secondary) =
+            parse_var(&var, "MULTI_OUTPUT_VIEWER_MAX_SECONDARY", parse_number)
+        {
+            builder = builder.virtual_list_viewport(max_secondary);
+        }
+        builder
+    }
+
+    /// Works out whether the terminal supports colors and Unicode, from `NO_COLOR`, `TERM`,
+    /// `COLORTERM`, and whether it's in raw mode.
+    pub fn detect_terminal() -> Result<Self> {
+        let raw_mode = crossterm::terminal::is_raw_mode_enabled()?;
+        Ok(Self::detect_from_vars(
+            |name| std::env::var(name).ok(),
+            raw_mode,
+        ))
+    }
+
+    fn detect_from_vars(var: impl Fn(&str) -> Option<String>, raw_mode: bool) -> Self {
+        let term = var("TERM");
+        let dumb = term.as_deref() == Some("dumb");
+        // https://no-color.org says any non-empty value disables color
+        let no_color = matches!(var("NO_COLOR"), Some(value) if !value.is_empty());
+        // Some terminals (eg on Windows) don't set `TERM`, but being in raw mode means we're
+        // talking to a terminal all the same.
+        let is_terminal = term.is_some() || var("COLORTERM").is_some() || raw_mode;
+        Self::new()
+            .strip_secondary_colors(no_color || dumb || !is_terminal)
+            .symbols(Symbols::for_term(term.as_deref()))
+    }
+
+    pub fn secondary_output_max_lines(mut self, max_lines: usize) -> Self {
+        self.secondary_output_max_lines = max_lines;
+        self
+    }
+
+    /// See [State::set_strip_secondary_colors].
+    pub fn strip_secondary_colors(mut self, strip: bool) -> Self {
+        self.strip_secondary_colors = strip;
+        self
+    }
+
+    /// Only use ASCII symbols when rendering, for terminals which can't show Unicode.
+    pub fn ascii_symbols(self, ascii: bool) -> Self {
+        self.symbols(if ascii {
+            Symbols::ASCII
+        } else {
+            Symbols::default()
+        })
+    }
+
+    fn symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// See [State::set_virtual_list_viewport].
+    pub fn virtual_list_viewport(mut self, visible_rows: u16) -> Self {
+        self.virtual_list_viewport = Some(visible_rows);
+        self
+    }
+
+    /// Which implementation to use for writing escape sequences, see [RenderBackend].
+    pub fn render_backend(mut self, render_backend: RenderBackend) -> Self {
+        self.render_backend = render_backend;
+        self
+    }
+
+    /// See [State::set_duration_style].
+    pub fn duration_style(mut self, style: DurationStyle) -> Self {
+        self.duration_style = style;
+        self
+    }
+
+    /// Where to get the current time from, rather than [Instant::now]. Lets tests control how
+    /// much time passes, eg for elapsed times.
+    pub fn clock(mut self, clock: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn build<W: Write>(self, output: &mut W) -> State<'_, W> {
+        let mut state = State::with_clock(output, self.secondary_output_max_lines, self.clock);
+        state.backend = self.render_backend.implementation();
+        state.symbols = self.symbols;
+        state.set_duration_style(self.duration_style);
+        state.set_strip_secondary_colors(self.strip_secondary_colors);
+        if let Some(visible_rows) = self.virtual_list_viewport {
+            state.set_virtual_list_viewport(visible_rows);
+        }
+        state
+    }
+}
+
+fn parse_var<T>(
+    var: impl Fn(&str) -> Option<String>,
+    name: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Option<T> {
+    let value = var(name)?;
+    let parsed = parse(value.trim());
+    if parsed.is_none() {
+        log::warn!("Ignoring malformed value for {name}: {value:?}");
+    }
+    parsed
 }
 
-impl SecondaryOutputState {
-    fn handle_bytes(&mut self, bytes: &[u8]) {
-        self.buffer.process(bytes);
+fn parse_number<T: std::str::FromStr>(value: &str) -> Option<T> {
+    value.parse().ok()
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "" | "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
     }
 }
 
+/// What goes to the left of a content row when an output marks its named ranges.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum RangeBorder {
+    /// The row is in at least one of the ranges, so gets [Symbols::range_border].
+    InRange,
+    /// A blank column, keeping the row lined up with the ones in ranges.
+    OutOfRange,
+}
+
+/// A secondary output's header, laid out for a particular width.
+struct Header {
+    cursor: &'static str,
+    indicator: &'static str,
+    indicator_color: Color,
+    /// Everything after the indicator.
+    text: String,
+    /// Text which should start at a particular column, eg a right aligned elapsed time.
+    aligned: Option<(usize, String)>,
+}
+
+/// One of the primary outputs, see [State::new_primary_output].
+struct PrimaryOutput {
+    id: PrimaryOutputId,
+    title: Option<String>,
+    /// Bytes handled while this wasn't the active primary output, to be written out once it is.
+    /// Limited to [State::set_primary_hold_budget], dropping the oldest.
+    held_bytes: Vec<u8>,
+    /// Each output has its own parser, so one output's partial escape sequence or UTF-8
+    /// character isn't continued by another's bytes.
+    parser: VteActionParser,
+}
+
 pub struct State<'a, W: Write> {
     output: &'a mut W,
+    backend: &'static dyn Backend,
 
+    /// Every primary output, starting with the one the state was created with.
+    primary_outputs: Vec<PrimaryOutput>,
+    primary_output_next_id: PrimaryOutputId,
+    /// The primary output whose bytes are written out rather than held on to.
+    active_primary_output: PrimaryOutputId,
     primary_bytes: Vec<u8>,
-    primary_output_parser: VteActionParser,
+    primary_flush_threshold: usize,
+    /// How many bytes each inactive primary output holds on to, see
+    /// [State::set_primary_hold_budget].
+    primary_hold_budget: usize,
+    /// An error from writing outside of [State::render], to be returned by the next render.
+    pending_write_error: Option<anyhow::Error>,
     /// Tracks how far from the left and bottom (respectively) of the output the cursor is.
     primary_output_final_cursor_offset: (u16, u16),
+    /// The last window title set by the primary output.
+    primary_title: Option<String>,
+    /// The (1-based) top and bottom rows of the primary output's scroll region, if it set one. A
+    /// bottom of 0 means the bottom of the terminal.
+    primary_scroll_region: Option<(u16, u16)>,
+    /// Where tabs in the primary output stop.
+    primary_tab_stops: TabStops,
+    /// How many sequences in the primary output weren't understood (or were deliberately ignored)
+    /// when tracking the cursor.
+    ignored_sequence_count: usize,
+    /// Whether the primary output has finished, so no more bytes are expected from it.
+    primary_eof: bool,
+    eof_indicator_position: EofIndicatorPosition,
+    eof_indicator_shown: bool,
+    /// Whether the primary output wants the cursor shown.
+    primary_cursor_visible: bool,
+    /// Which mouse events the primary output wants reported to it.
+    primary_mouse_modes: MouseModes,
+    /// The styling the primary output has turned on, which drawing the secondary outputs resets.
+    primary_style: SgrState,
+    /// Whether mouse events are being reported to us, see [State::set_mouse_capture].
+    mouse_capture: bool,
+    /// Everything handled from the primary output since logging was enabled, and when.
+    #[cfg(feature = "asciicast")]
+    primary_byte_log: Option<crate::asciicast::ByteLog>,
+    /// Queries from the primary output which haven't been taken to be answered yet.
+    pending_primary_queries: Vec<PrimaryQuery>,
 
+    /// How much of what's sent to each output can be held on to before it's been shown, see
+    /// [State::set_secondary_output_byte_budget].
+    secondary_output_byte_budget: usize,
     secondary_output_max_lines: usize,
     secondary_output_next_id: SecondaryOutputId,
     secondary_output_reference_start_time: Instant,
+    /// Where the time comes from, so it can be controlled in tests.
+    clock: Clock,
     secondary_outputs: Vec<SecondaryOutputState>,
     secondary_output_selected_index: usize,
+    last_move_was_clamped: bool,
+    /// The max number of outputs to render at once, if set, along with the index of the first one
+    /// currently shown.
+    secondary_output_viewport: Option<u16>,
+    secondary_output_viewport_start: usize,
+
+    /// The size of the terminal (columns, rows), if known.
+    terminal_size: Option<(u16, u16)>,
+    /// How wide outputs' screens are, once the terminal has been resized.
+    secondary_screen_cols: Option<u16>,
+    strip_secondary_colors: bool,
+    show_elapsed: bool,
+    elapsed_precision: ElapsedPrecision,
+    duration_style: DurationStyle,
+    search_case_insensitive: bool,
+    /// Whether to show when each line arrived, for outputs in [SecondaryMode::PlainLines].
+    line_timestamps: bool,
+    elapsed_right_align: bool,
+    symbols: Symbols,
+
+    /// Whether to ring the real terminal's bell when a secondary output rings its bell.
+    forward_bell: bool,
+    /// Whether to show a line summarizing the outputs below them.
+    summary_enabled: bool,
+    /// Extra text to show at the end of the summary line.
+    status_text: Option<String>,
+    /// Whether any outputs have been created, since the summary isn't shown until then.
+    had_secondary_outputs: bool,
+    /// Whether to hide the cursor while rendering secondary outputs.
+    hide_cursor_while_rendering: bool,
+    /// Whether a secondary output has rang its bell since the last render.
+    bell_pending: bool,
+    /// How long to mark an output which rang its bell.
+    bell_indicator_duration: Duration,
+    stall_threshold: Duration,
+    /// Whether bookmarks are kept when an output's buffer is cleared.
+    persist_bookmarks_on_clear: bool,
 
     previous_render_extra_lines: u16,
+    /// Whether the last render moved the cursor back to where the primary output left it, rather
+    /// than leaving it just below what it drew.
+    cursor_parked: bool,
+    /// Where the last render drew each output, and how many lines it laid out in all to check
+    /// `previous_render_extra_lines` against.
+    last_frame_layout: FrameLayout,
+    render_count: u64,
+    /// When each render in the last second happened, for working out the frame rate.
+    recent_render_times: VecDeque<Instant>,
+    debug_overlay_visible: bool,
+    /// Everything time-dependent that was showing as of the last [State::tick].
+    last_tick_time_visuals: Vec<String>,
+    event_handler: Option<Box<dyn FnMut(StateEvent) + Send>>,
+    /// Outputs created with [State::new_named_secondary_output].
+    name_to_id: HashMap<String, SecondaryOutputId>,
+    /// How many lines the combined tail shows, if it's shown.
+    combined_tail: Option<usize>,
+    /// The most recent lines across all outputs, in the order they were completed, along with the
+    /// title of the output each one came from.
+    combined_lines: VecDeque<(String, String)>,
+    /// The selected output as of the last [StateEvent::SelectionChanged].
+    last_selected_id: Option<SecondaryOutputId>,
 }
 
 impl<'a, W: Write> State<'a, W> {
     pub fn new(output: &'a mut W, secondary_output_max_lines: usize) -> Self {
+        Self::with_clock(output, secondary_output_max_lines, Arc::new(Instant::now))
+    }
+
+    fn with_clock(output: &'a mut W, secondary_output_max_lines: usize, clock: Clock) -> Self {
+        let mut primary_output_next_id = PrimaryOutputId::default();
+        let first_primary_output = PrimaryOutput {
+            id: primary_output_next_id.next_id(),
+            title: None,
+            held_bytes: Vec::new(),
+            parser: VteActionParser::new(),
+        };
         Self {
             output,
+            backend: RenderBackend::default().implementation(),
+            active_primary_output: first_primary_output.id,
+            primary_outputs: vec![first_primary_output],
+            primary_output_next_id,
             primary_bytes: Vec::new(),
-            primary_output_parser: VteActionParser::new(),
+            primary_flush_threshold: DEFAULT_PRIMARY_FLUSH_THRESHOLD,
+            primary_hold_budget: DEFAULT_PRIMARY_HOLD_BUDGET,
+            pending_write_error: None,
             primary_output_final_cursor_offset: (0, 0),
+            primary_title: None,
+            primary_scroll_region: None,
+            primary_tab_stops: TabStops::default(),
+            ignored_sequence_count: 0,
+            primary_eof: false,
+            eof_indicator_position: EofIndicatorPosition::default(),
+            eof_indicator_shown: false,
+            primary_cursor_visible: true,
+            primary_mouse_modes: MouseModes::default(),
+            primary_style: SgrState::default(),
+            mouse_capture: false,
+            #[cfg(feature = "asciicast")]
+            primary_byte_log: None,
+            pending_primary_queries: Vec::new(),
+            secondary_output_byte_budget: DEFAULT_SECONDARY_OUTPUT_BYTE_BUDGET,
             secondary_output_max_lines,
             secondary_output_next_id: Default::default(),
-            secondary_output_reference_start_time: Instant::now(),
+            secondary_output_reference_start_time: clock(),
+            clock,
             secondary_outputs: Vec::new(),
             secondary_output_selected_index: 0,
+            last_move_was_clamped: false,
+            secondary_output_viewport: None,
+            secondary_output_viewport_start: 0,
+            terminal_size: None,
+            secondary_screen_cols: None,
+            strip_secondary_colors: false,
+            show_elapsed: true,
+            elapsed_precision: ElapsedPrecision::Seconds,
+            duration_style: DurationStyle::default(),
+            search_case_insensitive: false,
+            line_timestamps: false,
+            elapsed_right_align: false,
+            symbols: Symbols::default(),
+            forward_bell: false,
+            hide_cursor_while_rendering: false,
+            summary_enabled: false,
+            status_text: None,
+            had_secondary_outputs: false,
+            bell_pending: false,
+            bell_indicator_duration: DEFAULT_BELL_INDICATOR_DURATION,
+            stall_threshold: DEFAULT_STALL_THRESHOLD,
+            persist_bookmarks_on_clear: false,
             previous_render_extra_lines: 0,
+            cursor_parked: false,
+            last_frame_layout: FrameLayout::default(),
+            render_count: 0,
+            recent_render_times: VecDeque::new(),
+            debug_overlay_visible: false,
+            last_tick_time_visuals: Vec::new(),
+            event_handler: None,
+            name_to_id: HashMap::new(),
+            combined_tail: None,
+            combined_lines: VecDeque::new(),
+            last_selected_id: None,
         }
     }
 
-    pub fn render(&mut self) -> Result<()> {
-        // Reset if necessary
-        let (mut x, mut y) = self.primary_output_final_cursor_offset;
-        if self.previous_render_extra_lines > 0 {
-            queue!(
-                self.output,
-                MoveToColumn(0),
-                MoveUp(self.previous_render_extra_lines),
-                Clear(FromCursorDown),
-                MoveUp(y + 1),
-                MoveRight(x),
-            )?;
+    /// Configures a state from environment variables, for CLI tools which wrap this. See
+    /// [StateBuilder::from_env] for the variables which are read.
+    pub fn from_env(output: &'a mut W) -> Result<Self> {
+        Ok(StateBuilder::from_env().build(output))
+    }
+
+    /// Zero-config constructor which turns off colors and Unicode if the terminal doesn't seem to
+    /// support them. See [StateBuilder::detect_terminal].
+    pub fn with_terminal_detection(output: &'a mut W) -> Result<Self> {
+        Ok(StateBuilder::detect_terminal()?.build(output))
+    }
+
+    /// Moves everything over to write to `output` instead, eg after reconnecting to a remote
+    /// session. The new terminal won't have anything from the old one, so the next render draws
+    /// the outputs from scratch rather than clearing what was there before. Any error from writing
+    /// to the old output is dropped.
+    pub fn replace_writer<'b, W2: Write>(self, output: &'b mut W2) -> State<'b, W2> {
+        // Listing every field (rather than using `..`) makes sure new ones get carried over
+        let State {
+            output: _,
+            backend,
+            primary_outputs,
+            primary_output_next_id,
+            active_primary_output,
+            primary_bytes,
+            primary_flush_threshold,
+            primary_hold_budget,
+            pending_write_error: _,
+            primary_output_final_cursor_offset,
+            primary_title,
+            primary_scroll_region,
+            primary_tab_stops,
+            ignored_sequence_count,
+            primary_eof,
+            eof_indicator_position,
+            eof_indicator_shown,
+            primary_cursor_visible,
+            primary_mouse_modes,
+            primary_style,
+            mouse_capture,
+            #[cfg(feature = "asciicast")]
+            primary_byte_log,
+            pending_primary_queries,
+            secondary_output_byte_budget,
+            secondary_output_max_lines,
+            secondary_output_next_id,
+            secondary_output_reference_start_time,
+            clock,
+            secondary_outputs,
+            secondary_output_selected_index,
+            last_move_was_clamped,
+            secondary_output_viewport,
+            secondary_output_viewport_start,
+            terminal_size,
+            secondary_screen_cols,
+            strip_secondary_colors,
+            show_elapsed,
+            elapsed_precision,
+            duration_style,
+            search_case_insensitive,
+            line_timestamps,
+            elapsed_right_align,
+            symbols,
+            forward_bell,
+            summary_enabled,
+            status_text,
+            had_secondary_outputs,
+            hide_cursor_while_rendering,
+            bell_pending,
+            bell_indicator_duration,
+            stall_threshold,
+            persist_bookmarks_on_clear,
+            previous_render_extra_lines: _,
+            cursor_parked: _,
+            last_frame_layout: _,
+            render_count,
+            recent_render_times,
+            debug_overlay_visible,
+            last_tick_time_visuals,
+            event_handler,
+            name_to_id,
+            combined_tail,
+            combined_lines,
+            last_selected_id,
+        } = self;
+        State {
+            output,
+            pending_write_error: None,
+            previous_render_extra_lines: 0,
+            cursor_parked: false,
+            last_frame_layout: FrameLayout::default(),
+            backend,
+            primary_outputs,
+            primary_output_next_id,
+            active_primary_output,
+            primary_bytes,
+            primary_flush_threshold,
+            primary_hold_budget,
+            primary_output_final_cursor_offset,
+            primary_title,
+            primary_scroll_region,
+            primary_tab_stops,
+            ignored_sequence_count,
+            primary_eof,
+            eof_indicator_position,
+            eof_indicator_shown,
+            primary_cursor_visible,
+            primary_mouse_modes,
+            primary_style,
+            mouse_capture,
+            #[cfg(feature = "asciicast")]
+            primary_byte_log,
+            pending_primary_queries,
+            secondary_output_byte_budget,
+            secondary_output_max_lines,
+            secondary_output_next_id,
+            secondary_output_reference_start_time,
+            clock,
+            secondary_outputs,
+            secondary_output_selected_index,
+            last_move_was_clamped,
+            secondary_output_viewport,
+            secondary_output_viewport_start,
+            terminal_size,
+            secondary_screen_cols,
+            strip_secondary_colors,
+            show_elapsed,
+            elapsed_precision,
+            duration_style,
+            search_case_insensitive,
+            line_timestamps,
+            elapsed_right_align,
+            symbols,
+            forward_bell,
+            summary_enabled,
+            status_text,
+            had_secondary_outputs,
+            hide_cursor_while_rendering,
+            bell_pending,
+            bell_indicator_duration,
+            stall_threshold,
+            persist_bookmarks_on_clear,
+            render_count,
+            recent_render_times,
+            debug_overlay_visible,
+            last_tick_time_visuals,
+            event_handler,
+            name_to_id,
+            combined_tail,
+            combined_lines,
+            last_selected_id,
         }
+    }
 
-        // Write out any pending primary bytes, update internal state tracking
-        self.output.write_all(&self.primary_bytes)?;
-        for action in self.primary_output_parser.parse_bytes(&self.primary_bytes) {
-            match action {
-                VteAction::Text(_) => x += 1,
-                VteAction::Tab => x += 8 - (x % 8),
-                VteAction::LineFeed => y = y.saturating_sub(1),
-                VteAction::CarriageReturn => x = 0,
-                VteAction::CursorUp(n) => y += n,
-                VteAction::CursorDown(n) => y = y.saturating_sub(n),
-                VteAction::CursorForward(n) => x += n,
-                VteAction::CursorBackward(n) => x = x.saturating_sub(n),
-                VteAction::CursorNextLine(n) => {
-                    y = y.saturating_sub(n);
-                    x = 0;
-                }
-                VteAction::CursorPreviousLine(n) => {
-                    y += n;
-                    x = 0;
-                }
-            }
+    pub fn render(&mut self) -> Result<()> {
+        self.notify_stalled_outputs();
+        if let Some(error) = self.pending_write_error.take() {
+            return Err(error);
         }
-        self.primary_output_final_cursor_offset = (x, y);
-        self.primary_bytes.clear();
+        self.clear_secondary_outputs()?;
+        // Captured for the debug overlay, since writing the bytes out clears them
+        let pending_bytes = self.primary_bytes.len();
+        self.write_pending_primary_bytes()?;
+        if self.primary_eof && !self.eof_indicator_shown {
+            self.eof_indicator_shown = true;
+            self.write_eof_indicator()?;
+        }
+        // Only renders which get as far as drawing count
+        self.render_count += 1;
+        let render_time = self.now();
+        while matches!(self.recent_render_times.front(), Some(time) if render_time.duration_since(*time) >= Duration::from_secs(1))
+        {
+            self.recent_render_times.pop_front();
+        }
+        self.recent_render_times.push_back(render_time);
+        let (_, y) = self.primary_output_final_cursor_offset;
 
         // Write out any secondary output
-        self.previous_render_extra_lines = 0;
-        if !self.secondary_outputs.is_empty() {
-            queue!(self.output, MoveToColumn(0), MoveDown(y + 1),)?;
-            let mut newline = || {
+        let backend = self.backend;
+        // Several bells between renders only need to ring once
+        if self.forward_bell && self.bell_pending {
+            backend.print(self.output, "\x07")?;
+        }
+        self.bell_pending = false;
+        let hide_cursor = self.hide_cursor_while_rendering && !self.secondary_outputs.is_empty();
+        if hide_cursor {
+            backend.hide_cursor(self.output)?;
+        }
+        let summary = self.summary_line(self.terminal_size.map(|(cols, _)| cols));
+        let combined_tail = self.combined_tail_lines(self.terminal_size.map(|(cols, _)| cols));
+        let mut frame_rows = combined_tail.len() + usize::from(summary.is_some());
+        let mut frame_outputs = Vec::new();
+        if !self.secondary_outputs.is_empty() || !combined_tail.is_empty() || summary.is_some() {
+            // Use newlines rather than moving the cursor down so the terminal scrolls if the
+            // primary output is at the bottom of the screen.
+            backend.print(self.output, &"\r\n".repeat(y as usize + 1))?;
+            let visible_indices = self.update_secondary_output_viewport();
+            frame_rows += visible_indices.len();
+            let now = self.now();
+            let cols = self.terminal_size.map(|(cols, _)| cols);
+            for i in visible_indices {
+                let secondary_state = &self.secondary_outputs[i];
+                let header = self.layout_header(
+                    i == self.secondary_output_selected_index,
+                    secondary_state,
+                    now,
+                    self.terminal_size.map(|(cols, _)| cols),
+                );
+                let header_row = usize::from(self.previous_render_extra_lines);
+                // Don't let any styling from the primary output bleed into the header
+                backend.reset_style(self.output)?;
+                backend.print(self.output, header.cursor)?;
+                backend.print_styled(self.output, header.indicator, header.indicator_color)?;
+                backend.print(self.output, &header.text)?;
+                if let Some((column, aligned_text)) = &header.aligned {
+                    backend.move_to_column(self.output, *column as u16)?;
+                    backend.print(self.output, aligned_text)?;
+                }
+                backend.print(self.output, "\r\n")?;
                 self.previous_render_extra_lines += 1;
-                Print("\r\n")
-            };
-            let now = Instant::now();
-            for (i, secondary_state) in self.secondary_outputs.iter().enumerate() {
-                let num_seconds = (now - secondary_state.start).as_secs();
-                let cursor = if i == self.secondary_output_selected_index {
-                    "> "
-                } else {
-                    "  "
-                };
-                let expanded_indicator = if secondary_state.expanded {
-                    "+++".with(Color::Yellow)
-                } else {
-                    "---".with(Color::Green)
-                };
-                queue!(
-                    self.output,
-                    Print(cursor),
-                    PrintStyledContent(expanded_indicator),
-                    Print(format!(" {num_seconds: >3}s {}", secondary_state.title)),
-                    newline()
-                )?;
-                if secondary_state.expanded {
-                    let rows = secondary_state
-                        .buffer
-                        .screen()
-                        .rows_formatted(0, u16::MAX)
-                        .collect::<Vec<_>>();
-
-                    let (cursor_row, cursor_col) =
-                        secondary_state.buffer.screen().cursor_position();
-                    // If we're at the beginning of the row, assume trailing newline, remove it
-                    let cursor_row =
-                        (cursor_row as usize).saturating_sub(if cursor_col == 0 { 1 } else { 0 });
-                    // Probably don't technically need this since we're nominally not handling
-                    // terminal control sequences
-                    let last_non_empty_row = rows.iter().rposition(|row| !row.is_empty());
-                    let end_idx = max(cursor_row, last_non_empty_row.unwrap_or(0));
-                    if end_idx > 0 {
-                        let start_idx = end_idx.saturating_sub(self.secondary_output_max_lines - 1);
-                        for row in &rows[start_idx..=end_idx] {
-                            self.output.write_all(row)?;
-                            queue!(self.output, newline())?;
+                // A max of 0 lines means only ever show the headers
+                if secondary_state.expanded() && self.secondary_output_max_lines > 0 {
+                    let rows = secondary_state.content_rows(
+                        self.content_width(secondary_state, cols),
+                        self.strip_secondary_colors,
+                        self.symbols.ellipsis,
+                    );
+                    let visible_rows = self.visible_content_range(secondary_state, rows.len());
+                    frame_rows += visible_rows.len();
+                    let scrollbar = self.scrollbar(secondary_state, cols, rows.len());
+                    let timestamps = self.line_timestamps(secondary_state);
+                    for (j, row) in rows[visible_rows.clone()].iter().enumerate() {
+                        match self.range_border(secondary_state, visible_rows.start + j) {
+                            Some(RangeBorder::InRange) => backend.print_styled(
+                                self.output,
+                                self.symbols.range_border,
+                                Color::Cyan,
+                            )?,
+                            Some(RangeBorder::OutOfRange) => backend.print(self.output, " ")?,
+                            None => {}
+                        }
+                        if let Some(timestamps) = &timestamps {
+                            backend.print_styled(
+                                self.output,
+                                &timestamps[visible_rows.start + j],
+                                Color::DarkGrey,
+                            )?;
+                        }
+                        match &secondary_state.highlight {
+                            Some(highlight) => self.output.write_all(&search::highlight_row(
+                                row,
+                                &search::find_matches(
+                                    &search::plain_text(row),
+                                    highlight,
+                                    self.search_case_insensitive,
+                                ),
+                            ))?,
+                            None => self.output.write_all(row)?,
+                        }
+                        // Rows end with whatever styling was active at the end of the line. The
+                        // primary output's own styling is turned back on once everything's drawn.
+                        backend.reset_style(self.output)?;
+                        if let (Some(scrollbar), Some(cols)) = (&scrollbar, cols) {
+                            backend.move_to_column(self.output, cols - 1)?;
+                            backend.print(self.output, scrollbar[j])?;
                         }
+                        backend.print(self.output, "\r\n")?;
+                        self.previous_render_extra_lines += 1;
                     }
                 }
+                frame_outputs.push(OutputLayout {
+                    id: secondary_state.id,
+                    header_row,
+                    content_rows: header_row + 1..usize::from(self.previous_render_extra_lines),
+                });
+            }
+            for (prefix, line) in &combined_tail {
+                backend.reset_style(self.output)?;
+                backend.print_styled(self.output, prefix, Color::DarkGrey)?;
+                backend.print(self.output, line)?;
+                backend.print(self.output, "\r\n")?;
+                self.previous_render_extra_lines += 1;
+            }
+            if let Some(summary) = summary {
+                backend.reset_style(self.output)?;
+                backend.print_styled(self.output, &summary, Color::DarkGrey)?;
+                backend.print(self.output, "\r\n")?;
+                self.previous_render_extra_lines += 1;
             }
         }
+        self.park_cursor()?;
+        if self.debug_overlay_visible {
+            self.write_debug_overlay(pending_bytes)?;
+        }
+        // Everything drawn above ends by resetting the styling
+        if self.previous_render_extra_lines > 0 || self.debug_overlay_visible {
+            self.restore_primary_style()?;
+        }
+        // Only show the cursor again if the primary output hasn't hidden it itself
+        if hide_cursor && self.primary_cursor_visible {
+            backend.show_cursor(self.output)?;
+        }
+        self.last_frame_layout = FrameLayout {
+            outputs: frame_outputs,
+            extra_lines: frame_rows,
+        };
 
-        self.output.flush()?;
+        backend.flush(self.output)?;
+        #[cfg(debug_assertions)]
+        {
+            let violations = self.validate();
+            assert!(
+                violations.is_empty(),
+                "Invalid state after rendering: {violations:?}\n{}",
+                self.debug_dump()
+            );
+        }
         Ok(())
     }
 
-    pub fn handle_primary_bytes(&mut self, bytes: &[u8]) -> &mut Self {
-        self.primary_bytes.extend(bytes);
-        self
+    /// Clears the whole screen and draws everything again from the top, for when the terminal has
+    /// been garbled, eg by another program writing to it. Only primary output which hasn't been
+    /// written yet can be drawn again, so anything from before is lost.
+    pub fn force_repaint(&mut self) -> Result<()> {
+        self.backend.clear_all(self.output)?;
+        self.backend.move_to(self.output, 0, 0)?;
+        self.previous_render_extra_lines = 0;
+        self.last_frame_layout = FrameLayout::default();
+        self.primary_output_final_cursor_offset = (0, 0);
+        self.render()
     }
 
-    pub fn new_secondary_output(&mut self, title: String) -> SecondaryOutputId {
-        // Align start time to the reference start time so different outputs tick to the next
-        // second together.
-        let seconds_since_reference =
-            (Instant::now() - self.secondary_output_reference_start_time).as_secs();
-        let start = self.secondary_output_reference_start_time
-            + Duration::from_secs(seconds_since_reference);
-        let id = self.secondary_output_next_id.next_id();
-        self.secondary_outputs.push(SecondaryOutputState {
-            id,
-            title,
-            start,
-            expanded: false,
-            buffer: vt100::Parser::new(50, 50, self.secondary_output_max_lines * 3),
-        });
-        id
+    /// Writes the indicator after the primary output, which then stays there as if the primary
+    /// output had written it.
+    fn write_eof_indicator(&mut self) -> Result<()> {
+        let (x, y) = self.primary_output_final_cursor_offset;
+        let width = EOF_INDICATOR.len() as u16;
+        let fits_on_line = match self.terminal_size {
+            Some((cols, _)) => x + 1 + width <= cols,
+            None => true,
+        };
+        let (prefix, x, y) = match self.eof_indicator_position {
+            EofIndicatorPosition::Hidden => return Ok(()),
+            _ if x == 0 => ("", width, y),
+            EofIndicatorPosition::EndOfLine if fits_on_line => (" ", x + 1 + width, y),
+            _ => ("\r\n", width, y.saturating_sub(1)),
+        };
+        self.backend.print(self.output, prefix)?;
+        self.backend.print_dimmed(self.output, EOF_INDICATOR)?;
+        self.primary_output_final_cursor_offset = (x, y);
+        Ok(())
     }
 
-    fn secondary_output_position(&self, id: &SecondaryOutputId) -> Result<usize> {
-        self.secondary_outputs
-            .iter()
-            .position(|secondary_state| secondary_state.id == *id)
-            // TODO - Use thiserror
-            .ok_or_else(|| anyhow!("Invalid ID: {id:?}"))
+    /// Draws some stats about rendering (dimmed) over the top right corner of the screen, leaving
+    /// the cursor where it was. Nothing clears it away, so the primary output or the next overlay
+    /// has to draw over it. Does nothing unless the terminal size is known.
+    ///
+    /// Getting back to the cursor takes knowing where it is on the screen, so this assumes the
+    /// lowest thing drawn (the primary output, or the secondary outputs below it) is at the bottom
+    /// of the screen, as tracking the primary output's absolute rows does. Saving and restoring
+    /// the cursor instead would lose any position the primary output had saved itself.
+    ///
+    /// This happens automatically after each render once [State::set_debug_overlay_visible] is
+    /// enabled.
+    pub fn render_debug_overlay(&mut self) -> Result<()> {
+        self.write_debug_overlay(self.primary_bytes.len())?;
+        self.restore_primary_style()?;
+        self.backend.flush(self.output)?;
+        Ok(())
     }
 
-    pub fn remove_secondary_output(&mut self, id: SecondaryOutputId) -> Result<&mut Self> {
-        // Note: Should use `drain_filter` once/if that's stabilized
-        // https://github.com/rust-lang/rust/issues/43244
-        let idx = self.secondary_output_position(&id)?;
-        self.secondary_outputs.remove(idx);
-        if self.secondary_output_selected_index > idx {
-            self.secondary_output_selected_index -= 1;
-        }
-        Ok(self)
+    /// `pending_bytes` is how much of the primary output was waiting to be written, which render
+    /// has to capture before writing it.
+    fn write_debug_overlay(&mut self, pending_bytes: usize) -> Result<()> {
+        let Some((cols, rows)) = self.terminal_size else {
+            return Ok(());
+        };
+        let text = format!(
+            "FPS {} renders {} pending_bytes {} outputs_count {}",
+            self.recent_render_times.len(),
+            self.render_count,
+            pending_bytes,
+            self.secondary_outputs.len(),
+        );
+        let text = truncate_to_width(&text, cols as usize, self.symbols.ellipsis);
+        // Where the cursor is, as columns in and rows up from the bottom of the screen
+        let (x, y) = self.primary_output_final_cursor_offset;
+        let (column, rows_up) = if self.cursor_parked {
+            (
+                x,
+                self.previous_render_extra_lines
+                    .saturating_add(y)
+                    .saturating_add(1),
+            )
+        } else if self.previous_render_extra_lines > 0 {
+            // Just below the secondary outputs
+            (0, 0)
+        } else {
+            (x, y)
+        };
+        let backend = self.backend;
+        backend.move_to(self.output, cols - display_width(&text) as u16, 0)?;
+        backend.print_dimmed(self.output, &text)?;
+        backend.move_to(
+            self.output,
+            column,
+            rows.saturating_sub(1).saturating_sub(rows_up),
+        )?;
+        Ok(())
     }
 
-    pub fn handle_secondary_bytes(
-        &mut self,
-        id: &SecondaryOutputId,
-        bytes: &[u8],
-    ) -> Result<&mut Self> {
+    /// Whether to draw the overlay from [State::render_debug_overlay] after each render.
+    pub fn set_debug_overlay_visible(&mut self, visible: bool) -> &mut Self {
+        self.debug_overlay_visible = visible;
+        self
+    }
+
+    /// Lays out the header for a secondary output, fitting it within `cols` if known.
+    fn layout_header(
+        &self,
+        selected: bool,
+        secondary_state: &SecondaryOutputState,
+        now: Instant,
+        cols: Option<u16>,
+    ) -> Header {
+        let elapsed = self.secondary_output_elapsed(secondary_state, now);
+        let cursor = if selected { "> " } else { "  " };
+        let (indicator, indicator_color) = if secondary_state.expanded() {
+            (self.symbols.expanded, Color::Yellow)
+        } else {
+            (self.symbols.collapsed, Color::Green)
+        };
+        let title = &secondary_state.title;
+        let mut markers = if self.showing_bell(secondary_state, now) {
+            format!("{} ", self.symbols.bell)
+        } else {
+            String::new()
+        };
+        if secondary_state.timer.is_paused() {
+            markers.insert_str(0, &format!("{} ", self.symbols.paused));
+        }
+        if secondary_state.pinned {
+            markers.insert_str(0, &format!("{} ", self.symbols.pinned));
+        }
+        let time_bar = secondary_state
+            .time_bar_max_duration
+            .map_or_else(String::new, |max_duration| {
+                format_time_bar(elapsed, max_duration)
+            });
+        // Cursor and indicator
+        let prefix_width = 2 + 3;
+        // Right aligning needs to know how wide the terminal is, fall back to the default
+        // layout if we don't know.
+        let (text, aligned) = match (cols, self.show_elapsed, self.elapsed_right_align) {
+            (Some(cols), true, true) => {
+                let elapsed = self
+                    .elapsed_precision
+                    .format(self.duration_style, elapsed, false);
+                let elapsed_column = (cols as usize).saturating_sub(time_bar.len() + elapsed.len());
+                // Leave a space on either side of the title
+                let title = truncate_to_width(
+                    title,
+                    elapsed_column.saturating_sub(prefix_width + 2 + display_width(&markers)),
+                    self.symbols.ellipsis,
+                );
+                (
+                    format!(" {markers}{title}"),
+                    Some((elapsed_column, format!("{time_bar}{elapsed}"))),
+                )
+            }
+            (cols, show_elapsed, _) => {
+                let elapsed = if show_elapsed {
+                    format!(
+                        " {} ",
+                        self.elapsed_precision
+                            .format(self.duration_style, elapsed, true)
+                    )
+                } else {
+                    " ".to_string()
+                };
+                let title = match cols {
+                    Some(cols) => truncate_to_width(
+                        title,
+                        (cols as usize).saturating_sub(
+                            prefix_width + elapsed.len() + time_bar.len() + display_width(&markers),
+                        ),
+                        self.symbols.ellipsis,
+                    ),
+                    None => title.into(),
+                };
+                (format!("{elapsed}{time_bar}{markers}{title}"), None)
+            }
+        };
+        Header {
+            cursor,
+            indicator,
+            indicator_color,
+            text,
+            aligned,
+        }
+    }
+
+    /// Lays out the secondary outputs as styled lines, for drawing them some other way than
+    /// [State::render], eg as part of a TUI. Doesn't write anything, including pending primary
+    /// bytes.
+    pub fn render_frame(&mut self, width: u16) -> Frame {
+        let visible_indices = self.update_secondary_output_viewport();
+        self.layout_frame(visible_indices, width)
+    }
+
+    /// The secondary outputs as plain text, laid out like [State::render_frame] but without any
+    /// styling, one line per row. The primary output isn't included, and the state isn't changed
+    /// at all (eg pending primary bytes aren't written), so this can be called at any time.
+    pub fn render_plain(&self, width: u16) -> String {
+        let (_, visible_indices) = self.scrolled_secondary_output_viewport();
+        self.layout_frame(visible_indices, width)
+            .lines
+            .iter()
+            .map(Line::text)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn layout_frame(&self, visible_indices: Vec<usize>, width: u16) -> Frame {
+        let mut frame = Frame::default();
+        let now = self.now();
+        for i in visible_indices {
+            let secondary_state = &self.secondary_outputs[i];
+            let header = self.layout_header(
+                i == self.secondary_output_selected_index,
+                secondary_state,
+                now,
+                Some(width),
+            );
+            let mut line = Line::default();
+            line.push(header.cursor, Style::default());
+            line.push(
+                header.indicator,
+                frame::Color::from_crossterm(header.indicator_color)
+                    .map_or_else(Style::default, Style::foreground),
+            );
+            line.push(&header.text, Style::default());
+            if let Some((column, aligned_text)) = &header.aligned {
+                let padding = column.saturating_sub(display_width(&line.text()));
+                line.push(&" ".repeat(padding), Style::default());
+                line.push(aligned_text, Style::default());
+            }
+            frame.lines.push(line);
+            // A max of 0 lines means only ever show the headers
+            if secondary_state.expanded() && self.secondary_output_max_lines > 0 {
+                let content_width = self.content_width(secondary_state, Some(width));
+                let border_width = usize::from(secondary_state.show_named_ranges);
+                let mut lines = secondary_state.content_lines(
+                    content_width,
+                    self.strip_secondary_colors,
+                    self.symbols.ellipsis,
+                );
+                let scrollbar = self.scrollbar(secondary_state, Some(width), lines.len());
+                let visible_lines = self.visible_content_range(secondary_state, lines.len());
+                let first_visible_line = visible_lines.start;
+                let timestamps = self.line_timestamps(secondary_state);
+                let timestamp_width = if timestamps.is_some() {
+                    LINE_TIMESTAMP_WIDTH as usize
+                } else {
+                    0
+                };
+                for (j, mut line) in lines.drain(visible_lines).enumerate() {
+                    if let Some(highlight) = &secondary_state.highlight {
+                        let matches = search::find_matches(
+                            &line.text(),
+                            highlight,
+                            self.search_case_insensitive,
+                        );
+                        line = search::highlight_line(&line, &matches);
+                    }
+                    let border = self.range_border(secondary_state, first_visible_line + j);
+                    let timestamp = timestamps
+                        .as_ref()
+                        .map(|timestamps| &timestamps[first_visible_line + j]);
+                    if border.is_some() || timestamp.is_some() {
+                        let mut prefixed = Line::default();
+                        match border {
+                            Some(RangeBorder::InRange) => prefixed.push(
+                                self.symbols.range_border,
+                                frame::Color::from_crossterm(Color::Cyan)
+                                    .map_or_else(Style::default, Style::foreground),
+                            ),
+                            Some(RangeBorder::OutOfRange) => prefixed.push(" ", Style::default()),
+                            None => {}
+                        }
+                        if let Some(timestamp) = timestamp {
+                            prefixed.push(
+                                timestamp,
+                                frame::Color::from_crossterm(Color::DarkGrey)
+                                    .map_or_else(Style::default, Style::foreground),
+                            );
+                        }
+                        for span in line.spans {
+                            prefixed.push(&span.text, span.style);
+                        }
+                        line = prefixed;
+                    }
+                    if let Some(scrollbar) = &scrollbar {
+                        let padding = (content_width as usize + border_width + timestamp_width)
+                            .saturating_sub(display_width(&line.text()));
+                        line.push(&" ".repeat(padding), Style::default());
+                        line.push(scrollbar[j], Style::default());
+                    }
+                    frame.lines.push(line);
+                }
+            }
+        }
+        for (prefix, text) in self.combined_tail_lines(Some(width)) {
+            let mut line = Line::default();
+            line.push(
+                &prefix,
+                frame::Color::from_crossterm(Color::DarkGrey)
+                    .map_or_else(Style::default, Style::foreground),
+            );
+            line.push(&text, Style::default());
+            frame.lines.push(line);
+        }
+        if let Some(summary) = self.summary_line(Some(width)) {
+            let mut line = Line::default();
+            line.push(
+                &summary,
+                frame::Color::from_crossterm(Color::DarkGrey)
+                    .map_or_else(Style::default, Style::foreground),
+            );
+            frame.lines.push(line);
+        }
+        frame
+    }
+
+    /// The combined tail's lines, as the prefix saying which output each line came from and the
+    /// line itself, fitting within `cols` if known.
+    fn combined_tail_lines(&self, cols: Option<u16>) -> Vec<(String, String)> {
+        self.combined_lines
+            .iter()
+            .map(|(title, line)| {
+                let prefix = format!("{title}: ");
+                match cols {
+                    Some(cols) => {
+                        let prefix =
+                            truncate_to_width(&prefix, cols as usize, self.symbols.ellipsis)
+                                .into_owned();
+                        let line = truncate_to_width(
+                            line,
+                            (cols as usize).saturating_sub(display_width(&prefix)),
+                            self.symbols.ellipsis,
+                        )
+                        .into_owned();
+                        (prefix, line)
+                    }
+                    None => (prefix, line.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Shows the last `lines` lines from all of the outputs below them, interleaved in the order
+    /// they arrived (like `tail -f` on several files), each prefixed with the title of the output
+    /// it came from. Only lines which arrive while this is on are shown, and lines from outputs
+    /// which have since been removed are kept. `None` turns it off.
+    pub fn set_combined_tail(&mut self, lines: Option<usize>) -> &mut Self {
+        self.combined_tail = lines;
+        match lines {
+            Some(lines) => {
+                let excess = self.combined_lines.len().saturating_sub(lines);
+                self.combined_lines.drain(..excess);
+            }
+            None => {
+                self.combined_lines.clear();
+                for secondary_state in &mut self.secondary_outputs {
+                    secondary_state.line_splitter = None;
+                }
+            }
+        }
+        self
+    }
+
+    /// The line summarizing the outputs, eg "3 running · 1 failed · 12 done · 02:41 elapsed",
+    /// fitting within `cols` if known. `None` if it isn't shown.
+    fn summary_line(&self, cols: Option<u16>) -> Option<String> {
+        if !self.summary_enabled || !self.had_secondary_outputs {
+            return None;
+        }
+        let (mut running, mut failed, mut done) = (0, 0, 0);
+        for secondary_state in &self.secondary_outputs {
+            match secondary_state.status {
+                SecondaryOutputStatus::Running => running += 1,
+                status if status.failed() => failed += 1,
+                SecondaryOutputStatus::Finished { .. } => done += 1,
+            }
+        }
+        // Builds tend to run long enough that plain seconds are hard to read
+        let style = match self.duration_style {
+            DurationStyle::Seconds => DurationStyle::Clock,
+            style => style,
+        };
+        let elapsed = style.format(self.now() - self.secondary_output_reference_start_time);
+        let mut parts = [(running, "running"), (failed, "failed"), (done, "done")]
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{count} {label}"))
+            .collect::<Vec<_>>();
+        parts.push(format!("{elapsed} elapsed"));
+        parts.extend(self.status_text.clone());
+        let summary = parts.join(self.symbols.separator);
+        Some(match cols {
+            Some(cols) => {
+                truncate_to_width(&summary, cols as usize, self.symbols.ellipsis).into_owned()
+            }
+            None => summary,
+        })
+    }
+
+    /// Shows a line below the outputs with how many are running, have failed, and are done
+    /// (see [State::set_secondary_output_status]), along with how long it's been since the state
+    /// was created. Nothing is shown until the first output is created.
+    pub fn set_summary_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.summary_enabled = enabled;
+        self
+    }
+
+    /// Text to add to the end of the summary line (see [State::set_summary_enabled]).
+    pub fn set_status_text(&mut self, text: Option<String>) -> &mut Self {
+        self.status_text = text;
+        self
+    }
+
+    /// Records how the process behind the output is doing, eg for the summary line.
+    pub fn set_secondary_output_status(
+        &mut self,
+        id: &SecondaryOutputId,
+        status: SecondaryOutputStatus,
+    ) -> Result<&mut Self> {
         let idx = self.secondary_output_position(id)?;
-        self.secondary_outputs[idx].handle_bytes(bytes);
+        let was_running = self.secondary_outputs[idx].status == SecondaryOutputStatus::Running;
+        self.secondary_outputs[idx].status = status;
+        if was_running && status != SecondaryOutputStatus::Running {
+            self.emit(StateEvent::OutputFinished { id: *id, status });
+        }
         Ok(self)
     }
 
-    pub fn move_cursor_down(&mut self) -> &mut Self {
-        self.secondary_output_selected_index =
-            (self.secondary_output_selected_index + 1).min(self.secondary_outputs.len() - 1);
+    /// How the process behind the output is doing, as last set by
+    /// [State::set_secondary_output_status].
+    pub fn secondary_output_status(&self, id: &SecondaryOutputId) -> Result<SecondaryOutputStatus> {
+        Ok(self.secondary_outputs[self.secondary_output_position(id)?].status)
+    }
+
+    /// Which of an output's `num_rows` rows of content are shown when it's expanded, taking
+    /// scrolling into account.
+    fn visible_content_range(
+        &self,
+        secondary_state: &SecondaryOutputState,
+        num_rows: usize,
+    ) -> Range<usize> {
+        let max_scroll_offset = num_rows.saturating_sub(self.secondary_output_max_lines);
+        let end = num_rows - secondary_state.scroll_offset.min(max_scroll_offset);
+        end.saturating_sub(self.secondary_output_max_lines)..end
+    }
+
+    /// How wide an output's content is laid out when `cols` wide, leaving room for the scrollbar
+    /// if it has one. The scrollbar needs to know where the right edge is, so it's only shown when
+    /// the width is known.
+    fn content_width(&self, secondary_state: &SecondaryOutputState, cols: Option<u16>) -> u16 {
+        match cols {
+            Some(cols) => {
+                let timestamp_width = if self.line_timestamps && secondary_state.has_line_times() {
+                    LINE_TIMESTAMP_WIDTH
+                } else {
+                    0
+                };
+                cols.saturating_sub(u16::from(secondary_state.show_scrollbar))
+                    .saturating_sub(u16::from(secondary_state.show_named_ranges))
+                    .saturating_sub(timestamp_width)
+            }
+            None => u16::MAX,
+        }
+    }
+
+    /// What to show to the left of each content row, if showing when lines arrived. These are
+    /// relative to when the output was created.
+    fn line_timestamps(&self, secondary_state: &SecondaryOutputState) -> Option<Vec<String>> {
+        if !self.line_timestamps {
+            return None;
+        }
+        let line_times = secondary_state.line_times()?;
+        Some(
+            line_times
+                .into_iter()
+                .map(|time| {
+                    format_line_timestamp(time.saturating_duration_since(secondary_state.created))
+                })
+                .collect(),
+        )
+    }
+
+    /// What to show to the left of content row `row` if the output marks its named ranges, ie a
+    /// border if the row is in one of them.
+    fn range_border(
+        &self,
+        secondary_state: &SecondaryOutputState,
+        row: usize,
+    ) -> Option<RangeBorder> {
+        if !secondary_state.show_named_ranges {
+            return None;
+        }
+        let in_range = secondary_state
+            .named_ranges
+            .iter()
+            .any(|range| (range.start..range.end).contains(&row));
+        Some(if in_range {
+            RangeBorder::InRange
+        } else {
+            RangeBorder::OutOfRange
+        })
+    }
+
+    /// The scrollbar symbol for each visible row of an output's `num_rows` rows of content, if it
+    /// shows one. The gutter is left blank when all of the content fits.
+    fn scrollbar(
+        &self,
+        secondary_state: &SecondaryOutputState,
+        cols: Option<u16>,
+        num_rows: usize,
+    ) -> Option<Vec<&'static str>> {
+        if !secondary_state.show_scrollbar || cols.is_none() {
+            return None;
+        }
+        let visible_rows = self.visible_content_range(secondary_state, num_rows).len();
+        let scrollbar = match self.scroll_fraction(secondary_state, num_rows) {
+            Some(fraction) => {
+                // The thumb is at the bottom when showing the newest content
+                let thumb = ((1.0 - fraction) * visible_rows.saturating_sub(1) as f32).round();
+                (0..visible_rows)
+                    .map(|row| {
+                        if row == thumb as usize {
+                            self.symbols.scrollbar_thumb
+                        } else {
+                            self.symbols.scrollbar_track
+                        }
+                    })
+                    .collect()
+            }
+            None => vec![" "; visible_rows],
+        };
+        Some(scrollbar)
+    }
+
+    /// How many rows of content an output has, at the width it would be rendered at.
+    fn num_content_rows(&self, secondary_state: &SecondaryOutputState) -> usize {
+        secondary_state
+            .content_rows(
+                self.content_width(secondary_state, self.terminal_size.map(|(cols, _)| cols)),
+                self.strip_secondary_colors,
+                self.symbols.ellipsis,
+            )
+            .len()
+    }
+
+    /// Finds everywhere `needle` appears in the output's content, including rows which have
+    /// scrolled out of view. Rows are searched as they'd be rendered, so anything past the edge of
+    /// the terminal isn't found.
+    pub fn search_secondary_output(
+        &self,
+        id: &SecondaryOutputId,
+        needle: &str,
+    ) -> Result<Vec<MatchLocation>> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        let rows = secondary_state.content_rows(
+            self.content_width(secondary_state, self.terminal_size.map(|(cols, _)| cols)),
+            self.strip_secondary_colors,
+            self.symbols.ellipsis,
+        );
+        Ok(rows
+            .iter()
+            .enumerate()
+            .flat_map(|(row, content)| {
+                search::find_matches(
+                    &search::plain_text(content),
+                    needle,
+                    self.search_case_insensitive,
+                )
+                .into_iter()
+                .map(move |range| MatchLocation {
+                    row,
+                    start: range.start,
+                    end: range.end,
+                })
+            })
+            .collect())
+    }
+
+    /// Highlights everywhere the text appears in the output's expanded content, or stops
+    /// highlighting if `None`.
+    pub fn set_highlight(
+        &mut self,
+        id: &SecondaryOutputId,
+        highlight: Option<String>,
+    ) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].highlight = highlight.filter(|text| !text.is_empty());
+        Ok(self)
+    }
+
+    /// Whether searching and highlighting ignore case.
+    pub fn set_search_case_insensitive(&mut self, case_insensitive: bool) -> &mut Self {
+        self.search_case_insensitive = case_insensitive;
         self
     }
 
-    pub fn move_cursor_up(&mut self) -> &mut Self {
-        self.secondary_output_selected_index =
-            self.secondary_output_selected_index.saturating_sub(1);
+    /// Whether to show how long after each output was created that each of its lines arrived.
+    /// This only applies to outputs in [SecondaryMode::PlainLines], since a terminal can go back
+    /// and change earlier rows.
+    pub fn set_line_timestamps(&mut self, line_timestamps: bool) -> &mut Self {
+        self.line_timestamps = line_timestamps;
         self
     }
 
-    pub fn toggle_current_selection_expanded(&mut self) -> &mut Self {
-        if let Some(secondary_state) = self
-            .secondary_outputs
-            .get_mut(self.secondary_output_selected_index)
-        {
-            secondary_state.expanded = !secondary_state.expanded;
+    /// Whether there's content above what the output shows when expanded.
+    pub fn secondary_output_can_scroll_up(&self, id: &SecondaryOutputId) -> Result<bool> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        let visible_rows =
+            self.visible_content_range(secondary_state, self.num_content_rows(secondary_state));
+        Ok(visible_rows.start > 0)
+    }
+
+    /// Whether there's content below what the output shows when expanded, ie it's been scrolled
+    /// up.
+    pub fn secondary_output_can_scroll_down(&self, id: &SecondaryOutputId) -> Result<bool> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        let num_rows = self.num_content_rows(secondary_state);
+        let visible_rows = self.visible_content_range(secondary_state, num_rows);
+        Ok(visible_rows.end < num_rows)
+    }
+
+    /// How far the expanded view of the output is scrolled, from `0.0` when showing the newest
+    /// content to `1.0` when showing the oldest. `None` if all of the content fits, so there's
+    /// nothing to scroll.
+    pub fn secondary_output_scroll_percentage(
+        &self,
+        id: &SecondaryOutputId,
+    ) -> Result<Option<f32>> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        Ok(self.scroll_fraction(secondary_state, self.num_content_rows(secondary_state)))
+    }
+
+    /// See [State::secondary_output_scroll_percentage].
+    fn scroll_fraction(
+        &self,
+        secondary_state: &SecondaryOutputState,
+        num_rows: usize,
+    ) -> Option<f32> {
+        let max_scroll_offset = num_rows.saturating_sub(self.secondary_output_max_lines);
+        if max_scroll_offset == 0 {
+            return None;
         }
-        self
+        let visible_rows = self.visible_content_range(secondary_state, num_rows);
+        Some((num_rows - visible_rows.end) as f32 / max_scroll_offset as f32)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::state::State;
-    #[allow(unused_imports)] // IntelliJ gets confused here
-    use insta::{assert_snapshot, with_settings};
+    /// Scrolls the expanded view of the output up by `lines`, stopping at the top of the content.
+    pub fn scroll_secondary_output_up(
+        &mut self,
+        id: &SecondaryOutputId,
+        lines: usize,
+    ) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        let max_scroll_offset = self
+            .num_content_rows(&self.secondary_outputs[idx])
+            .saturating_sub(self.secondary_output_max_lines);
+        let secondary_state = &mut self.secondary_outputs[idx];
+        secondary_state.scroll_offset = secondary_state
+            .scroll_offset
+            .saturating_add(lines)
+            .min(max_scroll_offset);
+        Ok(self)
+    }
 
-    const TEST_SECONDARY_OUTPUT_MAX_LINES: usize = 3;
+    /// Scrolls the expanded view of the output back down by `lines`, towards the latest content.
+    pub fn scroll_secondary_output_down(
+        &mut self,
+        id: &SecondaryOutputId,
+        lines: usize,
+    ) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        let secondary_state = &mut self.secondary_outputs[idx];
+        secondary_state.scroll_offset = secondary_state.scroll_offset.saturating_sub(lines);
+        Ok(self)
+    }
 
-    macro_rules! assert_state_output {
-        ($f:expr) => {
-            let output = get_state_output($f);
-            with_settings!({
-                description => stringify!($f),
-                omit_expression => true
-            }, {
-                assert_snapshot!(format!("# Rendered:\n```\n{}\n```\n\n\n# Raw:\n```\n{}\n```", rasterize_output(&output), output));
-            });
+    /// Saves the output's current scroll position under `label`, returning the index to get back
+    /// to it with [State::secondary_output_goto_bookmark]. In [SecondaryMode::PlainLines] outputs
+    /// the bookmark stays on its line as older lines drop out of the history, or goes to the
+    /// oldest line left once its own has.
+    pub fn secondary_output_add_bookmark(
+        &mut self,
+        id: &SecondaryOutputId,
+        label: String,
+    ) -> Result<usize> {
+        let idx = self.secondary_output_position(id)?;
+        let num_rows = self.num_content_rows(&self.secondary_outputs[idx]);
+        let row = self
+            .visible_content_range(&self.secondary_outputs[idx], num_rows)
+            .start;
+        let bookmarks = &mut self.secondary_outputs[idx].bookmarks;
+        bookmarks.push((label, row));
+        Ok(bookmarks.len() - 1)
+    }
 
-        };
+    /// Scrolls the output so the view starts where it did when the bookmark was added, as far as
+    /// the content allows.
+    pub fn secondary_output_goto_bookmark(
+        &mut self,
+        id: &SecondaryOutputId,
+        index: usize,
+    ) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        let (_, row) = self.secondary_outputs[idx]
+            .bookmarks
+            .get(index)
+            .ok_or_else(|| anyhow!("Invalid bookmark index {index} for ID: {id:?}"))?;
+        self.scroll_to_row(idx, *row);
+        Ok(())
     }
 
-    fn rasterize_output(output: &str) -> String {
-        let mut parser = vt100::Parser::new(50, 50, 50);
-        parser.process(output.as_bytes());
-        parser.screen().contents()
+    /// Scrolls the output at `idx` so the view starts at content row `row`, as far as the content
+    /// allows.
+    fn scroll_to_row(&mut self, idx: usize, row: usize) {
+        let num_rows = self.num_content_rows(&self.secondary_outputs[idx]);
+        let max_scroll_offset = num_rows.saturating_sub(self.secondary_output_max_lines);
+        self.secondary_outputs[idx].scroll_offset = num_rows
+            .saturating_sub(row + self.secondary_output_max_lines)
+            .min(max_scroll_offset);
     }
 
-    fn get_state_output(f: impl FnOnce(&mut State<Vec<u8>>)) -> String {
-        let mut output: Vec<u8> = Vec::new();
-        {
-            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
-            f(&mut state);
+    /// The output's bookmarks, as their index, label, and the content row at the top of the view.
+    pub fn secondary_output_list_bookmarks<'b>(
+        &'b self,
+        id: &SecondaryOutputId,
+    ) -> Result<impl Iterator<Item = (usize, &'b str, usize)> + 'b> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        Ok(secondary_state
+            .bookmarks
+            .iter()
+            .enumerate()
+            .map(|(index, (label, row))| (index, label.as_str(), *row)))
+    }
+
+    /// Names the output's content rows from `start_row` up to (but not including) `end_row`,
+    /// returning the index to get back to it with [State::secondary_output_goto_range].
+    pub fn secondary_output_add_range(
+        &mut self,
+        id: &SecondaryOutputId,
+        name: String,
+        start_row: usize,
+        end_row: usize,
+    ) -> Result<usize> {
+        if start_row > end_row {
+            return Err(anyhow!(
+                "Range {name:?} starts after it ends ({start_row} > {end_row})"
+            ));
         }
-        String::from_utf8(output).unwrap()
+        let idx = self.secondary_output_position(id)?;
+        let secondary_state = &mut self.secondary_outputs[idx];
+        secondary_state.named_ranges.push(NamedRange {
+            name,
+            start: start_row,
+            end: end_row,
+        });
+        let range_index = secondary_state.named_ranges.len() - 1;
+        if secondary_state.auto_follow_ranges {
+            self.scroll_to_row(idx, start_row);
+        }
+        Ok(range_index)
     }
 
-    mod primary_output {
-        use super::*;
+    /// Scrolls to each range as it's added with [State::secondary_output_add_range], eg to follow
+    /// along as a build moves through its phases.
+    pub fn set_secondary_output_auto_follow_ranges(
+        &mut self,
+        id: &SecondaryOutputId,
+        enabled: bool,
+    ) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].auto_follow_ranges = enabled;
+        Ok(())
+    }
 
-        #[test]
-        fn buffers_bytes() {
-            assert_state_output!(|state| {
-                state.handle_primary_bytes("should not render".as_bytes());
-            });
+    /// Scrolls the output so the view starts at the start of the range, as far as the content
+    /// allows.
+    pub fn secondary_output_goto_range(
+        &mut self,
+        id: &SecondaryOutputId,
+        range_index: usize,
+    ) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        let range = self.secondary_outputs[idx]
+            .named_ranges
+            .get(range_index)
+            .ok_or_else(|| anyhow!("Invalid range index {range_index} for ID: {id:?}"))?;
+        self.scroll_to_row(idx, range.start);
+        Ok(())
+    }
+
+    /// The output's named ranges, as their index, name, and rows.
+    pub fn secondary_output_named_ranges<'b>(
+        &'b self,
+        id: &SecondaryOutputId,
+    ) -> Result<impl Iterator<Item = (usize, &'b str, Range<usize>)> + 'b> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        Ok(secondary_state
+            .named_ranges
+            .iter()
+            .enumerate()
+            .map(|(index, range)| (index, range.name.as_str(), range.start..range.end)))
+    }
+
+    /// Marks rows in the output's named ranges with a colored border to the left of the content.
+    /// The content is laid out a column narrower to make room.
+    pub fn set_secondary_output_show_named_ranges(
+        &mut self,
+        id: &SecondaryOutputId,
+        show: bool,
+    ) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].show_named_ranges = show;
+        Ok(())
+    }
+
+    /// Removes the bookmark at `index`, moving any later bookmarks down an index. Returns whether
+    /// there was a bookmark to remove.
+    pub fn secondary_output_remove_bookmark(
+        &mut self,
+        id: &SecondaryOutputId,
+        index: usize,
+    ) -> Result<bool> {
+        let idx = self.secondary_output_position(id)?;
+        let bookmarks = &mut self.secondary_outputs[idx].bookmarks;
+        if index >= bookmarks.len() {
+            return Ok(false);
         }
+        bookmarks.remove(index);
+        Ok(true)
+    }
 
-        #[test]
-        fn passes_through_bytes() {
+    /// Removes all of the output's bookmarks. Its named ranges are kept.
+    pub fn secondary_output_clear_bookmarks(&mut self, id: &SecondaryOutputId) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].bookmarks.clear();
+        Ok(())
+    }
+
+    /// Whether the output rang its bell recently enough to still be marked.
+    fn showing_bell(&self, secondary_state: &SecondaryOutputState, now: Instant) -> bool {
+        matches!(secondary_state.last_bell, Some(last_bell) if now - last_bell < self.bell_indicator_duration)
+    }
+
+    fn secondary_output_elapsed(
+        &self,
+        secondary_state: &SecondaryOutputState,
+        now: Instant,
+    ) -> Duration {
+        let aligned = self.elapsed_precision == ElapsedPrecision::Seconds;
+        secondary_state.timer.elapsed(now, aligned)
+    }
+
+    fn now(&self) -> Instant {
+        (self.clock)()
+    }
+
+    /// `now`, aligned down to a whole number of seconds since the reference start time.
+    fn aligned_now(&self, now: Instant) -> Instant {
+        let seconds_since_reference = (now - self.secondary_output_reference_start_time).as_secs();
+        self.secondary_output_reference_start_time + Duration::from_secs(seconds_since_reference)
+    }
+
+    /// Checks whether anything which changes over time (eg elapsed times and time bars) looks
+    /// different since the last tick, meaning a render would produce different output. Lets a
+    /// render loop tick frequently but only render when something changed.
+    pub fn tick(&mut self) -> bool {
+        let now = self.now();
+        let mut time_visuals = self
+            .secondary_outputs
+            .iter()
+            .map(|secondary_state| {
+                let elapsed = self.secondary_output_elapsed(secondary_state, now);
+                let mut visuals = String::new();
+                if self.show_elapsed {
+                    visuals.push_str(&self.elapsed_precision.format(
+                        self.duration_style,
+                        elapsed,
+                        false,
+                    ));
+                }
+                if let Some(max_duration) = secondary_state.time_bar_max_duration {
+                    visuals.push_str(&format_time_bar(elapsed, max_duration));
+                }
+                if self.showing_bell(secondary_state, now) {
+                    visuals.push_str(self.symbols.bell);
+                }
+                if secondary_state.timer.is_paused() {
+                    visuals.push_str(self.symbols.paused);
+                }
+                visuals
+            })
+            .collect::<Vec<_>>();
+        // The summary's elapsed time counts up even with no outputs to show
+        time_visuals.extend(self.summary_line(self.terminal_size.map(|(cols, _)| cols)));
+        let changed = time_visuals != self.last_tick_time_visuals;
+        self.last_tick_time_visuals = time_visuals;
+        self.notify_stalled_outputs();
+        changed
+    }
+
+    /// Calls `handler` whenever outputs are added, removed, finish, or stall, or the cursor moves
+    /// to a different output. It's called synchronously from within the method which caused the
+    /// event (stalls are noticed in [State::render] and [State::tick]), while the [State] is
+    /// still borrowed, so it can't call back into the [State]. Anything which needs to should
+    /// record the event (eg in an `Arc<Mutex<..>>`) and handle it afterwards.
+    pub fn set_event_handler(
+        &mut self,
+        handler: impl FnMut(StateEvent) + Send + 'static,
+    ) -> &mut Self {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    fn emit(&mut self, event: StateEvent) {
+        if let Some(handler) = &mut self.event_handler {
+            handler(event);
+        }
+    }
+
+    /// Sends [StateEvent::SelectionChanged] if the cursor is on a different output than last
+    /// time.
+    fn notify_selection_changed(&mut self) {
+        let selected_id = self.selected_secondary_output_id();
+        if selected_id != self.last_selected_id {
+            self.last_selected_id = selected_id;
+            self.emit(StateEvent::SelectionChanged { id: selected_id });
+        }
+    }
+
+    fn notify_stalled_outputs(&mut self) {
+        if self.event_handler.is_none() {
+            return;
+        }
+        let now = self.now();
+        let mut stalled = Vec::new();
+        for secondary_state in &mut self.secondary_outputs {
+            if secondary_state.status == SecondaryOutputStatus::Running
+                && !secondary_state.stall_notified
+                && now - secondary_state.last_output >= self.stall_threshold
+            {
+                secondary_state.stall_notified = true;
+                stalled.push(secondary_state.id);
+            }
+        }
+        for id in stalled {
+            self.emit(StateEvent::OutputStalled { id });
+        }
+    }
+
+    /// Handles bytes from the primary output the state was created with (the [Default]
+    /// [PrimaryOutputId]). Ignored once [State::handle_primary_eof] has been called.
+    pub fn handle_primary_bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        let first_primary_output = self.primary_outputs[0].id;
+        self.handle_primary_bytes_for(first_primary_output, bytes)
+            .expect("The first primary output is never removed")
+    }
+
+    /// Same as [State::handle_primary_bytes].
+    pub fn write_primary_bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.handle_primary_bytes(bytes)
+    }
+
+    /// Adds another primary output, eg for a second process whose output should go straight to
+    /// the terminal. It starts out inactive, see [State::set_active_primary_output].
+    pub fn new_primary_output(&mut self, title: Option<String>) -> PrimaryOutputId {
+        let id = self.primary_output_next_id.next_id();
+        self.primary_outputs.push(PrimaryOutput {
+            id,
+            title,
+            held_bytes: Vec::new(),
+            parser: VteActionParser::new(),
+        });
+        id
+    }
+
+    /// Handles bytes from the given primary output. They're written out if it's the active
+    /// primary output, otherwise they're held on to until it becomes active. Ignored once
+    /// [State::handle_primary_eof] has been called.
+    pub fn handle_primary_bytes_for(
+        &mut self,
+        id: PrimaryOutputId,
+        bytes: &[u8],
+    ) -> Result<&mut Self> {
+        let idx = self.primary_output_position(id)?;
+        if self.primary_eof {
+            return Ok(self);
+        }
+        if id == self.active_primary_output {
+            self.write_through_primary_bytes(bytes);
+        } else {
+            let held_bytes = &mut self.primary_outputs[idx].held_bytes;
+            held_bytes.extend(bytes);
+            if held_bytes.len() > self.primary_hold_budget {
+                // Drop the oldest bytes, starting again somewhere which won't garble the terminal
+                let cut = held_bytes.len() - self.primary_hold_budget;
+                let start = resync_point(held_bytes, cut);
+                held_bytes.drain(..start);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Switches which primary output is written out. Anything it was sent while inactive is
+    /// written out now, and the previously active output holds on to its bytes from here on.
+    ///
+    /// Each primary output's bytes are parsed separately, but the terminal sees them in the order
+    /// they're written, so switching while the active output is partway through an escape
+    /// sequence or UTF-8 character leaves the terminal partway through it too. Switching between
+    /// whole lines avoids this.
+    pub fn set_active_primary_output(&mut self, id: PrimaryOutputId) -> Result<&mut Self> {
+        let idx = self.primary_output_position(id)?;
+        if id == self.active_primary_output {
+            return Ok(self);
+        }
+        // The pending bytes need parsing by the output which wrote them
+        if !self.primary_bytes.is_empty() {
+            if let Err(error) = self.flush_primary_bytes() {
+                self.pending_write_error.get_or_insert(error);
+            }
+        }
+        self.active_primary_output = id;
+        let held_bytes = std::mem::take(&mut self.primary_outputs[idx].held_bytes);
+        if !self.primary_eof {
+            self.write_through_primary_bytes(&held_bytes);
+        }
+        Ok(self)
+    }
+
+    /// The primary output whose bytes are currently written out.
+    pub fn active_primary_output(&self) -> PrimaryOutputId {
+        self.active_primary_output
+    }
+
+    /// The title given to [State::new_primary_output], if any.
+    pub fn primary_output_title(&self, id: PrimaryOutputId) -> Result<Option<&str>> {
+        let idx = self.primary_output_position(id)?;
+        Ok(self.primary_outputs[idx].title.as_deref())
+    }
+
+    fn primary_output_position(&self, id: PrimaryOutputId) -> Result<usize> {
+        self.primary_outputs
+            .iter()
+            .position(|primary_output| primary_output.id == id)
+            .ok_or_else(|| anyhow!("Invalid ID: {id:?}"))
+    }
+
+    fn write_through_primary_bytes(&mut self, bytes: &[u8]) {
+        #[cfg(feature = "asciicast")]
+        if let Some(log) = &mut self.primary_byte_log {
+            log.push(bytes, (self.clock)());
+        }
+        self.primary_bytes.extend(bytes);
+        if self.primary_bytes.len() >= self.primary_flush_threshold {
+            // Hold on to any error until the next render, which is where callers expect them
+            if let Err(error) = self.flush_primary_bytes() {
+                self.pending_write_error.get_or_insert(error);
+            }
+        }
+    }
+
+    /// Marks the primary output as finished, eg once the primary process closes its output. Any
+    /// bytes handled after this are ignored, and the next render marks the end of the primary
+    /// output (see [State::set_eof_indicator_position]).
+    pub fn handle_primary_eof(&mut self) -> &mut Self {
+        self.primary_eof = true;
+        self
+    }
+
+    /// Whether [State::handle_primary_eof] has been called.
+    pub fn is_primary_eof(&self) -> bool {
+        self.primary_eof
+    }
+
+    /// Where to show that the primary output has finished. Only takes effect if it's set before
+    /// the indicator is rendered.
+    pub fn set_eof_indicator_position(&mut self, position: EofIndicatorPosition) -> &mut Self {
+        self.eof_indicator_position = position;
+        self
+    }
+
+    /// Starts (or stops and discards) keeping a timestamped log of everything handled from the
+    /// primary output, for [State::export_to_asciicast]. The log grows for as long as it's
+    /// enabled.
+    #[cfg(feature = "asciicast")]
+    pub fn set_primary_byte_log_enabled(&mut self, enabled: bool) -> &mut Self {
+        if !enabled {
+            self.primary_byte_log = None;
+        } else if self.primary_byte_log.is_none() {
+            self.primary_byte_log = Some(crate::asciicast::ByteLog::new(self.now()));
+        }
+        self
+    }
+
+    /// Writes out the primary output logged since [State::set_primary_byte_log_enabled] as an
+    /// [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) recording, timed from when
+    /// logging started. Secondary outputs are only ever drawn over the primary output, so they
+    /// aren't part of the recording. Writes just the header if logging isn't enabled.
+    #[cfg(feature = "asciicast")]
+    pub fn export_to_asciicast<W2: Write>(
+        &self,
+        writer: &mut W2,
+        terminal_width: u16,
+        terminal_height: u16,
+    ) -> std::io::Result<()> {
+        crate::asciicast::write_header(writer, terminal_width, terminal_height)?;
+        if let Some(log) = &self.primary_byte_log {
+            log.write_events(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) recording (eg from
+    /// [State::export_to_asciicast]), returning a state sized to match the recording along with
+    /// the recorded events. Nothing is fed into the state; it's up to the caller to pass each
+    /// event to [State::handle_primary_bytes] at its time.
+    #[cfg(feature = "asciicast")]
+    pub fn replay_from_asciicast<R: std::io::Read>(
+        output: &'a mut W,
+        reader: &mut R,
+    ) -> Result<(Self, Vec<crate::asciicast::ReplayEvent>)> {
+        let ((cols, rows), events) =
+            crate::asciicast::read_recording(std::io::BufReader::new(reader))?;
+        let mut state = StateBuilder::new().build(output);
+        state.set_terminal_size(cols, rows);
+        Ok((state, events))
+    }
+
+    /// The window title most recently set by the primary output, as of the last time its bytes
+    /// were written out (see [State::render]).
+    pub fn primary_title(&self) -> Option<&str> {
+        self.primary_title.as_deref()
+    }
+
+    /// How many escape sequences in the primary output haven't been accounted for when tracking
+    /// where it leaves the cursor, which helps track down where things get misplaced. With
+    /// debug logging on, each one is logged too.
+    pub fn ignored_sequence_count(&self) -> usize {
+        self.ignored_sequence_count
+    }
+
+    /// Takes the queries the primary output has made since the last call, in order. Like
+    /// [State::primary_title], these are only picked up once the primary bytes are written out.
+    ///
+    /// The queries are still passed through to the terminal, so the terminal's own replies should
+    /// be dropped in favor of [PrimaryQuery::reply].
+    pub fn take_pending_queries(&mut self) -> Vec<PrimaryQuery> {
+        std::mem::take(&mut self.pending_primary_queries)
+    }
+
+    /// Shorthand for [State::handle_primary_bytes] followed by [State::render].
+    pub fn handle_primary_bytes_and_render(&mut self, bytes: &[u8]) -> Result<&mut Self> {
+        self.handle_primary_bytes(bytes).render()?;
+        Ok(self)
+    }
+
+    /// Writes the primary bytes straight through, without redrawing the secondary outputs. They
+    /// get cleared off the screen though, since they'd be in the way.
+    fn flush_primary_bytes(&mut self) -> Result<()> {
+        self.clear_secondary_outputs()?;
+        self.write_pending_primary_bytes()?;
+        self.backend.flush(self.output)?;
+        Ok(())
+    }
+
+    /// Removes the secondary outputs from the previous render (if any) and puts the cursor back
+    /// where the primary output left it.
+    fn clear_secondary_outputs(&mut self) -> Result<()> {
+        let (x, y) = self.primary_output_final_cursor_offset;
+        if self.previous_render_extra_lines > 0 {
+            let backend = self.backend;
+            backend.move_to_column(self.output, 0)?;
+            if self.cursor_parked {
+                backend.move_down(self.output, y.saturating_add(1))?;
+            } else {
+                backend.move_up(self.output, self.previous_render_extra_lines)?;
+            }
+            // Terminals clear with the current background color, so clear with none
+            let styled = self.primary_style.sequence().is_some();
+            if styled {
+                backend.reset_style(self.output)?;
+            }
+            backend.clear_down(self.output)?;
+            backend.move_up(self.output, y.saturating_add(1))?;
+            // Moving right by 0 would still move by 1, so move to the column instead
+            backend.move_to_column(self.output, x)?;
+            if styled {
+                self.restore_primary_style()?;
+            }
+        }
+        self.previous_render_extra_lines = 0;
+        self.cursor_parked = false;
+        self.last_frame_layout = FrameLayout::default();
+        Ok(())
+    }
+
+    /// Moves the cursor from just below what render drew back to where the primary output left
+    /// it, so it's only in the secondary outputs' area while they're being drawn. That way the
+    /// terminal shows it in the right place, and eg anything the user types is echoed there. Once
+    /// the primary output has finished it's left below, so whatever runs next starts after them.
+    fn park_cursor(&mut self) -> Result<()> {
+        if self.previous_render_extra_lines == 0 || self.primary_eof {
+            return Ok(());
+        }
+        let (x, y) = self.primary_output_final_cursor_offset;
+        let backend = self.backend;
+        backend.move_up(
+            self.output,
+            self.previous_render_extra_lines
+                .saturating_add(y)
+                .saturating_add(1),
+        )?;
+        // Moving right by 0 would still move by 1, so move to the column instead
+        backend.move_to_column(self.output, x)?;
+        self.cursor_parked = true;
+        Ok(())
+    }
+
+    /// Turns the primary output's styling back on, after drawing something which reset it.
+    fn restore_primary_style(&mut self) -> Result<()> {
+        if let Some(sequence) = self.primary_style.sequence() {
+            self.backend.print(self.output, &sequence)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out any pending primary bytes, tracking where they leave the cursor.
+    fn write_pending_primary_bytes(&mut self) -> Result<()> {
+        let (mut x, mut y) = self.primary_output_final_cursor_offset;
+        let mut turned_off_mouse_mode = false;
+        self.output.write_all(&self.primary_bytes)?;
+        let active_idx = self.primary_output_position(self.active_primary_output)?;
+        let parser = &mut self.primary_outputs[active_idx].parser;
+        // Only spell out ignored sequences if they'll be logged
+        parser.set_keep_ignored_raw(log::log_enabled!(log::Level::Debug));
+        parser.parse_bytes_streaming(&self.primary_bytes, |action| {
+            let keeps_pending_wrap = matches!(
+                action,
+                VteAction::Text(_)
+                    | VteAction::RepeatLastChar(_)
+                    | VteAction::SetTitle(_)
+                    | VteAction::CursorPositionQuery
+                    | VteAction::SetTabStop
+                    | VteAction::ClearTabStops(_)
+                    | VteAction::ShowCursor
+                    | VteAction::HideCursor
+                    | VteAction::SetMouseMode(..)
+                    | VteAction::SetStyle(_)
+                    | VteAction::DcsStart(_)
+                    | VteAction::DcsData(_)
+                    | VteAction::DcsEnd
+                    | VteAction::Ignored { .. }
+            );
+            // Moving down past the bottom of the scroll region (or up past the top) scrolls the
+            // region's content rather than moving the cursor
+            let region = scroll_region_offsets(self.terminal_size, self.primary_scroll_region);
+            let line_down = |y: u16| match region {
+                Some((_, bottom)) if y == bottom => y,
+                _ => y.saturating_sub(1),
+            };
+            match action {
+                VteAction::Text(c) => {
+                    // Wide characters (eg CJK and emoji) take up two columns, combining marks none
+                    let width = c.width().unwrap_or(1) as u16;
+                    // Text past the end of the line wraps onto the next one, as does a wide
+                    // character which doesn't fit in what's left of it
+                    if matches!(self.terminal_size, Some((cols, _)) if width > 0 && x + width > cols)
+                    {
+                        x = 0;
+                        y = line_down(y);
+                    }
+                    x = x.saturating_add(width);
+                }
+                VteAction::RepeatLastChar(n) => {
+                    // Like printing the character again, though its width isn't known
+                    for _ in 0..n {
+                        if matches!(self.terminal_size, Some((cols, _)) if x >= cols) {
+                            x = 0;
+                            y = line_down(y);
+                        }
+                        x = x.saturating_add(1);
+                    }
+                }
+                // These shift the rest of the line around the cursor
+                VteAction::InsertBlank(_) | VteAction::DeleteChars(_) => {}
+                VteAction::Tab => {
+                    let next_stop = self.primary_tab_stops.next_after(x);
+                    // Tabs don't wrap, they stop at the end of the line if there's no stop before
+                    x = match (next_stop, self.terminal_size) {
+                        (Some(stop), Some((cols, _))) => stop.min(cols.saturating_sub(1)),
+                        (Some(stop), None) => stop,
+                        (None, Some((cols, _))) => cols.saturating_sub(1),
+                        (None, None) => x,
+                    };
+                }
+                VteAction::SetTabStop => self.primary_tab_stops.set(x),
+                VteAction::ClearTabStops(0) => self.primary_tab_stops.clear(x),
+                VteAction::ShowCursor => self.primary_cursor_visible = true,
+                VteAction::HideCursor => self.primary_cursor_visible = false,
+                VteAction::SetMouseMode(mode, enabled) => {
+                    self.primary_mouse_modes.set(mode, enabled);
+                    turned_off_mouse_mode |= !enabled;
+                }
+                VteAction::SetStyle(params) => self.primary_style.apply(&params),
+                VteAction::ClearTabStops(3) => self.primary_tab_stops.replace([]),
+                // Terminals ignore the other modes
+                VteAction::ClearTabStops(_) => {}
+                VteAction::LineFeed | VteAction::Index => y = line_down(y),
+                VteAction::ReverseIndex => match region {
+                    Some((top, _)) if y == top => {}
+                    _ => y = y.saturating_add(1),
+                },
+                VteAction::SetScrollRegion(top, bottom) => {
+                    let rows = self.terminal_size.map(|(_, rows)| rows);
+                    // Terminals ignore regions which aren't at least two rows
+                    let valid = match (bottom, rows) {
+                        (0, Some(rows)) => top < rows,
+                        (0, None) => true,
+                        (bottom, _) => top < bottom,
+                    };
+                    if valid {
+                        self.primary_scroll_region =
+                            Some((top, bottom)).filter(|region| *region != (1, 0));
+                        // Setting the region also moves the cursor to the top left
+                        x = 0;
+                        if let Some(rows) = rows {
+                            y = rows.saturating_sub(1);
+                        }
+                    }
+                }
+                VteAction::CarriageReturn => x = 0,
+                VteAction::CursorUp(n) => y = y.saturating_add(n),
+                VteAction::CursorDown(n) => y = y.saturating_sub(n),
+                VteAction::CursorForward(n) => x = x.saturating_add(n),
+                VteAction::CursorBackward(n) => x = x.saturating_sub(n),
+                VteAction::CursorNextLine(n) => {
+                    y = y.saturating_sub(n);
+                    x = 0;
+                }
+                VteAction::CursorPreviousLine(n) => {
+                    y = y.saturating_add(n);
+                    x = 0;
+                }
+                VteAction::CursorColumn(column) => x = column.saturating_sub(1),
+                VteAction::CursorRow(row) => {
+                    // Rows count from the top of the terminal, so there's no telling where that
+                    // is relative to the bottom of the primary output without knowing its height
+                    if let Some((_, rows)) = self.terminal_size {
+                        y = rows.saturating_sub(row);
+                    }
+                }
+                VteAction::CursorPosition(row, column) => {
+                    // The row's only known with the terminal's height, as for CursorRow
+                    x = column.saturating_sub(1);
+                    if let Some((_, rows)) = self.terminal_size {
+                        y = rows.saturating_sub(row);
+                    }
+                }
+                VteAction::SetTitle(title) => self.primary_title = Some(title),
+                VteAction::CursorPositionQuery => {
+                    // A cursor just past the end of the line is still reported in the last column
+                    let column = match self.terminal_size {
+                        Some((cols, _)) => x.min(cols.saturating_sub(1)),
+                        None => x,
+                    };
+                    self.pending_primary_queries
+                        .push(PrimaryQuery::CursorPosition {
+                            column,
+                            lines_from_bottom: y,
+                        });
+                }
+                // Device control strings are passed through as-is and don't move the cursor
+                VteAction::DcsStart(header) => {
+                    self.ignored_sequence_count += 1;
+                    log::debug!(
+                        "Ignored DCS sequence in primary output: {:?}",
+                        String::from_utf8_lossy(&header)
+                    );
+                }
+                VteAction::DcsData(_) | VteAction::DcsEnd => {}
+                // Assume anything not understood doesn't move the cursor, eg colors
+                VteAction::Ignored { kind, raw } => {
+                    self.ignored_sequence_count += 1;
+                    if let Some(raw) = raw {
+                        log::debug!("Ignored {kind:?} sequence in primary output: {raw:?}");
+                    }
+                }
+            }
+            // The cursor can't leave the terminal. Printing text can leave it just past the last
+            // column though, until the next character wraps it or it's moved.
+            if let Some((cols, rows)) = self.terminal_size {
+                x = x.min(if keeps_pending_wrap {
+                    cols
+                } else {
+                    cols.saturating_sub(1)
+                });
+                y = y.min(rows.saturating_sub(1));
+            }
+        });
+        self.primary_output_final_cursor_offset = (x, y);
+        self.primary_bytes.clear();
+        // The terminal only has one set of mouse modes, so the primary output turning its modes
+        // off turns off our capture too
+        if turned_off_mouse_mode && self.mouse_capture {
+            self.backend.enable_mouse_capture(self.output)?;
+        }
+        Ok(())
+    }
+
+    /// How many primary bytes can pile up between renders before they're written out anyway, eg
+    /// if rendering stalls while the primary output is flooding. Writing them out early means the
+    /// secondary outputs disappear until the next render, rather than using unbounded memory and
+    /// writing a giant burst once rendering catches up.
+    pub fn set_primary_flush_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.primary_flush_threshold = bytes;
+        self
+    }
+
+    /// How many bytes each inactive primary output holds on to until it's made active (see
+    /// [State::set_active_primary_output]). Beyond that the oldest whole lines are dropped, so an
+    /// output nobody's looking at can't use up unbounded memory.
+    pub fn set_primary_hold_budget(&mut self, bytes: usize) -> &mut Self {
+        self.primary_hold_budget = bytes;
+        self
+    }
+
+    pub fn new_secondary_output(&mut self, title: String) -> SecondaryOutputId {
+        self.new_secondary_output_at(self.secondary_outputs.len(), title)
+    }
+
+    /// Same as [State::new_secondary_output], but inserts the output at `index` in the list rather
+    /// than at the end. The index is clamped to the end of the list, and to after any pinned
+    /// outputs. The cursor stays on the same output.
+    pub fn new_secondary_output_at(&mut self, index: usize, title: String) -> SecondaryOutputId {
+        let index = index
+            .min(self.secondary_outputs.len())
+            .max(self.pinned_count());
+        // Align start time to the reference start time so different outputs tick to the next
+        // second together.
+        let now = self.now();
+        let timer = ElapsedTimer::start(self.aligned_now(now), now);
+        let id = self.secondary_output_next_id.next_id();
+        self.had_secondary_outputs = true;
+        if !self.secondary_outputs.is_empty() && index <= self.secondary_output_selected_index {
+            self.secondary_output_selected_index += 1;
+        }
+        let mut secondary_state =
+            SecondaryOutputState::new(id, title, timer, now, self.secondary_output_max_lines);
+        if let Some(cols) = self.secondary_screen_cols {
+            secondary_state.set_screen_cols(cols);
+        }
+        self.secondary_outputs.insert(index, secondary_state);
+        self.emit(StateEvent::OutputAdded {
+            id,
+            title: self.secondary_outputs[index].title.clone(),
+        });
+        self.notify_selection_changed();
+        id
+    }
+
+    /// Inserts a new output directly above `before`, see [State::new_secondary_output_at].
+    pub fn new_secondary_output_before(
+        &mut self,
+        before: &SecondaryOutputId,
+        title: String,
+    ) -> Result<SecondaryOutputId> {
+        let idx = self.secondary_output_position(before)?;
+        Ok(self.new_secondary_output_at(idx, title))
+    }
+
+    /// Same as [State::new_secondary_output], but the output can also be looked up by `name`, eg
+    /// a build task's name. Names have to be unique among the outputs which haven't been removed.
+    pub fn new_named_secondary_output(
+        &mut self,
+        name: String,
+        title: String,
+    ) -> Result<SecondaryOutputId> {
+        if self.name_to_id.contains_key(&name) {
+            return Err(anyhow!("There's already an output named {name:?}"));
+        }
+        let id = self.new_secondary_output(title);
+        self.name_to_id.insert(name, id);
+        Ok(id)
+    }
+
+    /// The ID of the output created with `name` by [State::new_named_secondary_output], if it
+    /// hasn't been removed. Names are unique, so there's at most one.
+    pub fn find_secondary_output_by_name(&self, name: &str) -> Option<SecondaryOutputId> {
+        self.name_to_id.get(name).copied()
+    }
+
+    /// Removes the output named `name` like [State::remove_secondary_output]. Removing an output
+    /// (by name or not) frees up its name, so a new output can be created with it.
+    pub fn remove_secondary_output_by_name(&mut self, name: &str) -> Result<&mut Self> {
+        let id = self
+            .find_secondary_output_by_name(name)
+            .ok_or_else(|| anyhow!("No output named {name:?}"))?;
+        self.remove_secondary_output(id)
+    }
+
+    /// Stops the output's elapsed time from counting up, eg while its process is suspended, and
+    /// marks it as paused. Does nothing if it's already paused.
+    pub fn pause_timer(&mut self, id: &SecondaryOutputId) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        let now = self.now();
+        self.secondary_outputs[idx].timer.pause(now);
+        Ok(self)
+    }
+
+    /// Continues counting the output's elapsed time from where [State::pause_timer] left it.
+    /// Does nothing if it isn't paused.
+    pub fn resume_timer(&mut self, id: &SecondaryOutputId) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        let now = self.now();
+        let aligned_now = self.aligned_now(now);
+        self.secondary_outputs[idx].timer.resume(aligned_now, now);
+        Ok(self)
+    }
+
+    /// Scrolls the viewport the minimal amount needed to keep the selection in view, and returns
+    /// the indices of the outputs which should be rendered, in order.
+    fn update_secondary_output_viewport(&mut self) -> Vec<usize> {
+        let (start, visible_indices) = self.scrolled_secondary_output_viewport();
+        if let Some(start) = start {
+            self.secondary_output_viewport_start = start;
+        }
+        visible_indices
+    }
+
+    /// Where the viewport (if there is one) would start after scrolling to keep the selection in
+    /// view, and the indices of the outputs which it would show, in order.
+    fn scrolled_secondary_output_viewport(&self) -> (Option<usize>, Vec<usize>) {
+        let len = self.secondary_outputs.len();
+        let Some(visible_rows) = self.secondary_output_viewport else {
+            return (None, (0..len).collect());
+        };
+        // Pinned outputs are always shown, and the rest scroll in whatever space is left
+        let pinned = self.pinned_count();
+        let visible_rows = (visible_rows as usize).saturating_sub(pinned);
+        let selected = self.secondary_output_selected_index;
+        let mut start = self.secondary_output_viewport_start.max(pinned);
+        if selected >= pinned {
+            if selected < start {
+                start = selected;
+            } else if selected >= start + visible_rows {
+                start = (selected + 1).saturating_sub(visible_rows);
+            }
+        }
+        // Don't leave empty space at the end if outputs were removed
+        start = start.min(len.saturating_sub(visible_rows)).max(pinned);
+        let visible_indices = (0..pinned.min(len))
+            .chain(start..(start + visible_rows).min(len))
+            .collect();
+        (Some(start), visible_indices)
+    }
+
+    /// Pinned outputs are always kept at the start of the list.
+    fn pinned_count(&self) -> usize {
+        self.secondary_outputs
+            .iter()
+            .take_while(|secondary_state| secondary_state.pinned)
+            .count()
+    }
+
+    /// Pins the output to the top of the list, after any outputs which were already pinned. When
+    /// only some outputs are shown (see [State::set_virtual_list_viewport]), pinned outputs are
+    /// always shown. Unpinning an output moves it to the top of the unpinned outputs. Either way,
+    /// the selection stays on the same output.
+    pub fn set_pinned(&mut self, id: SecondaryOutputId, pinned: bool) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(&id)?;
+        if self.secondary_outputs[idx].pinned == pinned {
+            return Ok(self);
+        }
+        let selected_id = self.selected_secondary_output_id();
+        let mut secondary_state = self.secondary_outputs.remove(idx);
+        secondary_state.pinned = pinned;
+        let pinned_count = self.pinned_count();
+        self.secondary_outputs.insert(pinned_count, secondary_state);
+        if let Some(selected_id) = selected_id {
+            self.secondary_output_selected_index = self.secondary_output_position(&selected_id)?;
+        }
+        Ok(self)
+    }
+
+    fn secondary_output_position(&self, id: &SecondaryOutputId) -> Result<usize> {
+        self.secondary_outputs
+            .iter()
+            .position(|secondary_state| secondary_state.id == *id)
+            // TODO - Use thiserror
+            .ok_or_else(|| anyhow!("Invalid ID: {id:?}"))
+    }
+
+    /// Where the output currently is in the list, counting from the top, eg for
+    /// [State::move_cursor_to]. Takes time linear in the number of outputs, since lists are
+    /// usually short; a map from ID to index could be kept up to date if that stops being true.
+    pub fn secondary_output_index_of(&self, id: &SecondaryOutputId) -> Result<usize> {
+        self.secondary_output_position(id)
+    }
+
+    /// The output at `index` in the list, if there is one. The reverse of
+    /// [State::secondary_output_index_of].
+    pub fn secondary_output_id_at_index(&self, index: usize) -> Option<SecondaryOutputId> {
+        self.secondary_outputs
+            .get(index)
+            .map(|secondary_state| secondary_state.id)
+    }
+
+    pub fn remove_secondary_output(&mut self, id: SecondaryOutputId) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(&id)?;
+        self.secondary_outputs.remove(idx);
+        if self.secondary_output_selected_index > idx {
+            self.secondary_output_selected_index -= 1;
+        }
+        self.clamp_selection_index();
+        self.name_to_id.retain(|_, named_id| *named_id != id);
+        self.emit(StateEvent::OutputRemoved { id });
+        self.notify_selection_changed();
+        Ok(self)
+    }
+
+    /// Removes every output whose ID and title match `predicate`, eg all of the finished ones,
+    /// returning the IDs of those removed. As with [State::remove_secondary_output], the selection
+    /// stays on the same output if it's kept, or moves to the next one which is.
+    pub fn remove_secondary_outputs_where(
+        &mut self,
+        predicate: impl Fn(&SecondaryOutputId, &str) -> bool,
+    ) -> Vec<SecondaryOutputId> {
+        let selected_index = self.secondary_output_selected_index;
+        let mut removed = Vec::new();
+        let mut removed_before_selected = 0;
+        let mut idx = 0;
+        self.secondary_outputs.retain(|secondary_state| {
+            let remove = predicate(&secondary_state.id, &secondary_state.title);
+            if remove {
+                removed.push(secondary_state.id);
+                if idx < selected_index {
+                    removed_before_selected += 1;
+                }
+            }
+            idx += 1;
+            !remove
+        });
+        self.secondary_output_selected_index = selected_index - removed_before_selected;
+        self.clamp_selection_index();
+        self.name_to_id
+            .retain(|_, named_id| !removed.contains(named_id));
+        for id in &removed {
+            self.emit(StateEvent::OutputRemoved { id: *id });
+        }
+        self.notify_selection_changed();
+        removed
+    }
+
+    /// Captures the internal state, eg to include in a bug report.
+    pub fn dump_debug_state(&self) -> DebugState {
+        DebugState {
+            primary_bytes_len: self.primary_bytes.len(),
+            primary_cursor_offset: self.primary_output_final_cursor_offset,
+            previous_render_extra_lines: self.previous_render_extra_lines,
+            secondary_output_count: self.secondary_outputs.len(),
+            render_count: self.render_count,
+            secondary_outputs: self
+                .secondary_outputs
+                .iter()
+                .map(|secondary_state| SecondaryOutputDebugState {
+                    id: secondary_state.id,
+                    title: secondary_state.title.clone(),
+                    expanded: secondary_state.expanded(),
+                    bytes_received: secondary_state.bytes_received,
+                    buffer_size: secondary_state.buffer_size(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Describes the internal state as text, eg for users to paste into a bug report when the
+    /// outputs are drawn in the wrong place.
+    pub fn debug_dump(&self) -> String {
+        let mut dump = String::new();
+        let (x, y) = self.primary_output_final_cursor_offset;
+        dump.push_str(&format!(
+            "primary cursor: column {x}, {y} lines from the bottom\n"
+        ));
+        dump.push_str(&format!(
+            "pending primary bytes: {}\n",
+            self.primary_bytes.len()
+        ));
+        dump.push_str(&format!(
+            "previous render extra lines: {} (laid out {})\n",
+            self.previous_render_extra_lines, self.last_frame_layout.extra_lines
+        ));
+        match self.terminal_size {
+            Some((cols, rows)) => dump.push_str(&format!("terminal size: {cols}x{rows}\n")),
+            None => dump.push_str("terminal size: unknown\n"),
+        }
+        dump.push_str(&format!(
+            "selected index: {} of {}\n",
+            self.secondary_output_selected_index,
+            self.secondary_outputs.len()
+        ));
+        for secondary_state in &self.secondary_outputs {
+            let (row, column) = secondary_state.buffer_cursor_position();
+            let drawn = match self
+                .last_frame_layout
+                .outputs
+                .iter()
+                .find(|layout| layout.id == secondary_state.id)
+            {
+                Some(layout) => format!(
+                    "drawn at rows {}..{}",
+                    layout.header_row, layout.content_rows.end
+                ),
+                None => "not drawn".to_string(),
+            };
+            dump.push_str(&format!(
+                "output {} {:?}: expanded {}, cursor row {row} column {column}, {} lines, {drawn}\n",
+                secondary_state.id,
+                secondary_state.title,
+                secondary_state.expanded(),
+                self.num_content_rows(secondary_state),
+            ));
+        }
+        dump
+    }
+
+    /// Checks that the internal state is consistent, returning anything which isn't. This is
+    /// checked after every render in debug builds.
+    pub fn validate(&self) -> Vec<StateInvariantViolation> {
+        let mut violations = Vec::new();
+        let len = self.secondary_outputs.len();
+        let index = self.secondary_output_selected_index;
+        if index >= len && !(index == 0 && len == 0) {
+            violations.push(StateInvariantViolation::SelectionOutOfBounds { index, len });
+        }
+        let mut ids = HashSet::new();
+        for secondary_state in &self.secondary_outputs {
+            if !ids.insert(secondary_state.id) {
+                violations.push(StateInvariantViolation::DuplicateId(secondary_state.id));
+            }
+        }
+        if usize::from(self.previous_render_extra_lines) != self.last_frame_layout.extra_lines {
+            violations.push(StateInvariantViolation::ExtraLinesMismatch {
+                recorded: self.previous_render_extra_lines,
+                laid_out: self.last_frame_layout.extra_lines,
+            });
+        }
+        violations
+    }
+
+    /// When the output was started, eg for showing absolute timestamps in a custom header. This is
+    /// aligned to a whole number of seconds after [State::secondary_output_reference_start_time],
+    /// so that every output's elapsed time ticks over to the next second at the same moment rather
+    /// than at scattered ones. That means it can be up to a second before the output was actually
+    /// created. Pausing and resuming the timer doesn't change it.
+    pub fn secondary_output_start_time(&self, id: &SecondaryOutputId) -> Result<Instant> {
+        let idx = self.secondary_output_position(id)?;
+        Ok(self.secondary_outputs[idx].timer.started)
+    }
+
+    /// What outputs' start times are aligned to (see [State::secondary_output_start_time]), which
+    /// is when the state was created. An [Instant] can't be shown as a wall-clock time directly,
+    /// but can be converted by subtracting how long ago it was from [SystemTime::now].
+    pub fn secondary_output_reference_start_time(&self) -> Instant {
+        self.secondary_output_reference_start_time
+    }
+
+    /// A summary of everything about an output which might be useful to show elsewhere, eg in a
+    /// status bar.
+    pub fn secondary_output_metadata(&self, id: &SecondaryOutputId) -> Result<SecondaryOutputInfo> {
+        let idx = self.secondary_output_position(id)?;
+        Ok(self.secondary_output_info(&self.secondary_outputs[idx], self.now()))
+    }
+
+    /// [State::secondary_output_metadata] for every output, in the order they're shown.
+    pub fn all_secondary_output_metadata(&self) -> Vec<SecondaryOutputInfo> {
+        let now = self.now();
+        self.secondary_outputs
+            .iter()
+            .map(|secondary_state| self.secondary_output_info(secondary_state, now))
+            .collect()
+    }
+
+    fn secondary_output_info(
+        &self,
+        secondary_state: &SecondaryOutputState,
+        now: Instant,
+    ) -> SecondaryOutputInfo {
+        let exit_code = match secondary_state.status {
+            SecondaryOutputStatus::Running => None,
+            SecondaryOutputStatus::Finished { exit_code } => exit_code,
+        };
+        SecondaryOutputInfo {
+            id: secondary_state.id,
+            title: secondary_state.title.clone(),
+            elapsed: secondary_state.timer.elapsed(now, false),
+            expanded: secondary_state.expanded(),
+            completed: matches!(
+                secondary_state.status,
+                SecondaryOutputStatus::Finished { .. }
+            ),
+            exit_code,
+            line_count: self.num_content_rows(secondary_state),
+            byte_count: secondary_state.bytes_received,
+            since_last_output: now - secondary_state.last_output,
+            hidden: false,
+            dropped_bytes: secondary_state.dropped_bytes,
+        }
+    }
+
+    /// Removes every output, returning the ID and title of each. IDs keep counting up from where
+    /// they were, so any held on to from before can't be mistaken for new outputs.
+    pub fn remove_all_secondary_outputs(&mut self) -> Vec<(SecondaryOutputId, String)> {
+        let removed = std::mem::take(&mut self.secondary_outputs)
+            .into_iter()
+            .map(|secondary_state| (secondary_state.id, secondary_state.title))
+            .collect::<Vec<_>>();
+        for (id, _) in &removed {
+            self.emit(StateEvent::OutputRemoved { id: *id });
+        }
+        self.secondary_output_selected_index = 0;
+        self.secondary_output_viewport_start = 0;
+        self.name_to_id.clear();
+        self.notify_selection_changed();
+        removed
+    }
+
+    /// Removes the output like [State::remove_secondary_output], returning what it had in it.
+    pub fn take_secondary_output(&mut self, id: SecondaryOutputId) -> Result<TakenOutput> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(&id)?];
+        let rows = secondary_state.content_rows(
+            self.content_width(secondary_state, None),
+            false,
+            self.symbols.ellipsis,
+        );
+        let contents = rows
+            .iter()
+            .map(|row| {
+                let mut row = String::from_utf8_lossy(row).into_owned();
+                if row.contains('\x1b') {
+                    row.push_str("\x1b[0m");
+                }
+                row
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let taken = TakenOutput {
+            title: secondary_state.title.clone(),
+            elapsed: secondary_state.timer.elapsed(self.now(), false),
+            status: secondary_state.status,
+            contents,
+        };
+        self.remove_secondary_output(id)?;
+        Ok(taken)
+    }
+
+    /// Removes every output at once, eg when a new build starts, like
+    /// [State::remove_all_secondary_outputs] but chaining rather than returning what was removed.
+    /// The next render still clears away whatever the last one drew.
+    pub fn clear_all_secondary_outputs(&mut self) -> &mut Self {
+        self.remove_all_secondary_outputs();
+        self
+    }
+
+    /// Keeps the selection pointing at an output (or 0 if there are none), eg after removing the
+    /// last one.
+    fn clamp_selection_index(&mut self) {
+        self.secondary_output_selected_index = self
+            .secondary_output_selected_index
+            .min(self.secondary_outputs.len().saturating_sub(1));
+    }
+
+    pub fn handle_secondary_bytes(
+        &mut self,
+        id: &SecondaryOutputId,
+        bytes: &[u8],
+    ) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        let now = self.now();
+        if self.secondary_outputs[idx].handle_bytes(
+            bytes,
+            now,
+            self.secondary_output_byte_budget,
+            self.symbols.ellipsis,
+        ) {
+            self.bell_pending = true;
+        }
+        if let Some(max_lines) = self.combined_tail {
+            let secondary_state = &mut self.secondary_outputs[idx];
+            let lines = secondary_state
+                .line_splitter
+                .get_or_insert_with(LineSplitter::new)
+                .handle_bytes(bytes, now, self.secondary_output_byte_budget);
+            for line in lines {
+                self.combined_lines
+                    .push_back((secondary_state.title.clone(), line));
+            }
+            let excess = self.combined_lines.len().saturating_sub(max_lines);
+            self.combined_lines.drain(..excess);
+        }
+        Ok(self)
+    }
+
+    /// Shorthand for [State::handle_secondary_bytes] followed by [State::render].
+    pub fn handle_secondary_bytes_and_render(
+        &mut self,
+        id: &SecondaryOutputId,
+        bytes: &[u8],
+    ) -> Result<&mut Self> {
+        self.handle_secondary_bytes(id, bytes)?.render()?;
+        Ok(self)
+    }
+
+    /// Same as [State::handle_secondary_bytes].
+    pub fn write_secondary_bytes(
+        &mut self,
+        id: &SecondaryOutputId,
+        bytes: &[u8],
+    ) -> Result<&mut Self> {
+        self.handle_secondary_bytes(id, bytes)
+    }
+
+    /// Changes how bytes sent to the output are interpreted, discarding anything received so far.
+    pub fn set_secondary_output_mode(
+        &mut self,
+        id: &SecondaryOutputId,
+        mode: SecondaryMode,
+    ) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].set_mode(mode, self.secondary_output_max_lines);
+        Ok(self)
+    }
+
+    /// Discards everything received by the output so far, eg when the command it's showing is
+    /// rerun. Bookmarks and named ranges go too, unless [State::set_persist_bookmarks_on_clear] is
+    /// on.
+    pub fn secondary_output_clear_buffer(&mut self, id: &SecondaryOutputId) -> Result<&mut Self> {
+        let idx = self.secondary_output_position(id)?;
+        let secondary_state = &mut self.secondary_outputs[idx];
+        secondary_state.clear_buffer(self.secondary_output_max_lines);
+        if !self.persist_bookmarks_on_clear {
+            secondary_state.bookmarks.clear();
+            secondary_state.named_ranges.clear();
+        }
+        Ok(self)
+    }
+
+    /// Shows a bar in the output's header which fills up as its elapsed time approaches
+    /// `max_duration`, for outputs which don't report their own progress.
+    pub fn set_secondary_output_time_bar(
+        &mut self,
+        id: &SecondaryOutputId,
+        max_duration: Duration,
+    ) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].time_bar_max_duration = Some(max_duration);
+        Ok(())
+    }
+
+    /// Shows a one column scrollbar to the right of the output's content when it's expanded. The
+    /// content is laid out a column narrower to make room.
+    pub fn set_secondary_output_show_scrollbar(
+        &mut self,
+        id: &SecondaryOutputId,
+        show: bool,
+    ) -> Result<()> {
+        let idx = self.secondary_output_position(id)?;
+        self.secondary_outputs[idx].show_scrollbar = show;
+        Ok(())
+    }
+
+    /// Estimates when the output will have sent `expected_total_bytes`, assuming bytes keep
+    /// arriving at the average rate so far. Returns `None` if nothing has arrived yet.
+    pub fn secondary_output_estimated_finish_time(
+        &self,
+        id: &SecondaryOutputId,
+        expected_total_bytes: usize,
+    ) -> Result<Option<SystemTime>> {
+        let secondary_state = &self.secondary_outputs[self.secondary_output_position(id)?];
+        let bytes_received = secondary_state.bytes_received;
+        if bytes_received >= expected_total_bytes {
+            return Ok(Some(SystemTime::now()));
+        }
+        let elapsed = secondary_state
+            .timer
+            .elapsed(self.now(), false)
+            .as_secs_f64();
+        let byte_rate = bytes_received as f64 / elapsed;
+        if !byte_rate.is_finite() || byte_rate <= 0.0 {
+            return Ok(None);
+        }
+        let remaining = (expected_total_bytes - bytes_received) as f64 / byte_rate;
+        Ok(Some(SystemTime::now() + Duration::from_secs_f64(remaining)))
+    }
+
+    pub fn move_cursor_down(&mut self) -> &mut Self {
+        self.move_cursor_to(self.secondary_output_selected_index + 1)
+    }
+
+    /// Moves the cursor directly to the output at `index`, clamping to the end of the list. Mostly
+    /// useful for reaching outputs outside of the virtual list viewport.
+    pub fn move_cursor_to(&mut self, index: usize) -> &mut Self {
+        self.secondary_output_selected_index =
+            index.min(self.secondary_outputs.len().saturating_sub(1));
+        self.last_move_was_clamped = self.secondary_output_selected_index != index;
+        self.notify_selection_changed();
+        self
+    }
+
+    pub fn move_cursor_up(&mut self) -> &mut Self {
+        self.last_move_was_clamped = self.secondary_output_selected_index == 0;
+        self.secondary_output_selected_index =
+            self.secondary_output_selected_index.saturating_sub(1);
+        self.notify_selection_changed();
+        self
+    }
+
+    /// Swaps the selected output with the one above it, keeping it selected. Like the cursor
+    /// movements, this records whether it was clamped at the top of the list.
+    pub fn move_selected_output_up(&mut self) -> &mut Self {
+        let idx = self.secondary_output_selected_index;
+        // Outputs can't be moved past the pinned ones (or vice versa)
+        self.last_move_was_clamped = idx == 0
+            || self.secondary_outputs[idx].pinned != self.secondary_outputs[idx - 1].pinned;
+        if !self.last_move_was_clamped {
+            self.secondary_outputs.swap(idx, idx - 1);
+            self.secondary_output_selected_index -= 1;
+        }
+        self
+    }
+
+    /// Swaps the selected output with the one below it, keeping it selected. Like the cursor
+    /// movements, this records whether it was clamped at the bottom of the list.
+    pub fn move_selected_output_down(&mut self) -> &mut Self {
+        let idx = self.secondary_output_selected_index;
+        self.last_move_was_clamped = idx + 1 >= self.secondary_outputs.len()
+            || self.secondary_outputs[idx].pinned != self.secondary_outputs[idx + 1].pinned;
+        if !self.last_move_was_clamped {
+            self.secondary_outputs.swap(idx, idx + 1);
+            self.secondary_output_selected_index += 1;
+        }
+        self
+    }
+
+    /// Whether the last cursor movement ran into the start or end of the list, rather than
+    /// moving where it was asked to. Useful for giving feedback, eg flashing or a bell.
+    pub fn was_last_move_clamped(&self) -> bool {
+        self.last_move_was_clamped
+    }
+
+    /// Moves the cursor to the next output (in the order they're shown) which `predicate` accepts,
+    /// wrapping around to the start of the list. Returns whether any output was accepted, leaving
+    /// the cursor where it was if not. If only the selected output is accepted, the cursor stays
+    /// on it.
+    pub fn select_next_matching(
+        &mut self,
+        predicate: impl Fn(&SecondaryOutputInfo) -> bool,
+    ) -> bool {
+        let now = self.now();
+        let len = self.secondary_outputs.len();
+        let next = (1..=len)
+            .map(|offset| (self.secondary_output_selected_index + offset) % len)
+            .find(|idx| predicate(&self.secondary_output_info(&self.secondary_outputs[*idx], now)));
+        match next {
+            Some(idx) => {
+                self.move_cursor_to(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor to the next output which failed (see [SecondaryOutputStatus::failed]), see
+    /// [State::select_next_matching].
+    pub fn select_next_failed(&mut self) -> bool {
+        self.select_next_matching(|info| {
+            info.completed
+                && SecondaryOutputStatus::Finished {
+                    exit_code: info.exit_code,
+                }
+                .failed()
+        })
+    }
+
+    /// Moves the cursor to the next output which is still running but hasn't received anything
+    /// for a while (see [State::set_stall_threshold]), see [State::select_next_matching].
+    pub fn select_next_stalled(&mut self) -> bool {
+        let threshold = self.stall_threshold;
+        self.select_next_matching(|info| !info.completed && info.since_last_output >= threshold)
+    }
+
+    /// The ID and title of every output, in the order they're shown.
+    pub fn secondary_output_titles(&self) -> impl Iterator<Item = (SecondaryOutputId, &str)> {
+        self.secondary_outputs
+            .iter()
+            .map(|secondary_state| (secondary_state.id, secondary_state.title.as_str()))
+    }
+
+    /// The ID of the output the cursor is on, if there are any outputs.
+    pub fn selected_secondary_output_id(&self) -> Option<SecondaryOutputId> {
+        self.secondary_outputs
+            .get(self.secondary_output_selected_index)
+            .map(|secondary_state| secondary_state.id)
+    }
+
+    /// Whether the cursor is on the given output. Always false if there are no outputs.
+    pub fn is_secondary_output_selected(&self, id: SecondaryOutputId) -> bool {
+        self.selected_secondary_output_id() == Some(id)
+    }
+
+    /// Whether the output is expanded to show its content, see
+    /// [State::toggle_current_selection_expanded].
+    pub fn is_secondary_output_expanded(&self, id: SecondaryOutputId) -> Result<bool> {
+        let idx = self.secondary_output_position(&id)?;
+        Ok(self.secondary_outputs[idx].expanded())
+    }
+
+    /// Lets the state know how big the terminal is (in columns and rows), which enables layout
+    /// that depends on the terminal size.
+    pub fn set_terminal_size(&mut self, cols: u16, rows: u16) -> &mut Self {
+        self.terminal_size = Some((cols, rows));
+        self
+    }
+
+    /// Handles the terminal being resized to `new_cols` columns and `new_rows` rows, redrawing the
+    /// secondary outputs to fit. Outputs' screens are resized to the new width too, including
+    /// outputs created afterwards.
+    ///
+    /// Primary output is only kept until it's written out, so where it leaves the cursor can't be
+    /// worked out again from scratch at the new width. Instead the tracked column is clamped to
+    /// the new width, which is wrong if the terminal rewraps lines which no longer fit. Likewise,
+    /// if the terminal rewraps what the last render drew, clearing it may leave some of it behind.
+    /// [State::force_repaint] can clean up after either.
+    pub fn handle_resize(&mut self, new_rows: u16, new_cols: u16) -> Result<&mut Self> {
+        // Clear what was drawn at the old size before anything depends on the new one
+        self.clear_secondary_outputs()?;
+        self.set_terminal_size(new_cols, new_rows);
+        self.secondary_screen_cols = Some(new_cols);
+        for secondary_state in &mut self.secondary_outputs {
+            secondary_state.set_screen_cols(new_cols);
+        }
+        let (x, y) = self.primary_output_final_cursor_offset;
+        self.primary_output_final_cursor_offset = (x.min(new_cols), y);
+        self.render()?;
+        Ok(self)
+    }
+
+    /// Overrides where tabs in the primary output stop, as 0-based columns. The primary output can
+    /// also add stops itself (with HTS).
+    pub fn set_tab_stops(&mut self, stops: Vec<u16>) -> &mut Self {
+        self.primary_tab_stops.replace(stops);
+        self
+    }
+
+    /// Where the primary output has left the cursor, as of the last time its bytes were written
+    /// out (see [State::render]). This is `(column, lines_from_bottom)`: the 0-based column, and
+    /// how many lines above the last line of the primary output the cursor is, so `(0, 0)` is the
+    /// start of the last line. It's not relative to the top of the terminal, since where the
+    /// primary output started on screen isn't known.
+    pub fn primary_output_cursor_position(&self) -> (u16, u16) {
+        self.primary_output_final_cursor_offset
+    }
+
+    /// Whether the primary output wants the cursor shown, ie it hasn't hidden it with
+    /// `CSI ?25l` (or has since shown it again).
+    pub fn primary_cursor_visible(&self) -> bool {
+        self.primary_cursor_visible
+    }
+
+    /// Removes all tab stops, so tabs in the primary output go straight to the end of the line.
+    pub fn clear_tab_stops(&mut self) -> &mut Self {
+        self.primary_tab_stops.replace([]);
+        self
+    }
+
+    /// Drops any styling from secondary outputs' content, eg when the output is going to a log
+    /// file rather than a terminal.
+    pub fn set_strip_secondary_colors(&mut self, strip: bool) -> &mut Self {
+        self.strip_secondary_colors = strip;
+        self
+    }
+
+    /// Whether to show how long each output has been running for in its header, eg when the
+    /// outputs are steps in a list rather than long running processes.
+    pub fn set_show_elapsed(&mut self, show: bool) -> &mut Self {
+        self.show_elapsed = show;
+        self
+    }
+
+    /// Limits how many bytes each output can be sent at once, or hold on to without parsing them,
+    /// so a runaway process can't use up unbounded memory between renders. Beyond that the oldest
+    /// bytes are dropped, and the output shows that it was truncated. See
+    /// [SecondaryOutputInfo::dropped_bytes].
+    pub fn set_secondary_output_byte_budget(&mut self, budget: usize) -> &mut Self {
+        self.secondary_output_byte_budget = budget;
+        self
+    }
+
+    /// Changes how many lines of each expanded output are shown, including for outputs which
+    /// already exist. How much history outputs keep is based on this too, but that only changes
+    /// what's kept of output from here on; content they already have isn't trimmed or restored.
+    pub fn set_global_secondary_output_max_lines(&mut self, max_lines: usize) -> &mut Self {
+        self.secondary_output_max_lines = max_lines;
+        for secondary_state in &mut self.secondary_outputs {
+            secondary_state.set_max_lines(max_lines);
+        }
+        self
+    }
+
+    /// How precisely to show the elapsed time. Anything finer than seconds is measured from when
+    /// the output was actually created, rather than ticking in sync with the other outputs.
+    pub fn set_elapsed_precision(&mut self, precision: ElapsedPrecision) -> &mut Self {
+        self.elapsed_precision = precision;
+        self
+    }
+
+    /// How to show elapsed times once they get past a minute, when shown in whole seconds (see
+    /// [State::set_elapsed_precision]). The summary line (see [State::set_summary_enabled])
+    /// uses [DurationStyle::Clock] unless another style is chosen.
+    pub fn set_duration_style(&mut self, style: DurationStyle) -> &mut Self {
+        self.duration_style = style;
+        self
+    }
+
+    /// Renders the elapsed time against the right edge of the terminal rather than before the
+    /// title. Has no effect unless the terminal size is known.
+    pub fn set_elapsed_right_align(&mut self, enabled: bool) -> &mut Self {
+        self.elapsed_right_align = enabled;
+        self
+    }
+
+    /// Rings the terminal's bell (once per render) when any secondary output rings its bell.
+    /// Either way, outputs which rang their bell are marked for a little while.
+    pub fn set_forward_bell(&mut self, forward: bool) -> &mut Self {
+        self.forward_bell = forward;
+        self
+    }
+
+    /// Hides the cursor while rendering secondary outputs, so it doesn't flicker across them.
+    /// Afterwards it's only shown again if the primary output hasn't hidden it.
+    pub fn set_hide_cursor_while_rendering(&mut self, hide: bool) -> &mut Self {
+        self.hide_cursor_while_rendering = hide;
+        self
+    }
+
+    /// How long to mark outputs which rang their bell for.
+    pub fn set_bell_indicator_duration(&mut self, duration: Duration) -> &mut Self {
+        self.bell_indicator_duration = duration;
+        self
+    }
+
+    /// How long a running output has to go without any output before it's considered stalled,
+    /// see [State::select_next_stalled].
+    pub fn set_stall_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.stall_threshold = threshold;
+        self
+    }
+
+    /// Whether to keep bookmarks and named ranges when [State::secondary_output_clear_buffer] is
+    /// called, for when the new content is expected to line up with the old.
+    pub fn set_persist_bookmarks_on_clear(&mut self, persist: bool) -> &mut Self {
+        self.persist_bookmarks_on_clear = persist;
+        self
+    }
+
+    /// Only render (at most) `visible_rows` outputs, scrolling through the list to follow the
+    /// selection. Intended for when there are far more outputs than fit on the screen.
+    pub fn set_virtual_list_viewport(&mut self, visible_rows: u16) -> &mut Self {
+        self.secondary_output_viewport = Some(visible_rows);
+        self
+    }
+
+    /// Go back to rendering every output.
+    pub fn clear_virtual_list_viewport(&mut self) -> &mut Self {
+        self.secondary_output_viewport = None;
+        self
+    }
+
+    /// Where the last render drew each output. Empty before the first render, and once what was
+    /// drawn has been cleared away, eg by primary output being written.
+    pub fn last_frame_layout(&self) -> &FrameLayout {
+        &self.last_frame_layout
+    }
+
+    /// Turns on reporting mouse events from the terminal, so outputs can be clicked on (see
+    /// [State::handle_mouse_event]), or back off. The terminal doesn't turn it off by itself, so
+    /// it should be turned off before exiting.
+    pub fn set_mouse_capture(&mut self, capture: bool) -> Result<&mut Self> {
+        let backend = self.backend;
+        if capture {
+            backend.enable_mouse_capture(self.output)?;
+        } else {
+            backend.disable_mouse_capture(self.output)?;
+            // Turning capture off turns off every mode, including any the primary output wants
+            if let Some(mode) = self.primary_mouse_modes.reporting {
+                backend.set_private_mode(self.output, mode, true)?;
+            }
+            if self.primary_mouse_modes.sgr {
+                backend.set_private_mode(self.output, 1006, true)?;
+            }
+        }
+        self.mouse_capture = capture;
+        Ok(self)
+    }
+
+    /// Clicking an output's header selects it and toggles whether it's expanded, clicking its
+    /// content just selects it, and the wheel scrolls expanded outputs. Events anywhere other than
+    /// the secondary outputs are for the primary output, if it asked for them.
+    ///
+    /// There's no telling where the secondary outputs are on the screen from the output alone, so
+    /// `cursor_row` is which row of the terminal the cursor is on, eg from
+    /// [crossterm::cursor::position]. After rendering, that's where the primary output left it,
+    /// or the row just below the secondary outputs once the primary output has finished.
+    pub fn handle_mouse_event(&mut self, event: MouseEvent, cursor_row: u16) -> MouseOutcome {
+        let frame_rows = self.last_frame_layout.extra_lines;
+        let frame_row = if self.cursor_parked {
+            let (_, y) = self.primary_output_final_cursor_offset;
+            usize::from(event.row).checked_sub(usize::from(cursor_row) + usize::from(y) + 1)
+        } else {
+            (usize::from(event.row) + frame_rows).checked_sub(usize::from(cursor_row))
+        }
+        .filter(|row| *row < frame_rows);
+        let frame_row = match frame_row {
+            Some(frame_row) => frame_row,
+            None => {
+                return match self.primary_mouse_modes.encode(&event) {
+                    Some(bytes) => MouseOutcome::Forward(bytes),
+                    None => MouseOutcome::Ignored,
+                }
+            }
+        };
+        // Rows which aren't part of an output, eg the summary line, don't do anything
+        let rendered = self.last_frame_layout.outputs.iter().find(|rendered| {
+            rendered.header_row == frame_row || rendered.content_rows.contains(&frame_row)
+        });
+        let (id, on_header) = match rendered {
+            Some(rendered) => (rendered.id, rendered.header_row == frame_row),
+            None => return MouseOutcome::Ignored,
+        };
+        let idx = match self.secondary_output_position(&id) {
+            Ok(idx) => idx,
+            Err(_) => return MouseOutcome::Ignored,
+        };
+        let expanded = self.secondary_outputs[idx].expanded();
+        let scrolled = match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.move_cursor_to(idx);
+                if on_header {
+                    self.toggle_current_selection_expanded();
+                }
+                return MouseOutcome::Handled;
+            }
+            MouseEventKind::ScrollUp if expanded => {
+                self.scroll_secondary_output_up(&id, MOUSE_SCROLL_LINES)
+            }
+            MouseEventKind::ScrollDown if expanded => {
+                self.scroll_secondary_output_down(&id, MOUSE_SCROLL_LINES)
+            }
+            _ => return MouseOutcome::Ignored,
+        };
+        scrolled.expect("Output should exist");
+        MouseOutcome::Handled
+    }
+
+    pub fn toggle_current_selection_expanded(&mut self) -> &mut Self {
+        if let Some(secondary_state) = self
+            .secondary_outputs
+            .get_mut(self.secondary_output_selected_index)
+        {
+            secondary_state.set_expanded(!secondary_state.expanded());
+        }
+        self
+    }
+
+    /// Does what a key bound to `action` should do (see [KeyBindings](crate::KeyBindings)).
+    /// Quitting is left to the caller, so [UiAction::Quit] does nothing.
+    pub fn handle_ui_action(&mut self, action: UiAction) -> &mut Self {
+        match action {
+            UiAction::CursorUp => {
+                self.move_cursor_up();
+            }
+            UiAction::CursorDown => {
+                self.move_cursor_down();
+            }
+            UiAction::ToggleExpand => {
+                self.toggle_current_selection_expanded();
+            }
+            UiAction::ExpandAll | UiAction::CollapseAll => {
+                for secondary_state in &mut self.secondary_outputs {
+                    secondary_state.set_expanded(action == UiAction::ExpandAll);
+                }
+            }
+            UiAction::ScrollUp | UiAction::ScrollDown => {
+                if let Some(id) = self.selected_secondary_output_id() {
+                    let scrolled = if action == UiAction::ScrollUp {
+                        self.scroll_secondary_output_up(&id, 1)
+                    } else {
+                        self.scroll_secondary_output_down(&id, 1)
+                    };
+                    scrolled.expect("Selected output should exist");
+                }
+            }
+            UiAction::SelectNextFailed => {
+                self.select_next_failed();
+            }
+            UiAction::Quit => {}
+        }
+        self
+    }
+}
+
+/// Only a summary of each output is shown, rather than everything in its buffer.
+impl<W: Write + std::fmt::Debug> std::fmt::Debug for State<'_, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let now = self.now();
+        f.debug_struct("State")
+            .field("output", &self.output)
+            .field("terminal_size", &self.terminal_size)
+            .field("selected_index", &self.secondary_output_selected_index)
+            .field(
+                "secondary_outputs",
+                &self
+                    .secondary_outputs
+                    .iter()
+                    .map(|secondary_state| secondary_state.debug_at(now))
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Writing to a [State] handles the bytes as primary output (see [State::write_primary_bytes]),
+/// and flushing it renders.
+impl<W: Write> Write for State<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_primary_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.render().map_err(|error| {
+            error
+                .downcast::<std::io::Error>()
+                .unwrap_or_else(|error| std::io::Error::new(std::io::ErrorKind::Other, error))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::backend::RenderBackend;
+    use crate::state::{SecondaryOutputId, State, StateBuilder};
+    use crate::testing;
+    #[allow(unused_imports)] // IntelliJ gets confused here
+    use insta::{assert_snapshot, with_settings};
+
+    const TEST_SECONDARY_OUTPUT_MAX_LINES: usize = 3;
+
+    macro_rules! assert_state_output {
+        ($f:expr) => {
+            assert_state_output!(TEST_SECONDARY_OUTPUT_MAX_LINES, $f);
+        };
+        ($max_lines:expr, $f:expr) => {
+            let output = get_state_output($max_lines, $f);
+            // The ANSI backend should write exactly the same thing
+            let ansi_output = get_state_output_with_backend($max_lines, RenderBackend::Ansi, $f);
+            assert_eq!(ansi_output, output);
+            with_settings!({
+                description => stringify!($f),
+                omit_expression => true
+            }, {
+                assert_snapshot!(format!("# Rendered:\n```\n{}\n```\n\n\n# Raw:\n```\n{}\n```", rasterize_output(&output), output));
+            });
+
+        };
+    }
+
+    fn rasterize_output(output: &str) -> String {
+        testing::rasterize(output.as_bytes())
+    }
+
+    fn get_state_output(max_lines: usize, f: impl FnOnce(&mut State<Vec<u8>>)) -> String {
+        testing::render_state_with_max_lines(max_lines, f)
+    }
+
+    fn get_state_output_with_backend(
+        max_lines: usize,
+        backend: RenderBackend,
+        f: impl FnOnce(&mut State<Vec<u8>>),
+    ) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        {
+            let mut state = StateBuilder::new()
+                .secondary_output_max_lines(max_lines)
+                .render_backend(backend)
+                .build(&mut output);
+            f(&mut state);
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn secondary_output_id_round_trips_through_display() {
+        let mut next_id = SecondaryOutputId::default();
+        next_id.next_id();
+        let id = next_id.next_id();
+        assert_eq!(id.to_string(), "1");
+        assert_eq!("1".parse::<SecondaryOutputId>().unwrap(), id);
+        assert!("".parse::<SecondaryOutputId>().is_err());
+        assert!("-1".parse::<SecondaryOutputId>().is_err());
+        assert_eq!(
+            "output 1"
+                .parse::<SecondaryOutputId>()
+                .unwrap_err()
+                .to_string(),
+            "Invalid output ID \"output 1\": invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn secondary_output_ids_work_as_map_keys() {
+        let mut output: Vec<u8> = Vec::new();
+        let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+        let mut exit_codes = std::collections::HashMap::new();
+        let first = state.new_secondary_output("first".into());
+        let second = state.new_secondary_output("second".into());
+        exit_codes.insert(first, 0);
+        exit_codes.insert(second, 1);
+        exit_codes.insert(first, 2);
+        assert_eq!(exit_codes.len(), 2);
+        assert_eq!(exit_codes[&first], 2);
+        assert_eq!(exit_codes[&"1".parse().unwrap()], 1);
+    }
+
+    #[test]
+    fn secondary_output_ids_order_by_creation() {
+        let mut output: Vec<u8> = Vec::new();
+        let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+        let first = state.new_secondary_output("first".into());
+        let second = state.new_secondary_output("second".into());
+        let third = state.new_secondary_output("third".into());
+        let mut ids = vec![third, first, second];
+        ids.sort();
+        assert_eq!(ids, vec![first, second, third]);
+        assert!(first < second);
+        assert_eq!(third.max(second), third);
+    }
+
+    #[test]
+    fn state_can_move_between_threads() {
+        fn assert_send<T: Send>() {}
+        assert_send::<State<'static, Vec<u8>>>();
+        assert_send::<StateBuilder>();
+    }
+
+    mod primary_output {
+        use super::*;
+        use crate::state::{EofIndicatorPosition, PrimaryQuery};
+
+        #[test]
+        fn handle_and_render_matches_separate_calls() {
+            let separate = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.new_secondary_output("secondary".into());
+                state.handle_primary_bytes(b"abc\r\n").render().unwrap();
+                state
+                    .handle_primary_bytes(b"def")
+                    .handle_primary_bytes(b"ghi")
+                    .render()
+                    .unwrap();
+            });
+            let combined = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes_and_render(b"abc\r\n")
+                    .unwrap()
+                    .handle_primary_bytes(b"def")
+                    .handle_primary_bytes_and_render(b"ghi")
+                    .unwrap();
+            });
+            assert_eq!(combined, separate);
+        }
+
+        #[test]
+        fn restores_cursor_after_line_feeds() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state.handle_primary_bytes(b"abc\r\ndef").render().unwrap();
+                // The line feed moved the cursor down to the bottom of the primary output, so
+                // the offset from the bottom is still 0 and "ghi" continues after "def".
+                assert_eq!(state.primary_output_final_cursor_offset, (3, 0));
+                state.handle_primary_bytes(b"ghi").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (6, 0));
+            });
+        }
+
+        #[cfg(feature = "asciicast")]
+        #[test]
+        fn exports_primary_output_to_asciicast() {
+            use mock_instant::MockClock;
+            use std::time::Duration;
+
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            state.handle_primary_bytes(b"before logging ");
+            MockClock::advance(Duration::from_millis(500));
+            state
+                .set_primary_byte_log_enabled(true)
+                .handle_primary_bytes(b"abc\r\n");
+            MockClock::advance(Duration::from_millis(1250));
+            state.handle_primary_bytes(b"\x1b[1m\"bold\"");
+
+            let mut recording = Vec::new();
+            state.export_to_asciicast(&mut recording, 80, 24).unwrap();
+            assert_eq!(
+                String::from_utf8(recording).unwrap(),
+                "{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+                 [0.000000, \"o\", \"abc\\r\\n\"]\n\
+                 [1.250000, \"o\", \"\\u001b[1m\\\"bold\\\"\"]\n"
+            );
+        }
+
+        #[test]
+        fn ignores_bytes_after_eof() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes(b"abc")
+                    .handle_primary_eof()
+                    .handle_primary_bytes(b"def")
+                    .render()
+                    .unwrap();
+                assert!(state.is_primary_eof());
+                state.handle_primary_bytes(b"ghi").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (10, 0));
+            });
+        }
+
+        #[test]
+        fn switches_active_primary_output() {
+            assert_state_output!(|state| {
+                let first = state.active_primary_output();
+                let second = state.new_primary_output(Some("second".into()));
+                assert_eq!(state.primary_output_title(first).unwrap(), None);
+                assert_eq!(state.primary_output_title(second).unwrap(), Some("second"));
+                state.new_secondary_output("secondary".into());
+                // Only the active output is written out
+                state
+                    .handle_primary_bytes(b"first 1\r\n")
+                    .handle_primary_bytes_for(second, b"second 1\r\n")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                // Switching writes out what was held, and holds on to the other output
+                state
+                    .set_active_primary_output(second)
+                    .unwrap()
+                    .handle_primary_bytes(b"first 2\r\n")
+                    .handle_primary_bytes_for(second, b"second 2\r\n")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                assert_eq!(state.active_primary_output(), second);
+                state
+                    .set_active_primary_output(first)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn limits_held_primary_bytes() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let second = state.new_primary_output(None);
+                state
+                    .set_primary_hold_budget(10)
+                    .handle_primary_bytes_for(second, b"\x1b[31mdropped\r\n")
+                    .unwrap()
+                    // Only part of the first line fits, so it's dropped too
+                    .handle_primary_bytes_for(second, b"line 1\r\nline 2\r\n")
+                    .unwrap()
+                    .set_active_primary_output(second)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+            });
+            assert_eq!(output, "line 2\r\n");
+        }
+
+        #[test]
+        fn parses_each_primary_output_separately() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first = state.active_primary_output();
+            let second = state.new_primary_output(None);
+            state
+                .handle_primary_bytes(b"ab\x1b[")
+                .set_active_primary_output(second)
+                .unwrap()
+                .handle_primary_bytes_for(second, b"\r\ncd")
+                .unwrap()
+                .set_active_primary_output(first)
+                .unwrap()
+                // Finishes the first output's sequence, rather than starting with a "D"
+                .handle_primary_bytes(b"D")
+                .render()
+                .unwrap();
+            assert_eq!(state.primary_output_final_cursor_offset, (1, 0));
+        }
+
+        #[test]
+        fn write_aliases_match_handle() {
+            let f = |use_aliases: bool| {
+                get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                    let id = state.new_secondary_output("secondary".into());
+                    state.toggle_current_selection_expanded();
+                    if use_aliases {
+                        state
+                            .write_primary_bytes(b"primary\r\n")
+                            .write_secondary_bytes(&id, b"secondary\r\n")
+                            .unwrap();
+                    } else {
+                        state
+                            .handle_primary_bytes(b"primary\r\n")
+                            .handle_secondary_bytes(&id, b"secondary\r\n")
+                            .unwrap();
+                    }
+                    state.render().unwrap();
+                })
+            };
+            assert_eq!(f(true), f(false));
+        }
+
+        #[test]
+        fn writes_with_write_macros() {
+            use std::io::Write;
+
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                write!(state, "line {}\r\n", 1).unwrap();
+                write!(state, "partial line {}, ", 2).unwrap();
+                // Flushing renders
+                state.flush().unwrap();
+                writeln!(state, "finished line {}", 2).unwrap();
+                state.flush().unwrap();
+            });
+        }
+
+        #[test]
+        fn flush_returns_write_errors() {
+            use std::io::Write;
+
+            struct BrokenPipe;
+
+            impl Write for BrokenPipe {
+                fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                    Err(std::io::ErrorKind::BrokenPipe.into())
+                }
+
+                fn flush(&mut self) -> std::io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let mut output = BrokenPipe;
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            // Bytes are only written out when rendering
+            writeln!(state, "primary").unwrap();
+            assert_eq!(
+                state.flush().unwrap_err().kind(),
+                std::io::ErrorKind::BrokenPipe
+            );
+            // Renders which fail before drawing aren't counted
+            state.handle_primary_bytes(b"more");
+            assert!(state.render().is_err());
+            assert_eq!(state.dump_debug_state().render_count, 0);
+        }
+
+        #[test]
+        fn tracks_cursor_position() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert_eq!(state.primary_output_cursor_position(), (0, 0));
+            // Only updated once the bytes are written out
+            state.handle_primary_bytes(b"one\r\ntwo\r\nthree");
+            assert_eq!(state.primary_output_cursor_position(), (0, 0));
+            state.render().unwrap();
+            assert_eq!(state.primary_output_cursor_position(), (5, 0));
+            // Moving up counts from the last line
+            state.handle_primary_bytes(b"\x1b[2A\r").render().unwrap();
+            assert_eq!(state.primary_output_cursor_position(), (0, 2));
+        }
+
+        #[test]
+        fn rejects_unknown_primary_output() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut other_output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let mut other_state = State::new(&mut other_output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = other_state.new_primary_output(None);
+            assert!(state.handle_primary_bytes_for(id, b"abc").is_err());
+            assert!(state.set_active_primary_output(id).is_err());
+        }
+
+        #[test]
+        fn positions_eof_indicator() {
+            let render = |bytes: &[u8], position| {
+                let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                    state.new_secondary_output("out".into());
+                    state
+                        .set_terminal_size(14, 10)
+                        .set_eof_indicator_position(position)
+                        .handle_primary_bytes(bytes)
+                        .handle_primary_eof()
+                        .render()
+                        .unwrap();
+                    // Only shown once
+                    state.render().unwrap();
+                });
+                rasterize_output(&output)
+            };
+            assert_eq!(
+                render(b"abc", EofIndicatorPosition::EndOfLine),
+                "abc [done]\n> ---   0s out"
+            );
+            // Doesn't fit
+            assert_eq!(
+                render(b"abcdefgh", EofIndicatorPosition::EndOfLine),
+                "abcdefgh\n[done]\n> ---   0s out"
+            );
+            assert_eq!(
+                render(b"abc", EofIndicatorPosition::OwnLine),
+                "abc\n[done]\n> ---   0s out"
+            );
+            assert_eq!(
+                render(b"abc\r\n", EofIndicatorPosition::OwnLine),
+                "abc\n[done]\n> ---   0s out"
+            );
+            assert_eq!(
+                render(b"abc", EofIndicatorPosition::Hidden),
+                "abc\n> ---   0s out"
+            );
+        }
+
+        #[cfg(feature = "asciicast")]
+        #[test]
+        fn replays_exported_asciicast() {
+            use crate::asciicast::{ReplayEvent, ReplayEventKind};
+
+            let mut recording = Vec::new();
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            state
+                .set_primary_byte_log_enabled(true)
+                .handle_primary_bytes("abc\r\n🚀".as_bytes());
+            state.export_to_asciicast(&mut recording, 40, 10).unwrap();
+
+            let mut replay_output: Vec<u8> = Vec::new();
+            let (mut replay_state, events) =
+                State::replay_from_asciicast(&mut replay_output, &mut recording.as_slice())
+                    .unwrap();
+            assert_eq!(replay_state.terminal_size, Some((40, 10)));
+            assert_eq!(
+                events,
+                vec![ReplayEvent {
+                    timestamp: 0.0,
+                    kind: ReplayEventKind::Primary("abc\r\n🚀".as_bytes().to_vec()),
+                }]
+            );
+            for event in events {
+                let ReplayEventKind::Primary(bytes) = event.kind;
+                replay_state.handle_primary_bytes(&bytes);
+            }
+            replay_state.render().unwrap();
+            assert_eq!(replay_state.primary_output_final_cursor_offset, (2, 0));
+        }
+
+        #[test]
+        fn tracks_title_split_across_writes() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes(b"abc\x1b]0;my ti")
+                    .handle_primary_bytes(b"tle\x07def")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_title(), Some("my title"));
+                // Setting the title doesn't move the cursor
+                assert_eq!(state.primary_output_final_cursor_offset, (6, 0));
+                state
+                    .handle_primary_bytes(b"\x1b]2;other\x1b")
+                    .handle_primary_bytes(b"\\ghi")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_title(), Some("other"));
+                assert_eq!(state.primary_output_final_cursor_offset, (9, 0));
+            });
+        }
+
+        #[test]
+        fn tracks_progress_bars_using_absolute_columns() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                // As captured from an indicatif progress bar
+                state
+                    .set_terminal_size(40, 10)
+                    .handle_primary_bytes(b"Downloading\r\n")
+                    .handle_primary_bytes(b"\x1b[1G\x1b[2K[00:00:01] ####------ 4/10")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (26, 0));
+                state
+                    .handle_primary_bytes(b"\x1b[1G\x1b[2K[00:00:02] ########-- 8/10\x1b[11`")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (10, 0));
+            });
+        }
+
+        #[test]
+        fn tracks_absolute_rows_with_terminal_size() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.handle_primary_bytes(b"abc\x1b[8d").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (3, 0));
+                // Without it, only the column of a full position is known
+                state.handle_primary_bytes(b"\x1b[8;2f").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (1, 0));
+                state.handle_primary_bytes(b"\x1b[8;4H").render().unwrap();
+                // Rows are relative to the top of the terminal
+                state
+                    .set_terminal_size(40, 10)
+                    .handle_primary_bytes(b"\x1b[8d")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (3, 2));
+                state.handle_primary_bytes(b"\x1b[4;6f").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (5, 6));
+                state.handle_primary_bytes(b"\x1b[H").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (0, 9));
+            });
+        }
+
+        #[test]
+        fn tracks_scroll_regions() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .set_terminal_size(30, 10)
+                    .handle_primary_bytes(b"\x1b[1;9r\x1b[9d$ ls\r\nfile\x1bD\r$ ")
+                    .render()
+                    .unwrap();
+                // Moving down at the bottom of the region scrolls it rather than moving the cursor
+                assert_eq!(state.primary_output_final_cursor_offset, (2, 1));
+                state
+                    .handle_primary_bytes(b"\x1b[2d\x1bM")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (2, 9));
+                state.handle_primary_bytes(b"\x1bM").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (2, 9));
+                // Resetting the region lets the cursor move all the way down again
+                state
+                    .handle_primary_bytes(b"\x1b[r\x1b[9d\r\n")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (0, 0));
+            });
+        }
+
+        #[test]
+        fn places_secondary_outputs_after_scroll_region_prompt() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state.set_terminal_size(30, 50);
+                for i in 0..49 {
+                    state.handle_primary_bytes(format!("line {i}\r\n").as_bytes());
+                }
+                // As captured from a prompt which draws a status line at the bottom of the
+                // screen, then puts the scroll region back before running a command
+                state
+                    .handle_primary_bytes(b"\x1b[1;49r\x1b[50d\x1b[2Kstatus: ok\x1b[49d")
+                    .handle_primary_bytes(b"\r\n$ ls\x1b[r\x1b[50d\r\n")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (0, 0));
+            });
+        }
+
+        #[test]
+        fn tracks_repeated_characters() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .handle_primary_bytes(b"ab\x1b[3b\x1b[2@\x1b[P")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (5, 0));
+                // Repeats wrap like any other text
+                state
+                    .set_terminal_size(8, 10)
+                    .handle_primary_bytes(b"\x1b[4b")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (1, 0));
+            });
+        }
+
+        #[test]
+        fn tracks_wide_characters() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes("漢字".as_bytes())
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (4, 0));
+                // Combining marks don't take up a column of their own
+                state
+                    .handle_primary_bytes("e\u{301}🦀\r\n".as_bytes())
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (0, 0));
+            });
+        }
+
+        #[test]
+        fn wraps_wide_characters_which_dont_fit() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .set_terminal_size(5, 10)
+                    .handle_primary_bytes("abc漢".as_bytes())
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (5, 0));
+                state
+                    .handle_primary_bytes("\r\nabcd漢".as_bytes())
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (2, 0));
+            });
+        }
+
+        #[test]
+        fn tracks_tab_stops() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.handle_primary_bytes(b"a\t\t").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (16, 0));
+
+                state
+                    .set_tab_stops(vec![10, 4])
+                    .handle_primary_bytes(b"\ra\t")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (4, 0));
+                state.handle_primary_bytes(b"\t").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (10, 0));
+                // Nowhere left to stop, and the terminal size isn't known
+                state.handle_primary_bytes(b"\t").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (10, 0));
+
+                state
+                    .set_terminal_size(30, 10)
+                    .clear_tab_stops()
+                    .handle_primary_bytes(b"\rab\x1bH\r\t")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (2, 0));
+                state.handle_primary_bytes(b"\t").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (29, 0));
+            });
+        }
+
+        #[test]
+        fn primary_output_can_clear_tab_stops() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .set_terminal_size(30, 10)
+                    .handle_primary_bytes(b"\x1b[12G\x1bH\x1b[17G\x1b[g\ra\tb\tc\t")
+                    .render()
+                    .unwrap();
+                // Stops at 8, 11, then the one at 16 is cleared so 24 is next
+                assert_eq!(state.primary_output_final_cursor_offset, (24, 0));
+
+                state.handle_primary_bytes(b"\x1b[3g\r\t").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (29, 0));
+            });
+        }
+
+        #[test]
+        fn changes_tab_stops_without_terminal_size() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .handle_primary_bytes(b"\x1b[17G\x1b[g\x1b[21G\x1bH\ra\t\t\t")
+                    .render()
+                    .unwrap();
+                // Stops at 8, the added one at 20, then the one at 16 is cleared so 24 is next
+                assert_eq!(state.primary_output_final_cursor_offset, (24, 0));
+                state
+                    .handle_primary_bytes(b"\x1b[1001G\t")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (1008, 0));
+
+                state.handle_primary_bytes(b"\x1b[3g\r\t").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (0, 0));
+            });
+        }
+
+        #[test]
+        fn default_tabs_match_terminal() {
+            // vt100 doesn't support custom tab stops, but the defaults should match it, including
+            // stopping at the end of the line
+            let bytes = b"ab\tcd\te\tfg\t\t";
+            let mut parser = vt100::Parser::new(50, 30, 0);
+            parser.process(bytes);
+            let (_, vt100_column) = parser.screen().cursor_position();
+
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .set_terminal_size(30, 50)
+                    .handle_primary_bytes(bytes)
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (vt100_column, 0));
+            });
+        }
+
+        #[test]
+        fn keeps_cursor_hidden_by_primary_output() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .set_hide_cursor_while_rendering(true)
+                    .handle_primary_bytes(b"visible")
+                    .render()
+                    .unwrap();
+                assert!(state.primary_cursor_visible());
+                state
+                    .handle_primary_bytes(b"\x1b[?25lhidden")
+                    .render()
+                    .unwrap();
+                assert!(!state.primary_cursor_visible());
+                state.handle_primary_bytes(b"\r\n").render().unwrap();
+            });
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .set_hide_cursor_while_rendering(true)
+                    .handle_primary_bytes(b"\x1b[?25lhidden")
+                    .render()
+                    .unwrap();
+                state.handle_primary_bytes(b"more").render().unwrap();
+            });
+            assert!(!output.contains("\x1b[?25h"));
+        }
+
+        #[test]
+        fn primary_output_can_add_tab_stops() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .handle_primary_bytes(b"abc\x1bH\r\t\t\t")
+                    .render()
+                    .unwrap();
+                // The default stops are still there
+                assert_eq!(state.primary_output_final_cursor_offset, (16, 0));
+            });
+        }
+
+        #[test]
+        fn counts_ignored_sequences() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .handle_primary_bytes(b"\x1b[1mbold\x1b[0m\x1b[2J\x1b(B\x1b]8;;url\x07")
+                    .handle_primary_bytes(b"\x1bPq#0\x1b\\\x1b[3D")
+                    .render()
+                    .unwrap();
+                // Styling is kept track of, so only counts as understood
+                assert_eq!(state.ignored_sequence_count(), 4);
+                assert_eq!(state.primary_output_final_cursor_offset, (1, 0));
+            });
+        }
+
+        #[test]
+        fn sequences_which_dont_move_the_cursor_keep_pending_wrap() {
+            get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state
+                    .set_terminal_size(5, 10)
+                    .handle_primary_bytes(b"abcde\x1b]0;title\x07\x1bPq#0\x1b\\")
+                    .render()
+                    .unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (5, 0));
+                state.handle_primary_bytes(b"f").render().unwrap();
+                assert_eq!(state.primary_output_final_cursor_offset, (1, 0));
+            });
+        }
+
+        #[test]
+        fn surfaces_cursor_position_queries() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes(b"abc\r\nde\x1b[")
+                    .handle_primary_bytes(b"6nf\r\n\x1b[6n")
+                    .render()
+                    .unwrap();
+                let queries = state.take_pending_queries();
+                assert_eq!(
+                    queries,
+                    vec![
+                        PrimaryQuery::CursorPosition {
+                            column: 2,
+                            lines_from_bottom: 0
+                        },
+                        PrimaryQuery::CursorPosition {
+                            column: 0,
+                            lines_from_bottom: 0
+                        },
+                    ]
+                );
+                assert_eq!(queries[0].reply(24), b"\x1b[24;3R");
+                assert!(state.take_pending_queries().is_empty());
+            });
+        }
+
+        #[test]
+        fn parks_cursor_on_primary_output() {
+            let render_twice = |finished: bool| {
+                let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                    let id = state.new_secondary_output("secondary".into());
+                    state
+                        .handle_primary_bytes(b"abc\r\nde")
+                        .toggle_current_selection_expanded()
+                        .handle_secondary_bytes(&id, b"one\r\ntwo\r\n")
+                        .unwrap()
+                        .render()
+                        .unwrap();
+                    if finished {
+                        state.handle_primary_eof();
+                    }
+                    // Clearing away the first render starts from wherever it left the cursor
+                    state.render().unwrap();
+                });
+                let mut parser = vt100::Parser::new(50, 50, 0);
+                parser.process(output.as_bytes());
+                (parser.screen().cursor_position(), rasterize_output(&output))
+            };
+            assert_eq!(
+                render_twice(false),
+                (
+                    (1, 2),
+                    "abc\nde\n> +++   0s secondary\none\ntwo".to_string()
+                )
+            );
+            // Once the primary output has finished, the cursor's left below the outputs
+            assert_eq!(
+                render_twice(true),
+                (
+                    (5, 0),
+                    "abc\nde [done]\n> +++   0s secondary\none\ntwo".to_string()
+                )
+            );
+        }
+
+        #[test]
+        fn restores_primary_style_after_drawing() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes(b"\x1b[1;32;41mab")
+                    .toggle_current_selection_expanded()
+                    .handle_secondary_bytes(&id, b"\x1b[34mone\r\n")
+                    .unwrap()
+                    .set_debug_overlay_visible(true)
+                    .render()
+                    .unwrap();
+                state.handle_primary_bytes(b"cd\x1b[22m").render().unwrap();
+                state.handle_primary_bytes(b"ef").render().unwrap();
+            });
+            let mut parser = vt100::Parser::new(50, 50, 0);
+            parser.process(output.as_bytes());
+            let screen = parser.screen();
+            let cell = |row, col| screen.cell(row, col).unwrap();
+            for col in 0..6 {
+                assert_eq!(cell(0, col).fgcolor(), vt100::Color::Idx(2));
+                assert_eq!(cell(0, col).bgcolor(), vt100::Color::Idx(1));
+                assert_eq!(cell(0, col).bold(), col < 4);
+            }
+            // Neither the secondary output nor the space cleared for it pick up the styling
+            assert_eq!(
+                screen.contents_between(1, 0, 2, 3),
+                "> +++   0s secondary\none"
+            );
+            for (row, col) in [(1, 0), (1, 40), (2, 0), (2, 40)] {
+                assert_eq!(cell(row, col).bgcolor(), vt100::Color::Default);
+                assert!(!cell(row, col).bold());
+            }
+            assert_eq!(cell(1, 0).fgcolor(), vt100::Color::Default);
+            assert_eq!(cell(2, 0).fgcolor(), vt100::Color::Idx(4));
+        }
+
+        #[test]
+        fn keeps_secondary_style_out_of_primary_output() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("secondary".into());
+                state
+                    .handle_primary_bytes(b"ab")
+                    .toggle_current_selection_expanded()
+                    .handle_secondary_bytes(&id, b"\x1b[1;34mone\r\ntwo")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                state.handle_primary_bytes(b"cd").render().unwrap();
+            });
+            let mut parser = vt100::Parser::new(50, 50, 0);
+            parser.process(output.as_bytes());
+            let screen = parser.screen();
+            assert_eq!(
+                screen.contents_between(0, 0, 3, 3),
+                "abcd\n> +++   0s secondary\none\ntwo"
+            );
+            for col in 0..4 {
+                let cell = screen.cell(0, col).unwrap();
+                assert_eq!(cell.fgcolor(), vt100::Color::Default);
+                assert!(!cell.bold());
+            }
+            // The styling carries on from one of the output's rows to the next though
+            assert_eq!(screen.cell(3, 0).unwrap().fgcolor(), vt100::Color::Idx(4));
+        }
+
+        #[test]
+        fn flushes_primary_bytes_past_threshold() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("secondary".into());
+                state
+                    .set_primary_flush_threshold(10)
+                    .handle_primary_bytes(b"short\r\n")
+                    .render()
+                    .unwrap();
+                // Clears the secondary output and writes straight through
+                state.handle_primary_bytes(b"much longer\r\n");
+                assert!(state.primary_bytes.is_empty());
+                state.handle_primary_bytes(b"again").render().unwrap();
+            });
+        }
+
+        #[test]
+        fn flushing_primary_bytes_matches_rendering_at_end() {
+            let flood = |threshold| {
+                get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                    state.new_secondary_output("secondary".into());
+                    state
+                        .set_primary_flush_threshold(threshold)
+                        .toggle_current_selection_expanded()
+                        .render()
+                        .unwrap();
+                    for i in 0..200_000 {
+                        state.handle_primary_bytes(format!("line {i}\r\n").as_bytes());
+                        assert!(state.primary_bytes.len() < threshold);
+                    }
+                    state.render().unwrap();
+                })
+            };
+            let flushed = flood(64 * 1024);
+            let unflushed = flood(usize::MAX);
+            assert!(flushed.len() > 2_000_000);
+            assert_eq!(rasterize_output(&flushed), rasterize_output(&unflushed));
+        }
+
+        #[test]
+        fn buffers_bytes() {
+            assert_state_output!(|state| {
+                state.handle_primary_bytes("should not render".as_bytes());
+            });
+        }
+
+        #[test]
+        fn passes_through_bytes() {
+            assert_state_output!(|state| {
+                state
+                    .handle_primary_bytes("my test string\r\nhello hi".as_bytes())
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn only_renders_bytes_once() {
+            assert_state_output!(|state| {
+                state.handle_primary_bytes("no repeat ".as_bytes());
+                state.render().unwrap();
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn draws_secondary_output_after_content_and_restores_cursor_position() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("test secondary output".into());
+                state
+                    .handle_primary_bytes("abc\r\ndef\r\nghi\x1b[3D\x1b[1A\x1b[3C".as_bytes())
+                    .render()
+                    .unwrap();
+
+                state
+                    .handle_primary_bytes("123".as_bytes())
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn clears_secondary_output() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("test secondary output".into());
+                state
+                    .handle_primary_bytes("abc".as_bytes())
+                    .render()
+                    .unwrap();
+
+                state
+                    .handle_primary_bytes("def\r\n123".as_bytes())
+                    .render()
+                    .unwrap();
+            });
+        }
+    }
+
+    mod primary_output_properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn primary_bytes(csi_finals: &[char]) -> impl Strategy<Value = String> {
+            let chunk = prop_oneof![
+                "[a-z ]{1,20}",
+                Just("\t".to_string()),
+                Just("\n".to_string()),
+                Just("\r".to_string()),
+                Just("\r\n".to_string()),
+                (0u16..100, prop::sample::select(csi_finals.to_vec()))
+                    .prop_map(|(n, c)| format!("\x1b[{n}{c}")),
+            ];
+            prop::collection::vec(chunk, 0..50).prop_map(|chunks| chunks.concat())
+        }
+
+        fn rasterize_sized(output: &[u8], (cols, rows): (u16, u16)) -> String {
+            let mut parser = vt100::Parser::new(rows, cols, 0);
+            // Start at the bottom of the screen, like a terminal with some history in it. We can
+            // only track the cursor moving up past the top of the content if it's also the top
+            // of the screen.
+            parser.process("\r\n".repeat(rows as usize).as_bytes());
+            parser.process(output);
+            parser.screen().contents()
+        }
+
+        proptest! {
+            #[test]
+            fn cursor_offset_stays_within_terminal(
+                bytes in primary_bytes(&['A', 'B', 'C', 'D', 'E', 'F']),
+                cols in 10u16..100,
+                rows in 5u16..50,
+            ) {
+                let mut output = Vec::new();
+                let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+                state.set_terminal_size(cols, rows);
+                state.handle_primary_bytes(bytes.as_bytes()).render().unwrap();
+                let (x, y) = state.primary_output_final_cursor_offset;
+                prop_assert!(x <= cols, "x = {x}, cols = {cols}");
+                prop_assert!(y <= rows, "y = {y}, rows = {rows}");
+            }
+
+            #[test]
+            fn rendering_twice_matches_rendering_once(
+                // vt100 doesn't support next/previous line, so can't rasterize them
+                bytes in primary_bytes(&['A', 'B', 'C', 'D']),
+                // Leave room for at least the fixed width parts of the header
+                cols in 20u16..100,
+                rows in 5u16..50,
+            ) {
+                let render = |times: usize| {
+                    let mut output = Vec::new();
+                    let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+                    state.set_terminal_size(cols, rows);
+                    state.new_secondary_output("secondary".into());
+                    state.handle_primary_bytes(bytes.as_bytes());
+                    for _ in 0..times {
+                        state.render().unwrap();
+                    }
+                    let (_, y) = state.primary_output_final_cursor_offset;
+                    (y, rasterize_sized(&output, (cols, rows)))
+                };
+                let (y, once) = render(1);
+                // If drawing the secondary output scrolls the primary cursor off the top of the
+                // screen there's no way to get back to it.
+                prop_assume!(y + 2 < rows);
+                prop_assert_eq!(once, render(2).1);
+            }
+        }
+    }
+
+    mod secondary_output {
+        use super::*;
+        use crate::frame::{Color, Line, Span, Style};
+        use crate::keys::UiAction;
+        use crate::search::MatchLocation;
+        use crate::secondary_output::{CarriageReturnMode, SecondaryMode, SecondaryOutputStatus};
+        use crate::state::{
+            format_line_timestamp, DurationStyle, ElapsedPrecision, Instant, StateBuilder,
+            StateInvariantViolation, DEFAULT_SECONDARY_OUTPUT_MAX_LINES, LINE_TIMESTAMP_WIDTH,
+        };
+        use crate::symbols::Symbols;
+        use mock_instant::MockClock;
+        use std::time::{Duration, SystemTime};
+
+        #[test]
+        fn shows_titles_and_durations() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("first title".into());
+                MockClock::advance(Duration::from_secs(1));
+                state.new_secondary_output("second title".into());
+                MockClock::advance(Duration::from_secs(1));
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn outputs_either_side_of_a_second_tick_together() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            MockClock::advance(Duration::from_millis(999));
+            state.new_secondary_output("before".into());
+            MockClock::advance(Duration::from_millis(2));
+            state.new_secondary_output("after".into());
+            let elapsed_secs = |state: &State<Vec<u8>>| {
+                state
+                    .secondary_outputs
+                    .iter()
+                    .map(|secondary_state| {
+                        state
+                            .secondary_output_elapsed(secondary_state, Instant::now())
+                            .as_secs()
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(elapsed_secs(&state), vec![1, 0]);
+            MockClock::advance(Duration::from_millis(998));
+            assert_eq!(elapsed_secs(&state), vec![1, 0]);
+            // Both tick over on the same whole second since the state was created
+            MockClock::advance(Duration::from_millis(1));
+            assert_eq!(elapsed_secs(&state), vec![2, 1]);
+            MockClock::advance(Duration::from_millis(999));
+            assert_eq!(elapsed_secs(&state), vec![2, 1]);
+            MockClock::advance(Duration::from_millis(1));
+            assert_eq!(elapsed_secs(&state), vec![3, 2]);
+        }
+
+        #[test]
+        fn pausing_stops_elapsed_time() {
+            assert_state_output!(|state| {
+                let paused = state.new_secondary_output("paused".into());
+                state.new_secondary_output("running".into());
+                MockClock::advance(Duration::from_millis(2500));
+                state.pause_timer(&paused).unwrap().render().unwrap();
+                MockClock::advance(Duration::from_secs(10));
+                state.render().unwrap();
+                // Only the 2.5s + 1.5s spent running count
+                state.resume_timer(&paused).unwrap();
+                MockClock::advance(Duration::from_millis(1500));
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn pausing_keeps_sub_second_precision() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            state.set_elapsed_precision(ElapsedPrecision::Milliseconds);
+            let id = state.new_secondary_output("paused".into());
+            let elapsed = |state: &State<Vec<u8>>| {
+                state.secondary_output_elapsed(&state.secondary_outputs[0], Instant::now())
+            };
+            MockClock::advance(Duration::from_millis(1250));
+            state.pause_timer(&id).unwrap();
+            // Pausing twice doesn't lose anything
+            MockClock::advance(Duration::from_millis(500));
+            state.pause_timer(&id).unwrap();
+            MockClock::advance(Duration::from_millis(500));
+            assert_eq!(elapsed(&state), Duration::from_millis(1250));
+            state.resume_timer(&id).unwrap();
+            MockClock::advance(Duration::from_millis(300));
+            state.resume_timer(&id).unwrap();
+            MockClock::advance(Duration::from_millis(300));
+            assert_eq!(elapsed(&state), Duration::from_millis(1850));
+        }
+
+        #[test]
+        fn durations_change_at_same_time() {
+            assert_state_output!(|state| {
+                // Offset from any instant taken when the state was created.
+                MockClock::advance(Duration::from_millis(250));
+                state.new_secondary_output("first title".into());
+
+                // Offset by a non-whole-number of seconds
+                MockClock::advance(Duration::from_millis(500));
+                state.new_secondary_output("second title".into());
+
+                // Wait until just before the times should tick over; assumes they tick over at whole
+                // numbers of seconds from when the state was initially created.
+                MockClock::advance(Duration::from_millis(249));
+                state.render().unwrap();
+
+                // Have it tick over to the next second
+                MockClock::advance(Duration::from_millis(1));
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn shows_cursor_at_selected_index() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("one".into());
+                state.new_secondary_output("two".into());
+                state.new_secondary_output("three".into());
+                state.new_secondary_output("four".into());
+                state
+                    .move_cursor_down()
+                    .move_cursor_down()
+                    .move_cursor_down()
+                    .move_cursor_up()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn clamps_cursor_down() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("one".into());
+                state.new_secondary_output("two".into());
+                state
+                    .move_cursor_down()
+                    .move_cursor_down()
+                    .move_cursor_down()
+                    .move_cursor_down()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn clamps_cursor_up() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("one".into());
+                state.new_secondary_output("two".into());
+                state
+                    .move_cursor_down()
+                    .move_cursor_down()
+                    .move_cursor_up()
+                    .move_cursor_up()
+                    .move_cursor_up()
+                    .move_cursor_up()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn virtual_list_viewport_follows_cursor() {
+            assert_state_output!(|state| {
+                for title in ["one", "two", "three", "four", "five"] {
+                    state.new_secondary_output(title.into());
+                }
+                state.set_virtual_list_viewport(2).render().unwrap();
+                // Scrolls down only once the cursor leaves the viewport
+                state.move_cursor_down().render().unwrap();
+                state.move_cursor_down().render().unwrap();
+                // Can jump straight to outputs which aren't being rendered
+                state.move_cursor_to(4).render().unwrap();
+                // Scrolls back up
+                state.move_cursor_to(0).render().unwrap();
+            });
+        }
+
+        #[test]
+        fn right_aligns_elapsed() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("short".into());
+                state.new_secondary_output("a title much too long to fit".into());
+                MockClock::advance(Duration::from_secs(12));
+                state
+                    .set_terminal_size(30, 10)
+                    .set_elapsed_right_align(true)
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        const PROGRESS_BYTES: &[u8] =
+            b"\x1b[32mstarting\x1b[0m\r\nprogress 10%\rprogress 50%\r\x1b[2Kprogress 100%\r\ndone\r\n";
+
+        #[test]
+        fn handles_progress_as_terminal() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("terminal".into());
+                state
+                    .handle_secondary_bytes(&id, PROGRESS_BYTES)
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn handles_progress_as_overwritten_plain_lines() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("overwrite".into());
+                state
+                    .set_secondary_output_mode(
+                        &id,
+                        SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                    )
+                    .unwrap()
+                    .handle_secondary_bytes(&id, PROGRESS_BYTES)
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn handles_progress_as_separated_plain_lines() {
+            assert_state_output!(5, |state| {
+                let id = state.new_secondary_output("separate".into());
+                state
+                    .set_secondary_output_mode(
+                        &id,
+                        SecondaryMode::PlainLines(CarriageReturnMode::LineSeparator),
+                    )
+                    .unwrap()
+                    .handle_secondary_bytes(&id, PROGRESS_BYTES)
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn expanding_late_shows_same_tail_as_expanding_early() {
+            let send_lines = |state: &mut State<Vec<u8>>, id| {
+                for i in 0..5_000 {
+                    state
+                        .handle_secondary_bytes(
+                            &id,
+                            format!("\x1b[3{}mline {i}\r\n", i % 8).as_bytes(),
+                        )
+                        .unwrap();
+                }
+            };
+            let expanded_early = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("early".into());
+                state.toggle_current_selection_expanded();
+                send_lines(state, id);
+                state.render().unwrap();
+            });
+            let expanded_late = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("early".into());
+                send_lines(state, id);
+                state.toggle_current_selection_expanded().render().unwrap();
+            });
+            assert_eq!(expanded_early, expanded_late);
+            assert!(rasterize_output(&expanded_late).contains("line 4999"));
+        }
+
+        #[test]
+        fn parsing_lazily_matches_parsing_eagerly() {
+            let chunks = (0..50)
+                .map(|i| format!("\x1b[3{}mchunk {i}\r\n\x1b[0m", i % 8))
+                .collect::<Vec<_>>();
+            // Rendering after every chunk makes sure each one gets parsed as it arrives
+            let eager = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("eager".into());
+                state.toggle_current_selection_expanded();
+                for chunk in &chunks {
+                    state
+                        .handle_secondary_bytes(&id, chunk.as_bytes())
+                        .unwrap()
+                        .render()
+                        .unwrap();
+                }
+            });
+            let lazy = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("eager".into());
+                state.toggle_current_selection_expanded();
+                for chunk in &chunks {
+                    state.handle_secondary_bytes(&id, chunk.as_bytes()).unwrap();
+                }
+                state.render().unwrap();
+            });
+            assert_eq!(rasterize_output(&eager), rasterize_output(&lazy));
+            assert!(rasterize_output(&lazy).contains("chunk 49"));
+        }
+
+        #[test]
+        fn reuses_rows_when_nothing_changed() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("cached".into());
+            state
+                .handle_secondary_bytes(&id, b"some output\r\n")
+                .unwrap()
+                .toggle_current_selection_expanded();
+            let format_count =
+                |state: &State<Vec<u8>>| state.secondary_outputs[0].format_count.get();
+            for _ in 0..3 {
+                state.render().unwrap();
+            }
+            assert_eq!(format_count(&state), 1);
+
+            state.handle_secondary_bytes(&id, b"more\r\n").unwrap();
+            state.render().unwrap();
+            state.render().unwrap();
+            assert_eq!(format_count(&state), 2);
+
+            state.set_terminal_size(30, 10).render().unwrap();
+            state.render().unwrap();
+            assert_eq!(format_count(&state), 3);
+        }
+
+        #[test]
+        fn resets_colors_after_rows() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("colored".into());
+                state.new_secondary_output("after".into());
+                state
+                    .handle_secondary_bytes(&id, b"plain \x1b[31mred\r\nstill red\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn colors_do_not_bleed() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("colored".into());
+                state.new_secondary_output("after".into());
+                state
+                    .handle_primary_bytes(b"\x1b[32mprimary")
+                    .handle_secondary_bytes(&id, b"plain\r\n\x1b[31mred\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+            let mut parser = vt100::Parser::new(50, 50, 0);
+            parser.process(output.as_bytes());
+            let screen = parser.screen();
+            let fg = |row, col| screen.cell(row, col).unwrap().fgcolor();
+            // Primary color doesn't carry into the first header
+            assert_eq!(fg(1, 12), vt100::Color::Default);
+            // Secondary content keeps its color
+            assert_eq!(fg(3, 0), vt100::Color::Idx(1));
+            // But doesn't carry into the next header
+            assert_eq!(fg(4, 12), vt100::Color::Default);
+        }
+
+        #[test]
+        fn unfinished_colors_do_not_bleed() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let terminal = state.new_secondary_output("terminal".into());
+                let plain = state.new_secondary_output("plain".into());
+                state.new_secondary_output("after".into());
+                state
+                    .set_secondary_output_mode(
+                        &plain,
+                        SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                    )
+                    .unwrap()
+                    // Both end partway through setting more styling
+                    .handle_secondary_bytes(&terminal, b"plain\r\n\x1b[31mred\x1b[4")
+                    .unwrap()
+                    .handle_secondary_bytes(&plain, b"\x1b[32mgreen\x1b[4")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .move_cursor_down()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+            let mut parser = vt100::Parser::new(50, 50, 0);
+            parser.process(output.as_bytes());
+            let screen = parser.screen();
+            let cell = |row, col| screen.cell(row, col).unwrap();
+            assert_eq!(screen.contents_between(3, 0, 3, 3), "red");
+            assert_eq!(cell(3, 0).fgcolor(), vt100::Color::Idx(1));
+            for (row, col) in [(4, 12), (5, 0), (6, 12)] {
+                assert_eq!(cell(row, col).fgcolor(), vt100::Color::Default);
+                assert!(!cell(row, col).underline());
+            }
+        }
+
+        #[test]
+        fn strips_secondary_colors() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("colored".into());
+                state
+                    .set_strip_secondary_colors(true)
+                    .handle_secondary_bytes(&id, b"\x1b[1;31mred\x1b[0m plain\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn shows_elapsed_with_precision() {
+            assert_state_output!(|state| {
+                MockClock::advance(Duration::from_millis(250));
+                state.new_secondary_output("first".into());
+                MockClock::advance(Duration::from_millis(1234));
+                state.new_secondary_output("second".into());
+                MockClock::advance(Duration::from_millis(50));
+                state
+                    .set_elapsed_precision(ElapsedPrecision::Tenths)
+                    .render()
+                    .unwrap();
+                state
+                    .set_elapsed_precision(ElapsedPrecision::Milliseconds)
+                    .render()
+                    .unwrap();
+                state
+                    .set_terminal_size(30, 10)
+                    .set_elapsed_right_align(true)
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn shows_time_bar() {
+            assert_state_output!(|state| {
+                let first = state.new_secondary_output("first".into());
+                let second = state.new_secondary_output("second".into());
+                state.new_secondary_output("no bar".into());
+                state
+                    .set_secondary_output_time_bar(&first, Duration::from_secs(8))
+                    .unwrap();
+                state
+                    .set_secondary_output_time_bar(&second, Duration::from_secs(2))
+                    .unwrap();
+                state.render().unwrap();
+                MockClock::advance(Duration::from_secs(3));
+                // Second is overdue, so its bar stays full
+                state.render().unwrap();
+                state
+                    .set_terminal_size(30, 10)
+                    .set_elapsed_right_align(true)
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn uses_ascii_symbols() {
+            assert_state_output!(|state| {
+                state.symbols = Symbols::ASCII;
+                state.new_secondary_output("collapsed".into());
+                state.new_secondary_output("expanded with a long title".into());
+                state
+                    .move_cursor_down()
+                    .toggle_current_selection_expanded()
+                    .set_terminal_size(25, 10)
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn picks_symbols_from_term() {
+            assert_eq!(Symbols::for_term(Some("dumb")), Symbols::ASCII);
+            assert_eq!(
+                Symbols::for_term(Some("xterm-256color")),
+                Symbols::default()
+            );
+            assert_eq!(Symbols::for_term(None), Symbols::default());
+        }
+
+        #[test]
+        fn configures_from_env_vars() {
+            let builder = StateBuilder::from_vars(|name| {
+                match name {
+                    "TERM" => Some("xterm"),
+                    "MULTI_OUTPUT_VIEWER_MAX_LINES" => Some("5"),
+                    "MULTI_OUTPUT_VIEWER_NO_COLOR" => Some("true"),
+                    "MULTI_OUTPUT_VIEWER_NO_UNICODE" => Some("1"),
+                    "MULTI_OUTPUT_VIEWER_MAX_SECONDARY" => Some(" 20 "),
+                    _ => None,
+                }
+                .map(String::from)
+            });
+            assert_eq!(builder.secondary_output_max_lines, 5);
+            assert!(builder.strip_secondary_colors);
+            assert_eq!(builder.symbols, Symbols::ASCII);
+            assert_eq!(builder.virtual_list_viewport, Some(20));
+        }
+
+        #[test]
+        fn ignores_malformed_env_vars() {
+            let builder = StateBuilder::from_vars(|name| {
+                match name {
+                    "TERM" => Some("dumb"),
+                    "MULTI_OUTPUT_VIEWER_MAX_LINES" => Some("lots"),
+                    "MULTI_OUTPUT_VIEWER_NO_COLOR" => Some("maybe"),
+                    "MULTI_OUTPUT_VIEWER_NO_UNICODE" => Some("0"),
+                    "MULTI_OUTPUT_VIEWER_MAX_SECONDARY" => Some("-1"),
+                    _ => None,
+                }
+                .map(String::from)
+            });
+            assert_eq!(
+                builder.secondary_output_max_lines,
+                DEFAULT_SECONDARY_OUTPUT_MAX_LINES
+            );
+            assert!(!builder.strip_secondary_colors);
+            // Turning off the override doesn't make a dumb terminal support Unicode
+            assert_eq!(builder.symbols, Symbols::ASCII);
+            assert_eq!(builder.virtual_list_viewport, None);
+        }
+
+        #[test]
+        fn handles_removing_all_outputs() {
+            assert_state_output!(|state| {
+                let first = state.new_secondary_output("first".into());
+                let second = state.new_secondary_output("second".into());
+                state.move_cursor_down().render().unwrap();
+                state
+                    .remove_secondary_output(second)
+                    .unwrap()
+                    .remove_secondary_output(first)
+                    .unwrap();
+                assert_eq!(state.secondary_output_selected_index, 0);
+                state
+                    .move_cursor_down()
+                    .move_cursor_up()
+                    .move_cursor_to(5)
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+                // New outputs are selectable as usual
+                state.new_secondary_output("third".into());
+                state.toggle_current_selection_expanded().render().unwrap();
+            });
+        }
+
+        #[test]
+        fn detects_terminal_support() {
+            let detect = |vars: &[(&str, &str)], raw_mode| {
+                let builder = StateBuilder::detect_from_vars(
+                    |name| {
+                        vars.iter()
+                            .find(|(var, _)| *var == name)
+                            .map(|(_, value)| value.to_string())
+                    },
+                    raw_mode,
+                );
+                (builder.strip_secondary_colors, builder.symbols)
+            };
+            assert_eq!(
+                detect(&[("TERM", "xterm-256color")], false),
+                (false, Symbols::default())
+            );
+            assert_eq!(
+                detect(&[("TERM", "xterm"), ("NO_COLOR", "1")], false),
+                (true, Symbols::default())
+            );
+            // An empty `NO_COLOR` doesn't count
+            assert_eq!(
+                detect(&[("TERM", "xterm"), ("NO_COLOR", "")], false),
+                (false, Symbols::default())
+            );
+            assert_eq!(detect(&[("TERM", "dumb")], true), (true, Symbols::ASCII));
+            assert_eq!(detect(&[], false), (true, Symbols::default()));
+            assert_eq!(detect(&[], true), (false, Symbols::default()));
+            assert_eq!(
+                detect(&[("COLORTERM", "truecolor")], false),
+                (false, Symbols::default())
+            );
+        }
+
+        #[test]
+        fn moves_selected_output() {
+            assert_state_output!(|state| {
+                state.new_secondary_output("first".into());
+                state.new_secondary_output("second".into());
+                let third = state.new_secondary_output("third".into());
+                state
+                    .move_cursor_to(2)
+                    .move_selected_output_up()
+                    .render()
+                    .unwrap();
+                state.move_selected_output_up().render().unwrap();
+                assert_eq!(state.selected_secondary_output_id(), Some(third));
+                assert!(state.move_selected_output_up().was_last_move_clamped());
+                state
+                    .move_cursor_down()
+                    .move_selected_output_down()
+                    .render()
+                    .unwrap();
+                assert!(state.move_selected_output_down().was_last_move_clamped());
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn pinned_outputs_render_first() {
+            assert_state_output!(|state| {
+                let first = state.new_secondary_output("first".into());
+                state.new_secondary_output("second".into());
+                let third = state.new_secondary_output("third".into());
+                state.set_pinned(third, true).unwrap().render().unwrap();
+                // The selection stays on the same output, and moves in display order
+                assert_eq!(state.selected_secondary_output_id(), Some(first));
+                state.move_cursor_up().render().unwrap();
+                assert_eq!(state.selected_secondary_output_id(), Some(third));
+                // Can't be moved below the unpinned outputs
+                assert!(state.move_selected_output_down().was_last_move_clamped());
+                state.set_pinned(third, false).unwrap().render().unwrap();
+                assert_eq!(state.selected_secondary_output_id(), Some(third));
+            });
+        }
+
+        #[test]
+        fn unpinned_output_cant_move_above_pinned_ones() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first = state.new_secondary_output("first".into());
+            let second = state.new_secondary_output("second".into());
+            state.set_pinned(first, true).unwrap();
+            state.move_cursor_to(1);
+            assert!(state.move_selected_output_up().was_last_move_clamped());
+            assert_eq!(state.selected_secondary_output_id(), Some(second));
+            assert_eq!(
+                state
+                    .secondary_output_titles()
+                    .map(|(id, _)| id)
+                    .collect::<Vec<_>>(),
+                vec![first, second]
+            );
+        }
+
+        #[test]
+        fn pinned_outputs_stay_in_viewport() {
+            assert_state_output!(|state| {
+                let ids = ["one", "two", "three", "four", "five"]
+                    .map(|title| state.new_secondary_output(title.into()));
+                state
+                    .set_virtual_list_viewport(3)
+                    .set_pinned(ids[1], true)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                state.move_cursor_to(4).render().unwrap();
+                assert_eq!(state.selected_secondary_output_id(), Some(ids[4]));
+            });
+        }
+
+        #[test]
+        fn shows_summary_line() {
+            assert_state_output!(|state| {
+                state
+                    .set_summary_enabled(true)
+                    .set_terminal_size(50, 50)
+                    .handle_primary_bytes(b"no outputs yet")
+                    .render()
+                    .unwrap();
+                let ids = ["one", "two", "three", "four"]
+                    .map(|title| state.new_secondary_output(title.into()));
+                MockClock::advance(Duration::from_secs(61));
+                state.render().unwrap();
+
+                state
+                    .set_secondary_output_status(
+                        &ids[0],
+                        SecondaryOutputStatus::Finished { exit_code: Some(0) },
+                    )
+                    .unwrap()
+                    .set_secondary_output_status(
+                        &ids[1],
+                        SecondaryOutputStatus::Finished { exit_code: Some(2) },
+                    )
+                    .unwrap()
+                    .set_secondary_output_status(
+                        &ids[2],
+                        SecondaryOutputStatus::Finished { exit_code: None },
+                    )
+                    .unwrap()
+                    .set_status_text(Some("building".into()));
+                MockClock::advance(Duration::from_secs(3600));
+                state.render().unwrap();
+
+                // Still shown once all of the outputs are gone
+                state.remove_secondary_outputs_where(|_, _| true);
+                state.set_status_text(None).render().unwrap();
+            });
+        }
+
+        #[test]
+        fn reports_clamped_moves() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert!(!state.was_last_move_clamped());
+            assert!(state.move_cursor_down().was_last_move_clamped());
+            assert!(state.move_cursor_up().was_last_move_clamped());
+
+            state.new_secondary_output("first".into());
+            state.new_secondary_output("second".into());
+            assert!(state.move_cursor_up().was_last_move_clamped());
+            assert!(!state.move_cursor_down().was_last_move_clamped());
+            assert!(state.move_cursor_down().was_last_move_clamped());
+            assert!(!state.move_cursor_up().was_last_move_clamped());
+            assert!(!state.move_cursor_to(1).was_last_move_clamped());
+            assert!(state.move_cursor_to(5).was_last_move_clamped());
+            assert_eq!(state.secondary_output_selected_index, 1);
+        }
+
+        #[test]
+        fn renders_debug_overlay() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.new_secondary_output("first".into());
+                state
+                    .set_terminal_size(50, 6)
+                    .set_debug_overlay_visible(true)
+                    .handle_primary_bytes(b"\x1b7\r\n\r\n\r\n\r\nprimary")
+                    .render()
+                    .unwrap();
+                MockClock::advance(Duration::from_millis(500));
+                state.new_secondary_output("second".into());
+                state.render().unwrap();
+                // Only counts renders in the last second
+                MockClock::advance(Duration::from_millis(600));
+                state.handle_primary_bytes(b" more").render().unwrap();
+            });
+            let mut parser = vt100::Parser::new(6, 50, 0);
+            parser.process(output.as_bytes());
+            assert_eq!(
+                parser.screen().contents(),
+                "   FPS 2 renders 3 pending_bytes 5 outputs_count 2\n\nprimary more\n\
+                 > ---   1s first\n  ---   1s second"
+            );
+            assert_eq!(parser.screen().cursor_position(), (2, 12));
+            // Drawing the overlay doesn't lose the position the primary output saved
+            parser.process(b"\x1b8");
+            assert_eq!(parser.screen().cursor_position(), (0, 0));
+        }
+
+        #[test]
+        fn dumps_debug_state() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first_id = state.new_secondary_output("first".into());
+            let second_id = state.new_secondary_output("second".into());
+            state
+                .handle_secondary_bytes(&first_id, b"some output\r\n")
+                .unwrap()
+                .handle_primary_bytes_and_render(b"line\r\nprim")
+                .unwrap();
+            state
+                .move_cursor_down()
+                .toggle_current_selection_expanded()
+                .handle_secondary_bytes(&second_id, b"more")
+                .unwrap()
+                .handle_primary_bytes(b"ary");
+
+            assert_eq!(
+                state.dump_debug_state(),
+                crate::state::DebugState {
+                    primary_bytes_len: 3,
+                    primary_cursor_offset: (4, 0),
+                    previous_render_extra_lines: 2,
+                    secondary_output_count: 2,
+                    render_count: 1,
+                    secondary_outputs: vec![
+                        crate::state::SecondaryOutputDebugState {
+                            id: first_id,
+                            title: "first".into(),
+                            expanded: false,
+                            bytes_received: 13,
+                            buffer_size: 13,
+                        },
+                        crate::state::SecondaryOutputDebugState {
+                            id: second_id,
+                            title: "second".into(),
+                            expanded: true,
+                            bytes_received: 4,
+                            buffer_size: 4,
+                        },
+                    ],
+                }
+            );
+        }
+
+        #[test]
+        fn handles_resize() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("first".into());
+                state
+                    .handle_secondary_bytes(&id, b"a line which is 30 columns long")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .handle_primary_bytes(b"primary")
+                    .render()
+                    .unwrap();
+                state.handle_resize(10, 20).unwrap();
+            });
+        }
+
+        #[test]
+        fn resizes_output_screens() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first = state.new_secondary_output("first".into());
+            state.handle_primary_bytes(&[b'p'; 30]).render().unwrap();
+            assert_eq!(state.primary_output_final_cursor_offset, (30, 0));
+            state
+                .handle_resize(24, 20)
+                .unwrap()
+                .toggle_current_selection_expanded();
+            // The primary output can't be rewrapped, so the cursor stays at the end of the line
+            assert_eq!(state.primary_output_final_cursor_offset, (20, 0));
+
+            // Outputs created both before and after the resize wrap at the new width
+            let second = state.new_secondary_output("second".into());
+            for id in [first, second] {
+                state.handle_secondary_bytes(&id, &[b'x'; 30]).unwrap();
+            }
+            state.move_cursor_down().toggle_current_selection_expanded();
+            let text = state
+                .render_frame(20)
+                .lines
+                .iter()
+                .map(Line::text)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                text,
+                vec![
+                    "  +++   0s first",
+                    "xxxxxxxxxxxxxxxxxxxx",
+                    "xxxxxxxxxx",
+                    "> +++   0s second",
+                    "xxxxxxxxxxxxxxxxxxxx",
+                    "xxxxxxxxxx",
+                ]
+            );
+        }
+
+        #[test]
+        fn dumps_debug_text() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first_id = state.new_secondary_output("first".into());
+            let second_id = state.new_secondary_output("second".into());
+            state
+                .set_secondary_output_mode(
+                    &second_id,
+                    SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                )
+                .unwrap()
+                .handle_secondary_bytes(&first_id, b"one\r\ntwo")
+                .unwrap()
+                .handle_secondary_bytes(&second_id, b"line\npartial")
+                .unwrap()
+                .set_terminal_size(80, 24)
+                .toggle_current_selection_expanded()
+                .handle_primary_bytes_and_render(b"prompt")
+                .unwrap();
+            assert_eq!(
+                state.debug_dump(),
+                "primary cursor: column 6, 0 lines from the bottom\n\
+                 pending primary bytes: 0\n\
+                 previous render extra lines: 4 (laid out 4)\n\
+                 terminal size: 80x24\n\
+                 selected index: 0 of 2\n\
+                 output 0 \"first\": expanded true, cursor row 1 column 3, 2 lines, drawn at rows 0..3\n\
+                 output 1 \"second\": expanded false, cursor row 1 column 7, 2 lines, drawn at rows 3..4\n"
+            );
+        }
+
+        #[test]
+        fn reports_invariant_violations() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert_eq!(state.validate(), vec![]);
+            let first_id = state.new_secondary_output("first".into());
+            state.new_secondary_output("second".into());
+            state.render().unwrap();
+            assert_eq!(state.validate(), vec![]);
+
+            // Break the state in ways the public API shouldn't allow
+            state.secondary_output_selected_index = 2;
+            state.secondary_outputs[1].id = first_id;
+            state.previous_render_extra_lines += 1;
+            assert_eq!(
+                state.validate(),
+                vec![
+                    StateInvariantViolation::SelectionOutOfBounds { index: 2, len: 2 },
+                    StateInvariantViolation::DuplicateId(first_id),
+                    StateInvariantViolation::ExtraLinesMismatch {
+                        recorded: 3,
+                        laid_out: 2,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "Invalid state after rendering")]
+        fn render_checks_invariants() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first_id = state.new_secondary_output("first".into());
+            state.new_secondary_output("second".into());
+            state.secondary_outputs[1].id = first_id;
+            state.render().unwrap();
+        }
+
+        #[test]
+        fn selects_next_failed_or_stalled() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let ids =
+                ["first", "second", "third"].map(|title| state.new_secondary_output(title.into()));
+            state
+                .set_secondary_output_status(
+                    &ids[1],
+                    SecondaryOutputStatus::Finished { exit_code: Some(2) },
+                )
+                .unwrap()
+                .set_secondary_output_status(
+                    &ids[2],
+                    SecondaryOutputStatus::Finished { exit_code: Some(0) },
+                )
+                .unwrap();
+            let selected = |state: &mut State<_>| {
+                let frame = state.render_frame(30);
+                frame
+                    .lines
+                    .iter()
+                    .map(Line::text)
+                    .find(|line| line.starts_with('>'))
+                    .unwrap()
+            };
+
+            assert!(state.select_next_failed());
+            assert_eq!(selected(&mut state), "> ---   0s second");
+            // Wraps around to the same output
+            state.move_cursor_down();
+            assert!(state.select_next_failed());
+            assert_eq!(selected(&mut state), "> ---   0s second");
+
+            // Only the first output is still running, and it's not stalled until it's been quiet
+            // for long enough
+            state.set_stall_threshold(Duration::from_secs(10));
+            MockClock::advance(Duration::from_secs(5));
+            state.handle_secondary_bytes(&ids[0], b"progress").unwrap();
+            MockClock::advance(Duration::from_secs(9));
+            assert!(!state.select_next_stalled());
+            assert_eq!(state.selected_secondary_output_id(), Some(ids[1]));
+            MockClock::advance(Duration::from_secs(1));
+            assert!(state.select_next_stalled());
+            assert_eq!(selected(&mut state), "> ---  15s first");
+
+            assert!(!state.select_next_matching(|info| info.title == "missing"));
+            assert_eq!(state.selected_secondary_output_id(), Some(ids[0]));
+        }
+
+        #[test]
+        fn sends_events() {
+            use crate::state::StateEvent::*;
+            use std::sync::{Arc, Mutex};
+
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let recorded = events.clone();
+            state
+                .set_stall_threshold(Duration::from_secs(10))
+                .set_event_handler(move |event| recorded.lock().unwrap().push(event));
+            let take_events = || std::mem::take(&mut *events.lock().unwrap());
+
+            let first = state.new_secondary_output("first".into());
+            let second = state.new_secondary_output("second".into());
+            state.move_cursor_down().move_cursor_down();
+            state
+                .set_secondary_output_status(
+                    &second,
+                    SecondaryOutputStatus::Finished { exit_code: Some(1) },
+                )
+                .unwrap()
+                // Only the change from running counts
+                .set_secondary_output_status(
+                    &second,
+                    SecondaryOutputStatus::Finished { exit_code: Some(2) },
+                )
+                .unwrap();
+            assert_eq!(
+                take_events(),
+                vec![
+                    OutputAdded {
+                        id: first,
+                        title: "first".into()
+                    },
+                    SelectionChanged { id: Some(first) },
+                    OutputAdded {
+                        id: second,
+                        title: "second".into()
+                    },
+                    SelectionChanged { id: Some(second) },
+                    OutputFinished {
+                        id: second,
+                        status: SecondaryOutputStatus::Finished { exit_code: Some(1) }
+                    },
+                ]
+            );
+
+            // Finished outputs don't stall, and stalls are only sent once
+            MockClock::advance(Duration::from_secs(9));
+            state.tick();
+            assert_eq!(take_events(), vec![]);
+            MockClock::advance(Duration::from_secs(1));
+            state.tick();
+            state.tick();
+            assert_eq!(take_events(), vec![OutputStalled { id: first }]);
+
+            // Until the output gets more data
+            state.handle_secondary_bytes(&first, b"more").unwrap();
+            MockClock::advance(Duration::from_secs(10));
+            state.render().unwrap();
+            assert_eq!(take_events(), vec![OutputStalled { id: first }]);
+
+            state.remove_secondary_output(second).unwrap();
+            state.clear_all_secondary_outputs();
+            assert_eq!(
+                take_events(),
+                vec![
+                    OutputRemoved { id: second },
+                    SelectionChanged { id: Some(first) },
+                    OutputRemoved { id: first },
+                    SelectionChanged { id: None },
+                ]
+            );
+        }
+
+        #[test]
+        fn inserts_outputs_in_the_middle() {
+            assert_state_output!(|state| {
+                let first = state.new_secondary_output("stage 1".into());
+                let third = state.new_secondary_output("stage 3".into());
+                state.move_cursor_down().render().unwrap();
+                // Inserting above the cursor leaves it on the same output
+                state
+                    .new_secondary_output_before(&third, "stage 2".into())
+                    .unwrap();
+                state.render().unwrap();
+                state.new_secondary_output_at(0, "stage 0".into());
+                state.new_secondary_output_at(100, "stage 4".into());
+                state.render().unwrap();
+                // Pinned outputs stay at the top
+                state.set_pinned(first, true).unwrap();
+                state.new_secondary_output_at(0, "stage 0.5".into());
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn force_repaint_matches_fresh_render() {
+            let setup = |state: &mut State<Vec<u8>>| {
+                let id = state.new_secondary_output("first".into());
+                state.new_secondary_output("second".into());
+                state
+                    .handle_secondary_bytes(&id, b"some\r\ncontent\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded();
+            };
+            let repaint = |state: &mut State<Vec<u8>>| {
+                setup(state);
+                state
+                    .handle_primary_bytes_and_render(b"before\r\nmore")
+                    .unwrap()
+                    .handle_primary_bytes(b" pending");
+                state.force_repaint().unwrap();
+            };
+            let repainted = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, repaint);
+            assert_eq!(
+                get_state_output_with_backend(
+                    TEST_SECONDARY_OUTPUT_MAX_LINES,
+                    RenderBackend::Ansi,
+                    repaint
+                ),
+                repainted
+            );
+            let fresh = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                setup(state);
+                state.handle_primary_bytes(b" pending").render().unwrap();
+            });
+
+            let (_, after_clear) = repainted.split_once("\x1b[2J\x1b[1;1H").unwrap();
+            assert_eq!(after_clear, fresh);
+            assert_eq!(
+                rasterize_output(&repainted),
+                " pending\n> +++   0s first\nsome\ncontent\n  ---   0s second"
+            );
+        }
+
+        #[test]
+        fn shows_combined_tail() {
+            assert_state_output!(|state| {
+                let build = state.new_secondary_output("build".into());
+                let test = state.new_secondary_output("test".into());
+                state.set_combined_tail(Some(4));
+                state
+                    .handle_secondary_bytes(&build, b"compiling a\r\ncompiling")
+                    .unwrap()
+                    .handle_secondary_bytes(&test, b"test one ... ok\r\n")
+                    .unwrap()
+                    // Lines are ordered by when they're finished
+                    .handle_secondary_bytes(&build, b" b\r\n")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                state
+                    .handle_secondary_bytes(&test, b"test two ... ok\r\ntest three ... ok\r\n")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn combined_tail_in_frame() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first = state.new_secondary_output("first".into());
+            let second = state.new_secondary_output("second".into());
+            // Lines from before it's turned on aren't included
+            state.handle_secondary_bytes(&first, b"early\n").unwrap();
+            state.set_combined_tail(Some(3));
+            for (id, line) in [(first, "1"), (second, "2"), (second, "3"), (first, "4")] {
+                state
+                    .handle_secondary_bytes(&id, format!("line {line}\n").as_bytes())
+                    .unwrap();
+            }
+            let text = |state: &mut State<_>| {
+                let frame = state.render_frame(14);
+                frame
+                    .lines
+                    .iter()
+                    .map(Line::text)
+                    .filter(|line| line.contains(": "))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                text(&mut state),
+                vec!["second: line 2", "second: line 3", "first: line 4"]
+            );
+
+            // Lines from removed outputs are kept
+            state.remove_secondary_output(second).unwrap();
+            state.set_combined_tail(Some(2));
+            assert_eq!(text(&mut state), vec!["second: line 3", "first: line 4"]);
+            state.set_combined_tail(None);
+            assert_eq!(text(&mut state), Vec::<String>::new());
+        }
+
+        #[test]
+        fn replaces_writer() {
+            let mut first_output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut first_output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("first".into());
+            state
+                .handle_secondary_bytes(&id, b"some\r\ncontent\r\n")
+                .unwrap()
+                .toggle_current_selection_expanded()
+                .render()
+                .unwrap();
+
+            let mut second_output: Vec<u8> = Vec::new();
+            let mut state = state.replace_writer(&mut second_output);
+            state.new_secondary_output("second".into());
+            state.render().unwrap();
+            drop(state);
+
+            assert_eq!(
+                rasterize_output(&String::from_utf8(first_output).unwrap()),
+                "\n> +++   0s first\nsome\ncontent"
+            );
+            // Nothing from before needs clearing on the new output
+            let second_output = String::from_utf8(second_output).unwrap();
+            assert!(!second_output.contains("\x1b[J"), "{second_output:?}");
+            assert_eq!(
+                rasterize_output(&second_output),
+                "\n> +++   0s first\nsome\ncontent\n  ---   0s second"
+            );
+        }
+
+        #[test]
+        fn looks_up_outputs_by_name() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let build = state
+                .new_named_secondary_output("cargo:build".into(), "Building".into())
+                .unwrap();
+            let test = state
+                .new_named_secondary_output("cargo:test".into(), "Testing".into())
+                .unwrap();
+            state.new_secondary_output("unnamed".into());
+            assert!(state
+                .new_named_secondary_output("cargo:build".into(), "Again".into())
+                .is_err());
+
+            assert_eq!(
+                state.find_secondary_output_by_name("cargo:build"),
+                Some(build)
+            );
+            assert_eq!(
+                state.find_secondary_output_by_name("cargo:test"),
+                Some(test)
+            );
+            assert_eq!(state.find_secondary_output_by_name("unnamed"), None);
+
+            // Names can be reused once their output is removed, however it's removed
+            state
+                .remove_secondary_output_by_name("cargo:build")
+                .unwrap();
+            assert!(state
+                .remove_secondary_output_by_name("cargo:build")
+                .is_err());
+            assert_eq!(state.find_secondary_output_by_name("cargo:build"), None);
+            state.remove_secondary_output(test).unwrap();
+            let test = state
+                .new_named_secondary_output("cargo:test".into(), "Testing".into())
+                .unwrap();
+            assert_eq!(
+                state
+                    .secondary_output_titles()
+                    .map(|(_, title)| title)
+                    .collect::<Vec<_>>(),
+                vec!["unnamed", "Testing"]
+            );
+            assert_eq!(
+                state.find_secondary_output_by_name("cargo:test"),
+                Some(test)
+            );
+            state.remove_secondary_outputs_where(|_, title| title == "Testing");
+            assert_eq!(state.find_secondary_output_by_name("cargo:test"), None);
+        }
+
+        #[test]
+        fn reports_metadata() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first_id = state.new_secondary_output("first".into());
+            MockClock::advance(Duration::from_millis(1500));
+            let second_id = state.new_secondary_output("second".into());
+            state
+                .handle_secondary_bytes(&first_id, b"one\r\ntwo\r\n")
+                .unwrap()
+                .set_secondary_output_status(
+                    &first_id,
+                    SecondaryOutputStatus::Finished { exit_code: Some(1) },
+                )
+                .unwrap();
+            state.move_cursor_down().toggle_current_selection_expanded();
+            MockClock::advance(Duration::from_secs(2));
+
+            let first = state.secondary_output_metadata(&first_id).unwrap();
+            assert_eq!(first.title, "first");
+            assert_eq!(first.elapsed, Duration::from_millis(3500));
+            assert!(!first.expanded);
+            assert!(first.completed);
+            assert_eq!(first.exit_code, Some(1));
+            assert_eq!(first.line_count, 2);
+            assert_eq!(first.byte_count, 10);
+            assert!(!first.hidden);
+
+            let second = state.secondary_output_metadata(&second_id).unwrap();
+            assert_eq!(second.elapsed, Duration::from_secs(2));
+            assert!(second.expanded);
+            assert!(!second.completed);
+            assert_eq!(second.exit_code, None);
+            assert_eq!(second.line_count, 0);
+            assert_eq!(second.byte_count, 0);
+
+            assert_eq!(state.all_secondary_output_metadata(), vec![first, second]);
+
+            state.remove_secondary_output(first_id).unwrap();
+            assert!(state.secondary_output_metadata(&first_id).is_err());
+        }
+
+        #[test]
+        fn lists_titles_and_selection() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert_eq!(state.secondary_output_titles().count(), 0);
+            assert_eq!(state.selected_secondary_output_id(), None);
+
+            let first = state.new_secondary_output("first".into());
+            let second = state.new_secondary_output("second".into());
+            assert_eq!(
+                state.secondary_output_titles().collect::<Vec<_>>(),
+                vec![(first, "first"), (second, "second")]
+            );
+            assert_eq!(state.selected_secondary_output_id(), Some(first));
+            state.move_cursor_down();
+            assert_eq!(state.selected_secondary_output_id(), Some(second));
+        }
+
+        #[test]
+        fn checks_selection_and_expansion_by_id() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert!(!state.is_secondary_output_selected(Default::default()));
+            assert!(state
+                .is_secondary_output_expanded(Default::default())
+                .is_err());
+
+            let first = state.new_secondary_output("first".into());
+            let second = state.new_secondary_output("second".into());
+            assert!(state.is_secondary_output_selected(first));
+            assert!(!state.is_secondary_output_selected(second));
+            state.move_cursor_down().toggle_current_selection_expanded();
+            assert!(state.is_secondary_output_selected(second));
+            assert!(!state.is_secondary_output_expanded(first).unwrap());
+            assert!(state.is_secondary_output_expanded(second).unwrap());
+
+            state.remove_secondary_output(second).unwrap();
+            assert!(!state.is_secondary_output_selected(second));
+            assert!(state.is_secondary_output_expanded(second).is_err());
+        }
+
+        #[test]
+        fn estimates_finish_time_from_byte_rate() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("estimated".into());
+            // No bytes yet, or no time to measure a rate over
+            assert_eq!(
+                state
+                    .secondary_output_estimated_finish_time(&id, 200)
+                    .unwrap(),
+                None
+            );
+            state.handle_secondary_bytes(&id, &[b'a'; 100]).unwrap();
+            assert_eq!(
+                state
+                    .secondary_output_estimated_finish_time(&id, 200)
+                    .unwrap(),
+                None
+            );
+
+            // 10 bytes per second, so another 10 seconds to go
+            MockClock::advance(Duration::from_secs(10));
+            let before = SystemTime::now();
+            let estimate = state
+                .secondary_output_estimated_finish_time(&id, 200)
+                .unwrap()
+                .unwrap();
+            let after = SystemTime::now();
+            assert!(estimate >= before + Duration::from_secs(10));
+            assert!(estimate <= after + Duration::from_secs(10));
+
+            // Already there
+            let estimate = state
+                .secondary_output_estimated_finish_time(&id, 100)
+                .unwrap()
+                .unwrap();
+            assert!(estimate <= SystemTime::now());
+        }
+
+        #[test]
+        fn ticks_when_time_visuals_change() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert!(!state.tick());
+            let id = state.new_secondary_output("first".into());
+            assert!(state.tick());
+            assert!(!state.tick());
+
+            MockClock::advance(Duration::from_millis(500));
+            assert!(!state.tick());
+            MockClock::advance(Duration::from_millis(500));
+            assert!(state.tick());
+
+            // Finer precision changes more often
+            state.set_elapsed_precision(ElapsedPrecision::Tenths);
+            assert!(state.tick());
+            assert!(!state.tick());
+            MockClock::advance(Duration::from_millis(100));
+            assert!(state.tick());
+
+            // Time bars count too, even with the elapsed time hidden
+            state
+                .set_elapsed_precision(ElapsedPrecision::Seconds)
+                .set_show_elapsed(false);
+            state
+                .set_secondary_output_time_bar(&id, Duration::from_secs(8))
+                .unwrap();
+            assert!(state.tick());
+            MockClock::advance(Duration::from_millis(500));
+            assert!(!state.tick());
+            MockClock::advance(Duration::from_millis(500));
+            assert!(state.tick());
+
+            // So does the summary's elapsed time, even once there are no outputs
+            state.remove_secondary_output(id).unwrap();
+            state.set_summary_enabled(true);
+            assert!(state.tick());
+            assert!(!state.tick());
+            MockClock::advance(Duration::from_millis(500));
+            assert!(!state.tick());
+            MockClock::advance(Duration::from_millis(500));
+            assert!(state.tick());
+        }
+
+        #[test]
+        fn formats_long_durations() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            for (title, advance) in [("a", 1), ("b", 3539), ("c", 1), ("d", 59)] {
+                state.new_secondary_output(title.into());
+                MockClock::advance(Duration::from_secs(advance));
+            }
+            let mut headers = |style| {
+                state.set_duration_style(style);
+                let frame = state.render_frame(30);
+                frame.lines.iter().map(Line::text).collect::<Vec<_>>()
+            };
+
+            assert_eq!(
+                headers(DurationStyle::Seconds),
+                vec![
+                    "> --- 3600s a",
+                    "  --- 3599s b",
+                    "  ---  60s c",
+                    "  ---  59s d",
+                ]
+            );
+            assert_eq!(
+                headers(DurationStyle::Clock),
+                vec![
+                    "> --- 1:00:00 a",
+                    "  ---   59:59 b",
+                    "  ---   01:00 c",
+                    "  ---     59s d",
+                ]
+            );
+            assert_eq!(
+                headers(DurationStyle::Compact),
+                vec![
+                    "> ---  1h00m a",
+                    "  --- 59m59s b",
+                    "  ---  1m00s c",
+                    "  ---    59s d",
+                ]
+            );
+        }
+
+        #[test]
+        fn searches_rows_out_of_view() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("tests".into());
+            state
+                .handle_secondary_bytes(
+                    &id,
+                    b"test a ... ok\r\ntest b ... \x1b[31mFAILED\x1b[0m\r\ntest c ... ok\r\n\
+                      test d ... ok\r\ntest e ... ok\r\ntest f ... ok\r\ntest g ... failed\r\n",
+                )
+                .unwrap()
+                .toggle_current_selection_expanded();
+
+            let found = |row, start| MatchLocation {
+                row,
+                start,
+                end: start + 6,
+            };
+            assert_eq!(
+                state.search_secondary_output(&id, "FAILED").unwrap(),
+                vec![found(1, 11)]
+            );
+            state.set_search_case_insensitive(true);
+            assert_eq!(
+                state.search_secondary_output(&id, "FAILED").unwrap(),
+                vec![found(1, 11), found(6, 11)]
+            );
+            assert_eq!(
+                state.search_secondary_output(&id, "missing").unwrap(),
+                vec![]
+            );
+
+            // Only the visible rows are highlighted
+            state.set_highlight(&id, Some("failed".into())).unwrap();
+            let frame = state.render_frame(30);
+            let inverse = frame
+                .lines
+                .iter()
+                .flat_map(|line| &line.spans)
+                .filter(|span| span.style.inverse)
+                .map(|span| span.text.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(inverse, vec!["failed"]);
+            assert_eq!(frame.lines[3].text(), "test g ... failed");
+        }
+
+        #[test]
+        fn highlights_matches() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("tests".into());
+                state
+                    .handle_secondary_bytes(
+                        &id,
+                        b"one match\r\n\x1b[32mmat\x1b[0mch and match\r\nno mat...ch\r\n",
+                    )
+                    .unwrap()
+                    .set_highlight(&id, Some("match".into()))
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn changes_max_lines_for_existing_outputs() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, 1);
+            let id = state.new_secondary_output("first".into());
+            state
+                .set_secondary_output_mode(
+                    &id,
+                    SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                )
+                .unwrap()
+                .handle_secondary_bytes(&id, b"1\n2\n3\n4\n5\n6\n")
+                .unwrap()
+                .toggle_current_selection_expanded();
+            let lines = |state: &mut State<_>| {
+                let frame = state.render_frame(30);
+                frame
+                    .lines
+                    .iter()
+                    .skip(1)
+                    .map(Line::text)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(lines(&mut state), vec!["6"]);
+
+            // More is shown right away, but only the history which was already kept
+            state.set_global_secondary_output_max_lines(3);
+            assert_eq!(lines(&mut state), vec!["4", "5", "6"]);
+            assert_eq!(state.secondary_output_metadata(&id).unwrap().line_count, 3);
+
+            // New output is kept according to the new size
+            state
+                .handle_secondary_bytes(&id, b"7\n8\n9\n10\n11\n12\n")
+                .unwrap();
+            assert_eq!(lines(&mut state), vec!["10", "11", "12"]);
+            assert_eq!(state.secondary_output_metadata(&id).unwrap().line_count, 9);
+
+            // Shrinking trims the history once more output arrives
+            state.set_global_secondary_output_max_lines(1);
+            assert_eq!(lines(&mut state), vec!["12"]);
+            assert_eq!(state.secondary_output_metadata(&id).unwrap().line_count, 9);
+            state.handle_secondary_bytes(&id, b"13\n").unwrap();
+            assert_eq!(state.secondary_output_metadata(&id).unwrap().line_count, 3);
+
+            // Outputs created afterwards use it too
+            let second = state.new_secondary_output("second".into());
+            state
+                .handle_secondary_bytes(&second, b"a\r\nb\r\n")
+                .unwrap()
+                .move_cursor_down()
+                .toggle_current_selection_expanded();
+            assert_eq!(lines(&mut state).last().unwrap(), "b");
+        }
+
+        #[test]
+        fn shows_line_timestamps() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("first".into());
+            state
+                .set_line_timestamps(true)
+                .set_secondary_output_mode(
+                    &id,
+                    SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                )
+                .unwrap()
+                .toggle_current_selection_expanded();
+            MockClock::advance(Duration::from_millis(1500));
+            state.handle_secondary_bytes(&id, b"first\n").unwrap();
+            MockClock::advance(Duration::from_millis(500));
+            state.handle_secondary_bytes(&id, b"progress 1").unwrap();
+            MockClock::advance(Duration::from_secs(10));
+            // Overwriting the line counts as writing to it
+            state
+                .handle_secondary_bytes(&id, b"\rprogress 2 of a long line")
+                .unwrap();
+            let lines = |state: &mut State<_>| {
+                let frame = state.render_frame(30);
+                frame
+                    .lines
+                    .iter()
+                    .skip(1)
+                    .map(Line::text)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                lines(&mut state),
+                vec!["[001.5s] first", "[012.0s] progress 2 of a long…"]
+            );
+
+            state.set_line_timestamps(false);
+            assert_eq!(
+                lines(&mut state),
+                vec!["first", "progress 2 of a long line"]
+            );
+
+            // Terminal mode can change earlier rows, so doesn't show timestamps
+            state
+                .set_line_timestamps(true)
+                .set_secondary_output_mode(&id, SecondaryMode::Terminal)
+                .unwrap()
+                .handle_secondary_bytes(&id, b"line 1\r\nline 2\r\n")
+                .unwrap();
+            assert_eq!(lines(&mut state), vec!["line 1", "line 2"]);
+        }
+
+        #[test]
+        fn line_timestamps_stay_the_same_width() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("first".into());
+            state
+                .set_line_timestamps(true)
+                .set_secondary_output_mode(
+                    &id,
+                    SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                )
+                .unwrap()
+                .toggle_current_selection_expanded();
+            MockClock::advance(Duration::from_millis(999_900));
+            state.handle_secondary_bytes(&id, b"before\n").unwrap();
+            MockClock::advance(Duration::from_millis(100));
+            state.handle_secondary_bytes(&id, b"after\n").unwrap();
+            MockClock::advance(Duration::from_secs(200_000));
+            state.handle_secondary_bytes(&id, b"much later\n").unwrap();
+            let frame = state.render_frame(30);
+            let lines = frame
+                .lines
+                .iter()
+                .skip(1)
+                .map(Line::text)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                lines,
+                vec!["[999.9s] before", "[01000s] after", "[99999s] much later"]
+            );
+            for millis in [0, 999_949, 999_950, 1_000_000, 99_999_999, u64::MAX] {
+                assert_eq!(
+                    format_line_timestamp(Duration::from_millis(millis)).len(),
+                    LINE_TIMESTAMP_WIDTH as usize
+                );
+            }
+        }
+
+        #[test]
+        fn renders_line_timestamps() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("first".into());
+                state
+                    .set_line_timestamps(true)
+                    .set_secondary_output_mode(
+                        &id,
+                        SecondaryMode::PlainLines(CarriageReturnMode::LineSeparator),
+                    )
+                    .unwrap()
+                    .toggle_current_selection_expanded();
+                MockClock::advance(Duration::from_millis(250));
+                state
+                    .handle_secondary_bytes(&id, b"\x1b[32mgreen\x1b[0m\r\n")
+                    .unwrap();
+                MockClock::advance(Duration::from_secs(2));
+                state
+                    .handle_secondary_bytes(&id, b"later\r\n")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn renders_frame() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let first = state.new_secondary_output("first".into());
+            state.new_secondary_output("a second output with a long title".into());
+            state
+                .handle_secondary_bytes(&first, b"plain\r\n\x1b[31mred\x1b[0m text\r\n")
+                .unwrap()
+                .toggle_current_selection_expanded()
+                .move_cursor_down();
+            MockClock::advance(Duration::from_secs(3));
+
+            let frame = state.render_frame(30);
+            let text = frame.lines.iter().map(Line::text).collect::<Vec<_>>();
+            assert_eq!(
+                text,
+                vec![
+                    "  +++   3s first",
+                    "plain",
+                    "red text",
+                    "> ---   3s a second output wi…",
+                ]
+            );
+            assert_eq!(
+                frame.lines[0].spans[1],
+                Span {
+                    text: "+++".into(),
+                    style: Style::foreground(Color::Indexed(11)),
+                }
+            );
+            assert_eq!(
+                frame.lines[2].spans,
+                vec![
+                    Span {
+                        text: "red".into(),
+                        style: Style::foreground(Color::Indexed(1)),
+                    },
+                    Span {
+                        text: " text".into(),
+                        style: Style::default(),
+                    },
+                ]
+            );
+
+            // Right aligned elapsed times are padded out to the edge
+            state.set_elapsed_right_align(true);
+            let frame = state.render_frame(30);
+            assert_eq!(frame.lines[0].text(), "  +++ first                 3s");
+            assert_eq!(frame.lines[3].text(), "> --- a second output with… 3s");
+        }
+
+        /// Checks that [State::render_plain] matches what [State::render] draws on a 50 column
+        /// screen, after `f` sets up the state.
+        fn assert_plain_matches_render(f: impl FnOnce(&mut State<Vec<u8>>)) {
+            let mut plain = String::new();
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                state.set_terminal_size(50, 50);
+                f(state);
+                plain = state.render_plain(50);
+                state.render().unwrap();
+            });
+            // The secondary outputs start on the line after the (empty) primary output
+            let rendered = rasterize_output(&output);
+            assert_eq!(rendered.strip_prefix('\n').unwrap(), plain);
+        }
+
+        #[test]
+        fn renders_plain_text() {
+            assert_plain_matches_render(|state| {
+                let first = state.new_secondary_output("first".into());
+                let second = state.new_secondary_output("second".into());
+                state
+                    .handle_secondary_bytes(&first, b"plain\r\n\x1b[31mred\x1b[0m text\r\n")
+                    .unwrap()
+                    .handle_secondary_bytes(&second, b"not shown\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .set_summary_enabled(true);
+                state
+                    .set_secondary_output_status(
+                        &second,
+                        SecondaryOutputStatus::Finished { exit_code: Some(1) },
+                    )
+                    .unwrap();
+                MockClock::advance(Duration::from_secs(3));
+            });
+        }
+
+        #[test]
+        fn renders_plain_text_with_viewport() {
+            assert_plain_matches_render(|state| {
+                let ids = (0..5)
+                    .map(|i| state.new_secondary_output(format!("output {i}")))
+                    .collect::<Vec<_>>();
+                state
+                    .handle_secondary_bytes(&ids[4], b"1\r\n2\r\n3\r\n4\r\n5")
+                    .unwrap()
+                    .set_secondary_output_show_scrollbar(&ids[4], true)
+                    .unwrap();
+                state
+                    .set_virtual_list_viewport(2)
+                    .set_elapsed_right_align(true)
+                    .move_cursor_to(4)
+                    .toggle_current_selection_expanded();
+            });
+        }
+
+        #[test]
+        fn rendering_plain_text_does_not_change_state() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            state.new_secondary_output("first".into());
+            state
+                .handle_primary_bytes(b"primary")
+                .set_virtual_list_viewport(1)
+                .new_secondary_output("second".into());
+            state.move_cursor_down();
+            assert_eq!(state.render_plain(30), "> ---   0s second");
+            assert_eq!(state.secondary_output_viewport_start, 0);
+            assert_eq!(state.primary_bytes, b"primary");
+            assert_eq!(state.previous_render_extra_lines, 0);
+            drop(state);
+            assert!(output.is_empty());
+        }
+
+        #[test]
+        fn rendering_frame_does_not_affect_render() {
+            let f = |render_frame: bool| {
+                get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                    let id = state.new_secondary_output("first".into());
+                    state
+                        .handle_secondary_bytes(&id, b"content\r\n")
+                        .unwrap()
+                        .handle_primary_bytes(b"primary\r\n")
+                        .toggle_current_selection_expanded();
+                    if render_frame {
+                        state.render_frame(50);
+                    }
+                    state.render().unwrap();
+                    state.handle_primary_bytes(b"more primary");
+                    if render_frame {
+                        state.render_frame(50);
+                    }
+                    state.render().unwrap();
+                })
+            };
+            assert_eq!(f(true), f(false));
+        }
+
+        #[test]
+        fn scrolls_expanded_output() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("scrolling".into());
+                state.toggle_current_selection_expanded();
+                assert!(!state.secondary_output_can_scroll_up(&id).unwrap());
+                assert!(!state.secondary_output_can_scroll_down(&id).unwrap());
+
+                for i in 0..6 {
+                    state
+                        .handle_secondary_bytes(&id, format!("line {i}\r\n").as_bytes())
+                        .unwrap();
+                }
+                assert!(state.secondary_output_can_scroll_up(&id).unwrap());
+                assert!(!state.secondary_output_can_scroll_down(&id).unwrap());
+                state.render().unwrap();
+
+                state
+                    .scroll_secondary_output_up(&id, 2)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                assert!(state.secondary_output_can_scroll_up(&id).unwrap());
+                assert!(state.secondary_output_can_scroll_down(&id).unwrap());
+
+                // Stops at the top
+                state
+                    .scroll_secondary_output_up(&id, 5)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                assert!(!state.secondary_output_can_scroll_up(&id).unwrap());
+                assert!(state.secondary_output_can_scroll_down(&id).unwrap());
+
+                state
+                    .scroll_secondary_output_down(&id, 10)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                assert!(!state.secondary_output_can_scroll_down(&id).unwrap());
+            });
+        }
+
+        #[test]
+        fn goes_to_bookmarks() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("bookmarked".into());
+                state.toggle_current_selection_expanded();
+                for i in 0..8 {
+                    state
+                        .handle_secondary_bytes(&id, format!("line {i}\r\n").as_bytes())
+                        .unwrap();
+                }
+                state.scroll_secondary_output_up(&id, 2).unwrap();
+                let first = state
+                    .secondary_output_add_bookmark(&id, "first".into())
+                    .unwrap();
+                state.scroll_secondary_output_down(&id, 2).unwrap();
+                let second = state
+                    .secondary_output_add_bookmark(&id, "second".into())
+                    .unwrap();
+                assert_eq!(
+                    state
+                        .secondary_output_list_bookmarks(&id)
+                        .unwrap()
+                        .collect::<Vec<_>>(),
+                    vec![(0, "first", 3), (1, "second", 5)]
+                );
+
+                // New content doesn't move the bookmarked rows
+                state.handle_secondary_bytes(&id, b"line 8\r\n").unwrap();
+                state.secondary_output_goto_bookmark(&id, first).unwrap();
+                state.render().unwrap();
+                state.secondary_output_goto_bookmark(&id, second).unwrap();
+                state.render().unwrap();
+                assert!(state.secondary_output_goto_bookmark(&id, 2).is_err());
+            });
+        }
+
+        #[test]
+        fn keeps_bookmarks_on_their_lines_past_history() {
+            let mut output: Vec<u8> = Vec::new();
+            // Keeps 6 lines of history
+            let mut state = State::new(&mut output, 2);
+            let id = state.new_secondary_output("bookmarked".into());
+            state
+                .set_secondary_output_mode(
+                    &id,
+                    SecondaryMode::PlainLines(CarriageReturnMode::LineSeparator),
+                )
+                .unwrap()
+                .toggle_current_selection_expanded();
+            for i in 0..6 {
+                state
+                    .handle_secondary_bytes(&id, format!("line {i}\n").as_bytes())
+                    .unwrap();
+            }
+            let kept = state
+                .secondary_output_add_bookmark(&id, "kept".into())
+                .unwrap();
+            state.scroll_secondary_output_up(&id, 4).unwrap();
+            state
+                .secondary_output_add_bookmark(&id, "dropped".into())
+                .unwrap();
+
+            state
+                .handle_secondary_bytes(&id, b"line 6\nline 7\nline 8\n")
+                .unwrap();
+            // The first bookmark's line moves up as the oldest ones drop out, and the second goes
+            // to the oldest line left
+            assert_eq!(
+                state
+                    .secondary_output_list_bookmarks(&id)
+                    .unwrap()
+                    .collect::<Vec<_>>(),
+                vec![(0, "kept", 1), (1, "dropped", 0)]
+            );
+            state.secondary_output_goto_bookmark(&id, kept).unwrap();
+            let frame = state.render_frame(30);
+            assert_eq!(
+                frame.lines.iter().map(Line::text).collect::<Vec<_>>(),
+                vec!["> +++   0s bookmarked", "line 4", "line 5"]
+            );
+        }
+
+        #[test]
+        fn removes_bookmarks() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("bookmarked".into());
+            let labels = |state: &State<Vec<u8>>| {
+                state
+                    .secondary_output_list_bookmarks(&id)
+                    .unwrap()
+                    .map(|(index, label, _)| (index, label.to_string()))
+                    .collect::<Vec<_>>()
+            };
+            for label in ["first", "second", "third"] {
+                state
+                    .secondary_output_add_bookmark(&id, label.into())
+                    .unwrap();
+            }
+            assert!(state.secondary_output_remove_bookmark(&id, 1).unwrap());
+            assert!(!state.secondary_output_remove_bookmark(&id, 2).unwrap());
+            assert_eq!(
+                labels(&state),
+                vec![(0, "first".to_string()), (1, "third".to_string())]
+            );
+            state.secondary_output_clear_bookmarks(&id).unwrap();
+            assert_eq!(labels(&state), vec![]);
+        }
+
+        #[test]
+        fn clearing_buffer_drops_bookmarks_unless_persisted() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("cleared".into());
+            state.handle_secondary_bytes(&id, b"abc\r\n").unwrap();
+            state
+                .secondary_output_add_bookmark(&id, "kept".into())
+                .unwrap();
+            state
+                .secondary_output_add_range(&id, "range".into(), 0, 1)
+                .unwrap();
+            state
+                .set_persist_bookmarks_on_clear(true)
+                .secondary_output_clear_buffer(&id)
+                .unwrap();
+            assert_eq!(
+                state.secondary_output_list_bookmarks(&id).unwrap().count(),
+                1
+            );
+            assert_eq!(state.secondary_output_named_ranges(&id).unwrap().count(), 1);
+            state
+                .set_persist_bookmarks_on_clear(false)
+                .secondary_output_clear_buffer(&id)
+                .unwrap();
+            assert_eq!(
+                state.secondary_output_list_bookmarks(&id).unwrap().count(),
+                0
+            );
+            assert_eq!(state.secondary_output_named_ranges(&id).unwrap().count(), 0);
+        }
+
+        #[test]
+        fn clears_buffer() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("cleared".into());
+                state
+                    .set_secondary_output_mode(
+                        &id,
+                        SecondaryMode::PlainLines(CarriageReturnMode::LineSeparator),
+                    )
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .handle_secondary_bytes_and_render(&id, b"first run\r\n")
+                    .unwrap();
+                state
+                    .secondary_output_clear_buffer(&id)
+                    .unwrap()
+                    .handle_secondary_bytes_and_render(&id, b"second\rrun\r\n")
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn shows_named_ranges() {
+            assert_state_output!(5, |state| {
+                let id = state.new_secondary_output("ranges".into());
+                state.toggle_current_selection_expanded();
+                for i in 0..8 {
+                    state
+                        .handle_secondary_bytes(&id, format!("line {i}\r\n").as_bytes())
+                        .unwrap();
+                }
+                let first = state
+                    .secondary_output_add_range(&id, "first".into(), 1, 3)
+                    .unwrap();
+                state
+                    .secondary_output_add_range(&id, "second".into(), 4, 5)
+                    .unwrap();
+                assert!(state
+                    .secondary_output_add_range(&id, "backwards".into(), 5, 4)
+                    .is_err());
+                assert_eq!(
+                    state
+                        .secondary_output_named_ranges(&id)
+                        .unwrap()
+                        .collect::<Vec<_>>(),
+                    vec![(0, "first", 1..3), (1, "second", 4..5)]
+                );
+
+                state
+                    .set_secondary_output_show_named_ranges(&id, true)
+                    .unwrap();
+                state.secondary_output_goto_range(&id, first).unwrap();
+                state.render().unwrap();
+                assert!(state.secondary_output_goto_range(&id, 2).is_err());
+
+                let frame = state.render_frame(20);
+                assert_eq!(
+                    frame.lines[2].text(),
+                    format!("{}line 2", Symbols::default().range_border)
+                );
+                assert_eq!(frame.lines[3].text(), " line 3");
+            });
+        }
+
+        #[test]
+        fn follows_new_ranges() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("phases".into());
+            state.toggle_current_selection_expanded();
+            for i in 0..10 {
+                state
+                    .handle_secondary_bytes(&id, format!("line {i}\r\n").as_bytes())
+                    .unwrap();
+            }
+            let first_visible_line =
+                |state: &mut State<Vec<u8>>| state.render_frame(20).lines[1].text();
+
+            state
+                .secondary_output_add_range(&id, "ignored".into(), 2, 4)
+                .unwrap();
+            assert_eq!(first_visible_line(&mut state), "line 7");
+
+            state
+                .set_secondary_output_auto_follow_ranges(&id, true)
+                .unwrap();
+            state
+                .secondary_output_add_range(&id, "compile".into(), 3, 6)
+                .unwrap();
+            assert_eq!(first_visible_line(&mut state), "line 3");
+            state
+                .secondary_output_add_range(&id, "link".into(), 6, 10)
+                .unwrap();
+            assert_eq!(first_visible_line(&mut state), "line 6");
+        }
+
+        #[test]
+        fn shows_scrollbar() {
             assert_state_output!(|state| {
+                let id = state.new_secondary_output("scrolling".into());
                 state
-                    .handle_primary_bytes("my test string\r\nhello hi".as_bytes())
+                    .set_terminal_size(20, 10)
+                    .toggle_current_selection_expanded()
+                    .set_secondary_output_show_scrollbar(&id, true)
+                    .unwrap();
+                // Nothing to scroll yet
+                state
+                    .handle_secondary_bytes_and_render(&id, b"line 0\r\n")
+                    .unwrap();
+                // Content is wrapped a column narrower
+                state
+                    .handle_secondary_bytes(&id, b"line 1 is too long to fit\r\n")
+                    .unwrap();
+                for i in 2..6 {
+                    state
+                        .handle_secondary_bytes(&id, format!("line {i}\r\n").as_bytes())
+                        .unwrap();
+                }
+                state.render().unwrap();
+                state
+                    .scroll_secondary_output_up(&id, 3)
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                state
+                    .scroll_secondary_output_up(&id, 10)
+                    .unwrap()
                     .render()
                     .unwrap();
             });
         }
 
         #[test]
-        fn only_renders_bytes_once() {
-            assert_state_output!(|state| {
-                state.handle_primary_bytes("no repeat ".as_bytes());
-                state.render().unwrap();
-                state.render().unwrap();
-            });
+        fn shows_scrollbar_in_frame() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("scrolling".into());
+            state.toggle_current_selection_expanded();
+            state
+                .set_secondary_output_show_scrollbar(&id, true)
+                .unwrap();
+            for i in 0..5 {
+                state
+                    .handle_secondary_bytes(&id, format!("line {i}\r\n").as_bytes())
+                    .unwrap();
+            }
+            let lines = state
+                .render_frame(10)
+                .lines
+                .iter()
+                .map(Line::text)
+                .collect::<Vec<_>>();
+            let symbols = Symbols::default();
+            assert_eq!(
+                lines[1..],
+                [
+                    format!("line 2   {}", symbols.scrollbar_track),
+                    format!("line 3   {}", symbols.scrollbar_track),
+                    format!("line 4   {}", symbols.scrollbar_thumb),
+                ]
+            );
         }
 
         #[test]
-        fn draws_secondary_output_after_content_and_restores_cursor_position() {
-            assert_state_output!(|state| {
-                state.new_secondary_output("test secondary output".into());
+        fn handle_and_render_matches_separate_calls() {
+            let separate = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("secondary".into());
+                state.toggle_current_selection_expanded();
                 state
-                    .handle_primary_bytes("abc\r\ndef\r\nghi\x1b[3D\x1b[1A\x1b[3C".as_bytes())
+                    .handle_secondary_bytes(&id, b"abc\r\n")
+                    .unwrap()
                     .render()
                     .unwrap();
-
                 state
-                    .handle_primary_bytes("123".as_bytes())
+                    .handle_secondary_bytes(&id, b"def")
+                    .unwrap()
+                    .handle_secondary_bytes(&id, b"ghi")
+                    .unwrap()
                     .render()
                     .unwrap();
             });
+            let combined = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let id = state.new_secondary_output("secondary".into());
+                state
+                    .toggle_current_selection_expanded()
+                    .handle_secondary_bytes_and_render(&id, b"abc\r\n")
+                    .unwrap()
+                    .handle_secondary_bytes(&id, b"def")
+                    .unwrap()
+                    .handle_secondary_bytes_and_render(&id, b"ghi")
+                    .unwrap();
+            });
+            assert_eq!(combined, separate);
         }
 
         #[test]
-        fn clears_secondary_output() {
+        fn shows_bells() {
             assert_state_output!(|state| {
-                state.new_secondary_output("test secondary output".into());
+                let first = state.new_secondary_output("first".into());
+                let second = state.new_secondary_output("second".into());
                 state
-                    .handle_primary_bytes("abc".as_bytes())
+                    .set_forward_bell(true)
+                    .handle_secondary_bytes(&first, b"done\x07")
+                    .unwrap()
+                    .handle_secondary_bytes(&second, b"also done\x07")
+                    .unwrap()
                     .render()
                     .unwrap();
-
+                // Nothing new to forward
+                MockClock::advance(Duration::from_secs(2));
+                state.render().unwrap();
+                // Ending a title sequence isn't a bell
                 state
-                    .handle_primary_bytes("def\r\n123".as_bytes())
-                    .render()
+                    .handle_secondary_bytes(&second, b"\x1b]0;title\x07")
                     .unwrap();
+                MockClock::advance(Duration::from_secs(1));
+                state.render().unwrap();
             });
         }
-    }
 
-    mod secondary_output {
-        use super::*;
-        use mock_instant::MockClock;
-        use std::time::Duration;
+        #[test]
+        fn marks_bells_until_they_expire() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("first".into());
+            state.set_bell_indicator_duration(Duration::from_millis(1500));
+            state.tick();
+            state.handle_secondary_bytes(&id, b"\x07").unwrap();
+            assert!(state.tick());
+            assert_eq!(
+                state.render_frame(50).lines[0].text(),
+                "> ---   0s 🔔 first"
+            );
+            MockClock::advance(Duration::from_secs(1));
+            assert!(state.tick());
+            assert_eq!(
+                state.render_frame(50).lines[0].text(),
+                "> ---   1s 🔔 first"
+            );
+            MockClock::advance(Duration::from_millis(500));
+            assert!(state.tick());
+            assert_eq!(state.render_frame(50).lines[0].text(), "> ---   1s first");
+        }
 
         #[test]
-        fn shows_titles_and_durations() {
+        fn reports_scroll_percentage() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("scrolling".into());
+            state
+                .handle_secondary_bytes(&id, b"1\r\n2\r\n3\r\n")
+                .unwrap();
+            assert_eq!(state.secondary_output_scroll_percentage(&id).unwrap(), None);
+
+            state.handle_secondary_bytes(&id, b"4\r\n5\r\n").unwrap();
+            assert_eq!(
+                state.secondary_output_scroll_percentage(&id).unwrap(),
+                Some(0.0)
+            );
+            state.scroll_secondary_output_up(&id, 1).unwrap();
+            assert_eq!(
+                state.secondary_output_scroll_percentage(&id).unwrap(),
+                Some(0.5)
+            );
+            state.scroll_secondary_output_up(&id, 5).unwrap();
+            assert_eq!(
+                state.secondary_output_scroll_percentage(&id).unwrap(),
+                Some(1.0)
+            );
+        }
+
+        #[test]
+        fn hides_elapsed() {
             assert_state_output!(|state| {
                 state.new_secondary_output("first title".into());
-                MockClock::advance(Duration::from_secs(1));
-                state.new_secondary_output("second title".into());
-                MockClock::advance(Duration::from_secs(1));
-                state.render().unwrap();
+                state.new_secondary_output("a much longer second title".into());
+                MockClock::advance(Duration::from_secs(5));
+                state.set_show_elapsed(false).render().unwrap();
+                // Title gets all the space
+                state.set_terminal_size(20, 10).render().unwrap();
             });
         }
 
         #[test]
-        fn durations_change_at_same_time() {
+        fn truncates_wide_titles() {
             assert_state_output!(|state| {
-                // Offset from any instant taken when the state was created.
-                MockClock::advance(Duration::from_millis(250));
-                state.new_secondary_output("first title".into());
-
-                // Offset by a non-whole-number of seconds
-                MockClock::advance(Duration::from_millis(500));
-                state.new_secondary_output("second title".into());
-
-                // Wait until just before the times should tick over; assumes they tick over at whole
-                // numbers of seconds from when the state was initially created.
-                MockClock::advance(Duration::from_millis(249));
-                state.render().unwrap();
-
-                // Have it tick over to the next second
-                MockClock::advance(Duration::from_millis(1));
-                state.render().unwrap();
+                let id = state.new_secondary_output("🚀 build the thing".into());
+                state.new_secondary_output("テストテストテスト".into());
+                state
+                    .handle_secondary_bytes(&id, "テストテストテスト\r\n".as_bytes())
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .set_terminal_size(15, 10)
+                    .render()
+                    .unwrap();
+                state.set_elapsed_right_align(true).render().unwrap();
             });
         }
 
         #[test]
-        fn shows_cursor_at_selected_index() {
+        fn changes_prefix_when_expanded() {
             assert_state_output!(|state| {
+                // No-op if there's no outputs
+                state.toggle_current_selection_expanded();
                 state.new_secondary_output("one".into());
                 state.new_secondary_output("two".into());
                 state.new_secondary_output("three".into());
-                state.new_secondary_output("four".into());
                 state
+                    // Expand "one"
+                    .toggle_current_selection_expanded()
+                    // Expand "two"
                     .move_cursor_down()
+                    .toggle_current_selection_expanded()
+                    // Collapse "one"
+                    .move_cursor_up()
+                    .toggle_current_selection_expanded()
+                    // Expand "three"
                     .move_cursor_down()
                     .move_cursor_down()
-                    .move_cursor_up()
+                    .toggle_current_selection_expanded()
                     .render()
                     .unwrap();
             });
         }
 
         #[test]
-        fn clamps_cursor_down() {
+        fn removing_output_preserves_order_and_selection() {
             assert_state_output!(|state| {
                 state.new_secondary_output("one".into());
-                state.new_secondary_output("two".into());
+                let two_id = state.new_secondary_output("two".into());
+                state.new_secondary_output("three".into());
                 state
+                    // Put the cursor on the item to be removed
                     .move_cursor_down()
-                    .move_cursor_down()
-                    .move_cursor_down()
-                    .move_cursor_down()
+                    .remove_secondary_output(two_id)
+                    .unwrap()
                     .render()
                     .unwrap();
+
+                // Can't call a second time, id is now invalid
+                assert!(state.remove_secondary_output(two_id).is_err());
             });
         }
 
         #[test]
-        fn clamps_cursor_up() {
+        fn clears_all_outputs() {
             assert_state_output!(|state| {
                 state.new_secondary_output("one".into());
-                state.new_secondary_output("two".into());
+                let two_id = state.new_secondary_output("two".into());
                 state
                     .move_cursor_down()
-                    .move_cursor_down()
-                    .move_cursor_up()
-                    .move_cursor_up()
-                    .move_cursor_up()
-                    .move_cursor_up()
-                    .render()
+                    .toggle_current_selection_expanded()
+                    .handle_secondary_bytes_and_render(&two_id, b"content\r\n")
+                    .unwrap();
+                // The previous overlay is still cleared away
+                state
+                    .clear_all_secondary_outputs()
+                    .handle_primary_bytes_and_render(b"primary")
                     .unwrap();
+                assert!(state.handle_secondary_bytes(&two_id, b"").is_err());
+
+                // IDs from before the clear don't get reused
+                let new_id = state.new_secondary_output("new".into());
+                assert_ne!(new_id, two_id);
+                assert!(state.handle_secondary_bytes(&two_id, b"").is_err());
+                state.render().unwrap();
             });
         }
 
         #[test]
-        fn changes_prefix_when_expanded() {
-            assert_state_output!(|state| {
-                // No-op if there's no outputs
-                state.toggle_current_selection_expanded();
-                state.new_secondary_output("one".into());
-                state.new_secondary_output("two".into());
-                state.new_secondary_output("three".into());
+        fn debug_formats_outputs_without_buffers() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("build".into());
+            state
+                .handle_secondary_bytes(&id, b"lots of output\r\n")
+                .unwrap()
+                .toggle_current_selection_expanded();
+            MockClock::advance(Duration::from_millis(1500));
+            assert_eq!(
+                format!("{state:?}"),
+                "State { output: [], terminal_size: None, selected_index: 0, secondary_outputs: \
+                 [SecondaryOutputState { id: SecondaryOutputId(0), title: \"build\", \
+                 expanded: true, elapsed_ms: 1500, .. }], .. }"
+            );
+        }
+
+        #[test]
+        fn records_frame_layout() {
+            use crate::frame::{FrameLayout, OutputLayout};
+
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            assert_eq!(state.last_frame_layout(), &FrameLayout::default());
+            let one_id = state.new_secondary_output("one".into());
+            let two_id = state.new_secondary_output("two".into());
+            state
+                .set_summary_enabled(true)
+                .move_cursor_down()
+                .toggle_current_selection_expanded()
+                .handle_secondary_bytes(&two_id, b"1\r\n2\r\n3\r\n4\r\n")
+                .unwrap()
+                .render()
+                .unwrap();
+            assert_eq!(
+                state.last_frame_layout(),
+                &FrameLayout {
+                    outputs: vec![
+                        OutputLayout {
+                            id: one_id,
+                            header_row: 0,
+                            content_rows: 1..1,
+                        },
+                        OutputLayout {
+                            id: two_id,
+                            header_row: 1,
+                            content_rows: 2..5,
+                        },
+                    ],
+                    // Including the summary line
+                    extra_lines: 6,
+                }
+            );
+
+            state
+                .set_summary_enabled(false)
+                .clear_all_secondary_outputs()
+                .render()
+                .unwrap();
+            assert_eq!(state.last_frame_layout(), &FrameLayout::default());
+        }
+
+        #[test]
+        fn handles_mouse_events() {
+            use crate::mouse::MouseOutcome;
+            use crossterm::event::{KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let one_id = state.new_secondary_output("one".into());
+            let two_id = state.new_secondary_output("two".into());
+            state
+                .handle_primary_bytes(b"\x1b[?1000h\x1b[?1006h$ ")
+                .toggle_current_selection_expanded()
+                .handle_secondary_bytes(&one_id, b"1\r\n2\r\n3\r\n4\r\n5\r\n")
+                .unwrap()
+                .render()
+                .unwrap();
+
+            // With the cursor back on the primary output on row 14, the outputs are on rows 15 to 19
+            let event = |kind, row| MouseEvent {
+                kind,
+                column: 2,
+                row,
+                modifiers: KeyModifiers::NONE,
+            };
+            let click = MouseEventKind::Down(MouseButton::Left);
+            assert_eq!(
+                state.handle_mouse_event(event(click, 19), 14),
+                MouseOutcome::Handled
+            );
+            assert!(state.is_secondary_output_selected(two_id));
+            assert!(state.is_secondary_output_expanded(two_id).unwrap());
+            assert_eq!(
+                state.handle_mouse_event(event(MouseEventKind::ScrollUp, 16), 14),
+                MouseOutcome::Handled
+            );
+            assert!(state.secondary_output_can_scroll_down(&one_id).unwrap());
+            // Clicking content selects the output without collapsing it
+            assert_eq!(
+                state.handle_mouse_event(event(click, 17), 14),
+                MouseOutcome::Handled
+            );
+            assert!(state.is_secondary_output_selected(one_id));
+            assert!(state.is_secondary_output_expanded(one_id).unwrap());
+
+            // Anywhere else is for the primary output, which asked for mouse events
+            assert_eq!(
+                state.handle_mouse_event(event(click, 14), 14),
+                MouseOutcome::Forward(b"\x1b[<0;3;15M".to_vec())
+            );
+            state.handle_primary_bytes(b"\x1b[?1000l").render().unwrap();
+            assert_eq!(
+                state.handle_mouse_event(event(click, 14), 14),
+                MouseOutcome::Ignored
+            );
+            // Once the primary output has finished, the cursor's left just below the outputs
+            state.handle_primary_eof().render().unwrap();
+            assert_eq!(
+                state.handle_mouse_event(event(click, 19), 20),
+                MouseOutcome::Handled
+            );
+            assert!(state.is_secondary_output_selected(two_id));
+        }
+
+        #[test]
+        fn maps_ids_to_indices() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let one_id = state.new_secondary_output("one".into());
+            let two_id = state.new_secondary_output("two".into());
+            let zero_id = state.new_secondary_output_at(0, "zero".into());
+            assert_eq!(state.secondary_output_index_of(&zero_id).unwrap(), 0);
+            assert_eq!(state.secondary_output_index_of(&two_id).unwrap(), 2);
+            assert_eq!(state.secondary_output_id_at_index(1), Some(one_id));
+            assert_eq!(state.secondary_output_id_at_index(3), None);
+
+            state.remove_secondary_output(one_id).unwrap();
+            assert!(state.secondary_output_index_of(&one_id).is_err());
+            assert_eq!(state.secondary_output_id_at_index(1), Some(two_id));
+        }
+
+        #[test]
+        fn aligns_start_times_to_reference() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let reference = state.secondary_output_reference_start_time();
+            MockClock::advance(Duration::from_millis(1700));
+            let id = state.new_secondary_output("late".into());
+            // Rounded down to a whole second after the reference
+            assert_eq!(
+                state.secondary_output_start_time(&id).unwrap(),
+                reference + Duration::from_secs(1)
+            );
+            state.pause_timer(&id).unwrap();
+            MockClock::advance(Duration::from_secs(5));
+            state.resume_timer(&id).unwrap();
+            assert_eq!(
+                state.secondary_output_start_time(&id).unwrap(),
+                reference + Duration::from_secs(1)
+            );
+            assert_eq!(state.secondary_output_reference_start_time(), reference);
+        }
+
+        #[test]
+        fn handles_ui_actions() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let one_id = state.new_secondary_output("one".into());
+            let two_id = state.new_secondary_output("two".into());
+            state
+                .handle_ui_action(UiAction::ExpandAll)
+                .handle_ui_action(UiAction::CursorDown);
+            assert!(state.is_secondary_output_expanded(one_id).unwrap());
+            assert!(state.is_secondary_output_selected(two_id));
+            state.handle_ui_action(UiAction::ToggleExpand);
+            assert!(!state.is_secondary_output_expanded(two_id).unwrap());
+            state.handle_ui_action(UiAction::CollapseAll);
+            assert!(!state.is_secondary_output_expanded(one_id).unwrap());
+        }
+
+        #[test]
+        fn rerenders_outputs_with_only_blank_rows() {
+            let output = get_state_output(TEST_SECONDARY_OUTPUT_MAX_LINES, |state| {
+                let blank_id = state.new_secondary_output("blank".into());
+                state.new_secondary_output("empty".into());
                 state
-                    // Expand "one"
+                    .handle_primary_bytes(b"primary\r\n")
                     .toggle_current_selection_expanded()
-                    // Expand "two"
                     .move_cursor_down()
                     .toggle_current_selection_expanded()
-                    // Collapse "one"
-                    .move_cursor_up()
-                    .toggle_current_selection_expanded()
-                    // Expand "three"
-                    .move_cursor_down()
-                    .move_cursor_down()
+                    .handle_secondary_bytes_and_render(&blank_id, b"\r\n\r\n")
+                    .unwrap();
+                // Everything from the first render is cleared away, with nothing left over
+                state.render().unwrap();
+            });
+            assert_eq!(
+                rasterize_output(&output),
+                "primary\n\n  +++   0s blank\n\n\n> +++   0s empty"
+            );
+        }
+
+        #[test]
+        fn drops_bytes_over_budget() {
+            assert_state_output!(|state| {
+                let id = state.new_secondary_output("flood".into());
+                state
+                    .set_secondary_output_byte_budget(16)
                     .toggle_current_selection_expanded()
+                    .handle_secondary_bytes(&id, b"line 1\r\n")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+                assert_eq!(
+                    state.secondary_output_metadata(&id).unwrap().dropped_bytes,
+                    0
+                );
+                // Only the newest 16 bytes of this are kept
+                state
+                    .handle_secondary_bytes(&id, b"line 2\r\nline 3\r\nline 4\r\n")
+                    .unwrap()
                     .render()
                     .unwrap();
+                assert_eq!(
+                    state.secondary_output_metadata(&id).unwrap().dropped_bytes,
+                    8
+                );
             });
         }
 
         #[test]
-        fn removing_output_preserves_order_and_selection() {
+        fn drops_start_of_long_plain_line() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("plain".into());
+            state
+                .set_secondary_output_byte_budget(10)
+                .set_secondary_output_mode(
+                    &id,
+                    SecondaryMode::PlainLines(CarriageReturnMode::Overwrite),
+                )
+                .unwrap()
+                .toggle_current_selection_expanded();
+            // Each chunk fits, but the line they make up doesn't
+            for chunk in [&b"0123456"[..], b"789abc", b"def"] {
+                state.handle_secondary_bytes(&id, chunk).unwrap();
+            }
+            let text = state
+                .render_frame(30)
+                .lines
+                .iter()
+                .skip(1)
+                .map(Line::text)
+                .collect::<Vec<_>>();
+            assert_eq!(text, vec!["… output truncated …", "6789abcdef"]);
+            assert_eq!(
+                state.secondary_output_metadata(&id).unwrap().dropped_bytes,
+                6
+            );
+        }
+
+        #[test]
+        fn budget_cut_starts_at_next_line() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let id = state.new_secondary_output("flood".into());
+            state
+                .set_secondary_output_byte_budget(12)
+                .set_combined_tail(Some(3))
+                .toggle_current_selection_expanded();
+            // The cut lands in the middle of the color sequence
+            state
+                .handle_secondary_bytes(&id, b"0123456789\x1b[31mred\r\nnext\r\n")
+                .unwrap();
+            // A line which never ends only keeps its newest bytes in the combined tail too
+            for _ in 0..3 {
+                state.handle_secondary_bytes(&id, b"0123456789").unwrap();
+            }
+            state.handle_secondary_bytes(&id, b"end\r\n").unwrap();
+            let text = state
+                .render_frame(30)
+                .lines
+                .iter()
+                .map(Line::text)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                text,
+                vec![
+                    "> +++   0s flood",
+                    "… output truncated …",
+                    "next",
+                    "012345678901234567890123456789",
+                    "flood: next",
+                    "flood: 890123456789end",
+                ]
+            );
+        }
+
+        #[test]
+        fn marks_each_gap_once() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, 10);
+            let id = state.new_secondary_output("flood".into());
+            state
+                .set_secondary_output_byte_budget(10)
+                .toggle_current_selection_expanded();
+            // None of these chunks end a line, so they all drop into the same gap
+            for _ in 0..3 {
+                state
+                    .handle_secondary_bytes(&id, b"0123456789abcdef")
+                    .unwrap();
+            }
+            state
+                .handle_secondary_bytes(&id, b"\r\nnext\r\n")
+                .unwrap()
+                .handle_secondary_bytes(&id, b"0123456789abcdef")
+                .unwrap();
+            let text = state
+                .render_frame(30)
+                .lines
+                .iter()
+                .skip(1)
+                .map(Line::text)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                text,
+                vec![
+                    "… output truncated …",
+                    "6789abcdef",
+                    "next",
+                    "… output truncated …",
+                    "6789abcdef",
+                ]
+            );
+            assert_eq!(
+                state.secondary_output_metadata(&id).unwrap().dropped_bytes,
+                24
+            );
+        }
+
+        #[test]
+        fn takes_output() {
             assert_state_output!(|state| {
                 state.new_secondary_output("one".into());
                 let two_id = state.new_secondary_output("two".into());
                 state.new_secondary_output("three".into());
                 state
-                    // Put the cursor on the item to be removed
-                    .move_cursor_down()
-                    .remove_secondary_output(two_id)
+                    .handle_secondary_bytes(&two_id, b"plain\r\n\x1b[31mred\x1b[0m\r\n")
                     .unwrap()
+                    .set_secondary_output_status(
+                        &two_id,
+                        SecondaryOutputStatus::Finished { exit_code: Some(0) },
+                    )
+                    .unwrap()
+                    .move_cursor_to(2)
                     .render()
                     .unwrap();
+                MockClock::advance(Duration::from_secs(2));
 
-                // Can't call a second time, id is now invalid
-                assert!(state.remove_secondary_output(two_id).is_err());
+                let taken = state.take_secondary_output(two_id).unwrap();
+                assert_eq!(taken.title, "two");
+                assert_eq!(
+                    taken.status,
+                    SecondaryOutputStatus::Finished { exit_code: Some(0) }
+                );
+                assert_eq!(taken.contents, "plain\n\x1b[31mred\x1b[0m");
+                assert!(state.take_secondary_output(two_id).is_err());
+                // Still on "three"
+                state.render().unwrap();
+            });
+        }
+
+        #[test]
+        fn removes_all_outputs() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let ids = ["one", "two"].map(|title| state.new_secondary_output(title.into()));
+            state.move_cursor_down();
+            assert_eq!(
+                state.remove_all_secondary_outputs(),
+                vec![(ids[0], "one".to_string()), (ids[1], "two".to_string())]
+            );
+            assert_eq!(state.secondary_output_selected_index, 0);
+            assert!(state.remove_all_secondary_outputs().is_empty());
+            // Unlike clearing, IDs aren't reused
+            let new_id = state.new_secondary_output("new".into());
+            assert!(!ids.contains(&new_id));
+        }
+
+        #[test]
+        fn removes_matching_outputs() {
+            assert_state_output!(|state| {
+                let ids = ["done 1", "running 1", "done 2", "running 2", "done 3"]
+                    .map(|title| state.new_secondary_output(title.into()));
+                state.move_cursor_to(3).render().unwrap();
+                let removed =
+                    state.remove_secondary_outputs_where(|_, title| title.starts_with("done"));
+                assert_eq!(removed, vec![ids[0], ids[2], ids[4]]);
+                // Still on "running 2"
+                assert_eq!(state.secondary_output_selected_index, 1);
+                state.render().unwrap();
+                // Nothing matches
+                assert_eq!(
+                    state.remove_secondary_outputs_where(|id, _| *id == ids[0]),
+                    vec![]
+                );
             });
         }
 
+        #[test]
+        fn removing_matching_outputs_moves_selection_to_next_kept() {
+            let mut output: Vec<u8> = Vec::new();
+            let mut state = State::new(&mut output, TEST_SECONDARY_OUTPUT_MAX_LINES);
+            let ids = ["a", "b", "c", "d"].map(|title| state.new_secondary_output(title.into()));
+            state.move_cursor_to(1);
+            state.remove_secondary_outputs_where(|id, _| *id == ids[0] || *id == ids[1]);
+            assert_eq!(state.secondary_output_selected_index, 0);
+            assert_eq!(state.secondary_outputs[0].id, ids[2]);
+            // Removing the selected last output moves the selection up instead
+            state.move_cursor_to(1);
+            state.remove_secondary_outputs_where(|id, _| *id == ids[3]);
+            assert_eq!(state.secondary_output_selected_index, 0);
+            state.remove_secondary_outputs_where(|_, _| true);
+            assert_eq!(state.secondary_output_selected_index, 0);
+        }
+
         #[test]
         fn removing_output_moves_selection_down() {
             assert_state_output!(|state| {
@@ -542,6 +7578,38 @@ mod test {
                     .unwrap();
             });
         }
+
+        #[test]
+        fn zero_max_lines_only_shows_headers() {
+            assert_state_output!(0, |state| {
+                let id = state.new_secondary_output("out".into());
+                state
+                    .handle_secondary_bytes(&id, b"a\r\nb\r\nc\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+            });
+        }
+
+        #[test]
+        fn one_max_line_shows_last_line() {
+            assert_state_output!(1, |state| {
+                let id = state.new_secondary_output("out".into());
+                state
+                    .handle_secondary_bytes(&id, b"a\r\nb\r\nc\r\n")
+                    .unwrap()
+                    .toggle_current_selection_expanded()
+                    .render()
+                    .unwrap();
+                // Partial lines count as the last line
+                state
+                    .handle_secondary_bytes(&id, b"d")
+                    .unwrap()
+                    .render()
+                    .unwrap();
+            });
+        }
     }
 
     /*
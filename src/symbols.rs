@@ -0,0 +1,65 @@
+/// Whether this build uses Unicode symbols when rendering. Disabled by the `no-unicode` feature.
+pub const UNICODE_SUPPORTED: bool = !cfg!(feature = "no-unicode");
+
+/// The symbols used when rendering, so they can be swapped out for terminals without Unicode.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Symbols {
+    pub(crate) expanded: &'static str,
+    pub(crate) collapsed: &'static str,
+    pub(crate) ellipsis: &'static str,
+    pub(crate) bell: &'static str,
+    pub(crate) paused: &'static str,
+    pub(crate) pinned: &'static str,
+    /// Between the parts of the summary line.
+    pub(crate) separator: &'static str,
+    pub(crate) scrollbar_track: &'static str,
+    pub(crate) scrollbar_thumb: &'static str,
+    pub(crate) range_border: &'static str,
+}
+
+impl Symbols {
+    pub(crate) const UNICODE: Symbols = Symbols {
+        expanded: "+++",
+        collapsed: "---",
+        ellipsis: "…",
+        bell: "🔔",
+        paused: "⏸",
+        pinned: "📌",
+        separator: " · ",
+        scrollbar_track: "│",
+        scrollbar_thumb: "█",
+        range_border: "▎",
+    };
+
+    pub(crate) const ASCII: Symbols = Symbols {
+        expanded: "[+]",
+        collapsed: "[-]",
+        ellipsis: "...",
+        bell: "(!)",
+        paused: "||",
+        pinned: "(pin)",
+        separator: " - ",
+        scrollbar_track: "|",
+        scrollbar_thumb: "#",
+        range_border: "|",
+    };
+
+    /// Picks symbols based on the value of the `TERM` environment variable.
+    pub(crate) fn for_term(term: Option<&str>) -> Symbols {
+        if !UNICODE_SUPPORTED || term == Some("dumb") {
+            Self::ASCII
+        } else {
+            Self::UNICODE
+        }
+    }
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        if UNICODE_SUPPORTED {
+            Self::UNICODE
+        } else {
+            Self::ASCII
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Helpers for writing tests against [State], eg snapshot tests of what gets rendered.
+//!
+//! Only available with the `testing` feature, which should only be enabled from
+//! `[dev-dependencies]`.
+
+use crate::state::{Instant, State, StateBuilder};
+use std::cell::{Ref, RefCell};
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many lines of each secondary output [render_state] shows when expanded.
+pub const DEFAULT_MAX_LINES: usize = 3;
+
+/// Plays `bytes` through a virtual 50x50 terminal and returns the resulting screen contents.
+pub fn rasterize(bytes: &[u8]) -> String {
+    rasterize_with_size(bytes, 50, 50)
+}
+
+/// Same as [rasterize], but with a terminal `width` columns wide and `height` rows tall.
+pub fn rasterize_with_size(bytes: &[u8], width: u16, height: u16) -> String {
+    let mut parser = vt100::Parser::new(height, width, 50);
+    parser.process(bytes);
+    parser.screen().contents()
+}
+
+/// Runs `f` against a fresh [State] and returns everything it wrote, including control sequences.
+pub fn render_state<F: FnOnce(&mut State<Vec<u8>>)>(f: F) -> String {
+    render_state_with_max_lines(DEFAULT_MAX_LINES, f)
+}
+
+/// Same as [render_state], but showing `max_lines` lines of each expanded secondary output.
+pub fn render_state_with_max_lines<F: FnOnce(&mut State<Vec<u8>>)>(
+    max_lines: usize,
+    f: F,
+) -> String {
+    let mut output: Vec<u8> = Vec::new();
+    {
+        let mut state = State::new(&mut output, max_lines);
+        f(&mut state);
+    }
+    String::from_utf8(output).unwrap()
+}
+
+/// A clock which only moves when told to, for use with [StateBuilder::clock]. Clones share the
+/// same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    pub fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
+
+/// Where a [TestHarness]'s [State] writes to, shared so the harness can read it back.
+#[derive(Debug, Default, Clone)]
+pub struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer which keeps each write separately, along with when it happened. Useful for checking
+/// which bytes were written by which render, rather than only the output as a whole.
+pub struct MockWriter {
+    /// The bytes from each write call, and when it was made.
+    pub writes: Vec<(Instant, Vec<u8>)>,
+    clock: Arc<dyn Fn() -> Instant + Send + Sync>,
+}
+
+impl Default for MockWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockWriter {
+    /// A writer which timestamps writes with [Instant::now].
+    pub fn new() -> Self {
+        Self::with_clock(Instant::now)
+    }
+
+    /// A writer which timestamps writes using `clock`, eg the same [ManualClock] the [State] uses.
+    pub fn with_clock(clock: impl Fn() -> Instant + Send + Sync + 'static) -> Self {
+        Self {
+            writes: Vec::new(),
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Everything written so far, as if it had all been written at once.
+    pub fn full_output(&self) -> Vec<u8> {
+        self.writes
+            .iter()
+            .flat_map(|(_, bytes)| bytes)
+            .copied()
+            .collect()
+    }
+}
+
+impl Write for MockWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writes.push(((self.clock)(), buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [State] along with everything needed to test it deterministically: somewhere to write to,
+/// and a [ManualClock] for it to tell the time with. The state borrows its writer, so harnesses
+/// only exist for the duration of [TestHarness::run].
+///
+/// ```
+/// # use multi_output_viewer::testing::TestHarness;
+/// # use std::time::Duration;
+/// TestHarness::run(|harness| {
+///     harness.state().new_secondary_output("build".into());
+///     harness.clock().advance(Duration::from_secs(2));
+///     harness.state().render().unwrap();
+///     assert_eq!(harness.rasterize(20, 3), "\n> ---   2s build");
+/// });
+/// ```
+pub struct TestHarness<'a> {
+    output: SharedOutput,
+    clock: ManualClock,
+    state: State<'a, SharedOutput>,
+}
+
+impl<'a> TestHarness<'a> {
+    /// Runs `f` with a harness whose state shows [DEFAULT_MAX_LINES] lines of each expanded
+    /// secondary output.
+    pub fn run<R>(f: impl FnOnce(&mut TestHarness) -> R) -> R {
+        TestHarness::run_with_builder(
+            StateBuilder::new().secondary_output_max_lines(DEFAULT_MAX_LINES),
+            f,
+        )
+    }
+
+    /// Runs `f` with a harness whose state is configured by `builder`. Any clock set on the
+    /// builder is replaced with the harness's [ManualClock].
+    pub fn run_with_builder<R>(builder: StateBuilder, f: impl FnOnce(&mut TestHarness) -> R) -> R {
+        let output = SharedOutput::default();
+        let mut writer = output.clone();
+        let clock = ManualClock::new();
+        let state_clock = clock.clone();
+        let state = builder.clock(move || state_clock.now()).build(&mut writer);
+        f(&mut TestHarness {
+            output,
+            clock,
+            state,
+        })
+    }
+
+    pub fn state(&mut self) -> &mut State<'a, SharedOutput> {
+        &mut self.state
+    }
+
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// Everything the state has written so far, including control sequences.
+    pub fn raw(&self) -> Ref<'_, str> {
+        Ref::map(self.output.0.borrow(), |output| {
+            std::str::from_utf8(output).expect("Output should be valid UTF-8")
+        })
+    }
+
+    /// Plays everything written so far through a virtual terminal `width` columns wide and
+    /// `height` rows tall, and returns the resulting screen contents.
+    pub fn rasterize(&self, width: u16, height: u16) -> String {
+        rasterize_with_size(self.output.0.borrow().as_slice(), width, height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn harness_example() {
+        TestHarness::run(|harness| {
+            let id = harness.state().new_secondary_output("tests".into());
+            harness
+                .state()
+                .handle_primary_bytes(b"$ cargo test\r\n")
+                .handle_secondary_bytes(&id, b"running 2 tests\r\ntest a ... ok\r\n")
+                .unwrap()
+                .toggle_current_selection_expanded()
+                .render()
+                .unwrap();
+            harness.clock().advance(Duration::from_secs(3));
+            harness.state().render().unwrap();
+            assert_eq!(
+                harness.rasterize(30, 10),
+                "$ cargo test\n\n> +++   3s tests\nrunning 2 tests\ntest a ... ok"
+            );
+            assert!(harness.raw().starts_with("$ cargo test\r\n"));
+        });
+    }
+
+    #[test]
+    fn mock_writer_records_when_bytes_were_written() {
+        let clock = ManualClock::new();
+        let writer_clock = clock.clone();
+        let mut writer = MockWriter::with_clock(move || writer_clock.now());
+        let start = clock.now();
+        {
+            let state_clock = clock.clone();
+            let mut state = StateBuilder::new()
+                .clock(move || state_clock.now())
+                .build(&mut writer);
+            state.handle_primary_bytes(b"first").render().unwrap();
+            clock.advance(Duration::from_secs(1));
+            state.handle_primary_bytes(b"second").render().unwrap();
+        }
+        let first_render = writer
+            .writes
+            .iter()
+            .filter(|(time, _)| *time == start)
+            .flat_map(|(_, bytes)| bytes)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(rasterize(&first_render), "first");
+        assert!(writer
+            .writes
+            .iter()
+            .all(|(time, _)| *time == start || *time == start + Duration::from_secs(1)));
+        assert_eq!(rasterize(&writer.full_output()), "firstsecond");
+    }
+}
@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+use unicode_width::UnicodeWidthChar;
+
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
+/// Splits a string into (roughly) the units a terminal renders as a single glyph: a base
+/// character followed by any zero-width characters (eg combining marks), with zero-width joiners
+/// pulling the following character into the same unit. This isn't full grapheme segmentation,
+/// but is enough to avoid cutting a glyph in half.
+fn clusters(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let mut chars = rest.char_indices().peekable();
+        let (_, first) = chars.next()?;
+        let mut joining = first == ZERO_WIDTH_JOINER;
+        let mut end = rest.len();
+        while let Some(&(idx, c)) = chars.peek() {
+            if !joining && c != ZERO_WIDTH_JOINER && char_width(c) > 0 {
+                end = idx;
+                break;
+            }
+            joining = c == ZERO_WIDTH_JOINER;
+            chars.next();
+        }
+        let (cluster, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some(cluster)
+    })
+}
+
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Terminals render a cluster at the width of its first character, eg an emoji joined with other
+/// emoji still only takes up the width of one.
+fn cluster_width(cluster: &str) -> usize {
+    cluster.chars().next().map_or(0, char_width)
+}
+
+/// The number of columns `s` takes up when printed to a terminal.
+pub fn display_width(s: &str) -> usize {
+    clusters(s).map(cluster_width).sum()
+}
+
+/// Shortens `s` to fit within `max_width` columns, marking the cut with `ellipsis`.
+pub fn truncate_to_width<'a>(s: &'a str, max_width: usize, ellipsis: &str) -> Cow<'a, str> {
+    if display_width(s) <= max_width {
+        return Cow::Borrowed(s);
+    }
+    let ellipsis_width = display_width(ellipsis);
+    if max_width < ellipsis_width {
+        return Cow::Borrowed("");
+    }
+    let mut width = 0;
+    let mut end = 0;
+    for cluster in clusters(s) {
+        width += cluster_width(cluster);
+        if width + ellipsis_width > max_width {
+            break;
+        }
+        end += cluster.len();
+    }
+    Cow::Owned(format!("{}{ellipsis}", &s[..end]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn truncate_to_width(s: &str, max_width: usize) -> Cow<'_, str> {
+        super::truncate_to_width(s, max_width, "…")
+    }
+
+    #[test]
+    fn measures_wide_characters() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("テスト"), 6);
+        assert_eq!(display_width("🚀 build"), 8);
+    }
+
+    #[test]
+    fn measures_clusters_as_one_glyph() {
+        // "e" with a combining acute accent
+        assert_eq!(display_width("e\u{301}"), 1);
+        // Family emoji built from zero-width joiners
+        assert_eq!(display_width("👨\u{200d}👩\u{200d}👧"), 2);
+    }
+
+    #[test]
+    fn leaves_short_strings_alone() {
+        assert_eq!(truncate_to_width("🚀 build", 8), "🚀 build");
+    }
+
+    #[test]
+    fn truncates_wide_characters() {
+        assert_eq!(truncate_to_width("🚀 build", 5), "🚀 b…");
+        assert_eq!(truncate_to_width("テスト", 5), "テス…");
+        // Doesn't split a wide character to fill the space
+        assert_eq!(truncate_to_width("テスト", 4), "テ…");
+        assert_eq!(truncate_to_width("テスト", 1), "…");
+        assert_eq!(truncate_to_width("テスト", 0), "");
+    }
+
+    #[test]
+    fn does_not_split_clusters() {
+        assert_eq!(truncate_to_width("ae\u{301}bc", 3), "ae\u{301}…");
+        assert_eq!(
+            truncate_to_width("👨\u{200d}👩\u{200d}👧 family", 3),
+            "👨\u{200d}👩\u{200d}👧…"
+        );
+    }
+
+    #[test]
+    fn truncates_with_ascii_ellipsis() {
+        assert_eq!(super::truncate_to_width("testing", 5, "..."), "te...");
+        assert_eq!(super::truncate_to_width("testing", 2, "..."), "");
+    }
+}
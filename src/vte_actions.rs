@@ -1,12 +1,15 @@
 use crate::vte_actions::VteAction::{
-    CarriageReturn, CursorBackward, CursorDown, CursorForward, CursorNextLine, CursorPreviousLine,
-    CursorUp, LineFeed, Tab, Text,
+    CarriageReturn, ClearTabStops, CursorBackward, CursorColumn, CursorDown, CursorForward,
+    CursorNextLine, CursorPosition, CursorPositionQuery, CursorPreviousLine, CursorRow, CursorUp,
+    DcsData, DcsEnd, DcsStart, DeleteChars, HideCursor, Ignored, Index, InsertBlank, LineFeed,
+    RepeatLastChar, ReverseIndex, SetMouseMode, SetScrollRegion, SetStyle, SetTabStop, SetTitle,
+    ShowCursor, Tab, Text,
 };
 use vte::{Params, Parser, Perform};
 
 /// The semantic actions that can be taken as a result of bytes sent to the terminal.
 // TODO - Implement more actions to be complete here (as needed?)
-#[derive(Debug)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum VteAction {
     Text(char),
     Tab,
@@ -18,6 +21,75 @@ pub enum VteAction {
     CursorBackward(u16),
     CursorNextLine(u16),
     CursorPreviousLine(u16),
+    /// Moves to an absolute (1-based) column, from CHA (`CSI G`) or HPA (`` CSI ` ``).
+    CursorColumn(u16),
+    /// Moves to an absolute (1-based) row, from VPA (`CSI d`).
+    CursorRow(u16),
+    /// Moves to an absolute (1-based) row and column, from CUP (`CSI H`) or HVP (`CSI f`).
+    CursorPosition(u16, u16),
+    /// The (1-based) top and bottom rows which scroll, from DECSTBM (`CSI r`). A bottom of 0 means
+    /// the bottom of the terminal.
+    SetScrollRegion(u16, u16),
+    /// Moves down a line, scrolling if at the bottom of the scroll region (IND, `ESC D`).
+    Index,
+    /// Moves up a line, scrolling if at the top of the scroll region (RI, `ESC M`).
+    ReverseIndex,
+    /// Sets a tab stop at the cursor's column (HTS, `ESC H`).
+    SetTabStop,
+    /// Clears the tab stop at the cursor's column (mode 0) or all tab stops (mode 3), from TBC
+    /// (`CSI g`).
+    ClearTabStops(u16),
+    /// Prints the last printed character again this many times (REP, `CSI b`). Only produced if
+    /// the last thing printed was a character, rather than eg a line feed.
+    RepeatLastChar(u16),
+    /// Inserts this many blanks at the cursor, shifting the rest of the line right without moving
+    /// the cursor (ICH, `CSI @`).
+    InsertBlank(u16),
+    /// Deletes this many characters at the cursor, shifting the rest of the line left without
+    /// moving the cursor (DCH, `CSI P`).
+    DeleteChars(u16),
+    /// Changes the styling of text printed after it (SGR, `CSI m`), with each parameter's
+    /// sub-parameters (eg the `2:1:2:3` of `CSI 38:2:1:2:3 m`) after it. No parameters at all
+    /// comes through as a single 0.
+    SetStyle(Vec<Vec<u16>>),
+    /// The window title, from OSC 0 or OSC 2.
+    SetTitle(String),
+    /// Shows the cursor (DECTCEM, `CSI ?25h`).
+    ShowCursor,
+    /// Hides the cursor (DECTCEM, `CSI ?25l`).
+    HideCursor,
+    /// Turns a mouse reporting mode on or off, eg `CSI ?1000h`. The modes are 1000 (presses and
+    /// releases), 1002 (drags too), 1003 (all motion), and 1006 (reporting in SGR's format).
+    SetMouseMode(u16, bool),
+    /// A request for the terminal to report where the cursor is (DSR, `CSI 6n`).
+    CursorPositionQuery,
+    /// The start of a Device Control String, with the bytes between `ESC P` and the data (ie the
+    /// parameters, intermediates, and final byte). DCS sequences matter for passing things
+    /// through to the outer terminal, eg tmux's passthrough mode wraps sequences meant for the
+    /// outer terminal in `ESC P tmux; ... ESC \`.
+    DcsStart(Vec<u8>),
+    /// A byte of the current Device Control String's data.
+    DcsData(u8),
+    /// The end of the current Device Control String.
+    DcsEnd,
+    /// A sequence which doesn't have an action of its own. The sequence itself (reconstructed
+    /// from what was parsed) is only included for diagnostics if
+    /// [VteActionParser::set_keep_ignored_raw] asked for it, since building it allocates.
+    Ignored {
+        kind: IgnoredKind,
+        raw: Option<String>,
+    },
+}
+
+/// What sort of sequence a [VteAction::Ignored] was.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IgnoredKind {
+    /// `ESC [ ...`
+    Csi,
+    /// `ESC ...`, other than the introducers of the other kinds
+    Esc,
+    /// `ESC ] ...`
+    Osc,
 }
 
 /// A wrapper over [Parser] and [Perform] which takes bytes in and exposes an iterator
@@ -25,52 +97,109 @@ pub enum VteAction {
 /// of cells.
 pub struct VteActionParser {
     parser: Parser,
+    /// The last character printed, if nothing else has been since, for [VteAction::RepeatLastChar].
+    last_printed: Option<char>,
+    /// Whether [VteAction::Ignored] includes the sequence itself.
+    keep_ignored_raw: bool,
+}
+
+impl Default for VteActionParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VteActionParser {
     pub fn new() -> Self {
         Self {
             parser: Parser::new(),
+            last_printed: None,
+            keep_ignored_raw: false,
         }
     }
 
+    /// Drops any partially parsed sequence, eg when the process on the other end restarts.
+    pub fn reset(&mut self) {
+        *self = Self {
+            keep_ignored_raw: self.keep_ignored_raw,
+            ..Self::new()
+        };
+    }
+
+    /// Whether to include the sequence itself in each [VteAction::Ignored], eg for logging
+    /// sequences which aren't understood. Off by default.
+    pub fn set_keep_ignored_raw(&mut self, keep: bool) -> &mut Self {
+        self.keep_ignored_raw = keep;
+        self
+    }
+
     pub fn parse_bytes(&mut self, bytes: &[u8]) -> Vec<VteAction> {
-        let mut performer = Performer::new();
+        let mut actions = Vec::new();
+        self.parse_bytes_streaming(bytes, |action| actions.push(action));
+        actions
+    }
+
+    /// Like [VteActionParser::parse_bytes], but hands each action to `callback` as it's parsed
+    /// rather than collecting them.
+    pub fn parse_bytes_streaming<F: FnMut(VteAction)>(&mut self, bytes: &[u8], callback: F) {
+        let mut performer = Performer {
+            callback,
+            last_printed: &mut self.last_printed,
+            keep_ignored_raw: self.keep_ignored_raw,
+        };
         for byte in bytes {
             self.parser.advance(&mut performer, *byte)
         }
-        performer.actions
     }
 }
 
-// Private struct to hide this implementation detail
-struct Performer {
-    actions: Vec<VteAction>,
+/// Where to start reading `bytes` if everything before `cut` is dropped, so as not to start
+/// partway through a line, escape sequence or UTF-8 character. That's `cut` itself if it's at the
+/// start of a line, otherwise just after the next newline if there is one, otherwise the next
+/// escape, otherwise the next character boundary.
+pub(crate) fn resync_point(bytes: &[u8], cut: usize) -> usize {
+    if cut == 0 || bytes[cut - 1] == b'\n' {
+        return cut;
+    }
+    let rest = &bytes[cut..];
+    let offset = if let Some(newline) = rest.iter().position(|byte| *byte == b'\n') {
+        newline + 1
+    } else if let Some(escape) = rest.iter().position(|byte| *byte == 0x1b) {
+        escape
+    } else {
+        // UTF-8 continuation bytes are 0b10xxxxxx
+        rest.iter()
+            .position(|byte| byte & 0xc0 != 0x80)
+            .unwrap_or(rest.len())
+    };
+    cut + offset
 }
 
-impl Performer {
-    fn new() -> Self {
-        Self {
-            actions: Vec::new(),
-        }
-    }
+// Private struct to hide this implementation detail
+struct Performer<'a, F: FnMut(VteAction)> {
+    callback: F,
+    last_printed: &'a mut Option<char>,
+    keep_ignored_raw: bool,
 }
 
 // Implementation largely inspired by vt100-rust:
 // https://github.com/doy/vt100-rust/blob/main/src/perform.rs
-impl Perform for Performer {
+impl<'a, F: FnMut(VteAction)> Perform for Performer<'a, F> {
     fn print(&mut self, c: char) {
-        self.actions.push(Text(c))
+        *self.last_printed = Some(c);
+        (self.callback)(Text(c))
     }
 
     fn execute(&mut self, byte: u8) {
+        // Only characters printed immediately before can be repeated
+        *self.last_printed = None;
         let action = match byte {
             9 => Tab,
             10 => LineFeed,
             13 => CarriageReturn,
             _ => return,
         };
-        self.actions.push(action);
+        (self.callback)(action);
     }
 
     fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, c: char) {
@@ -82,15 +211,145 @@ impl Perform for Performer {
                 'D' => CursorBackward(params.canonicalize_1(1)),
                 'E' => CursorNextLine(params.canonicalize_1(1)),
                 'F' => CursorPreviousLine(params.canonicalize_1(1)),
-                _ => return,
+                'G' | '`' => CursorColumn(params.canonicalize_1(1)),
+                'd' => CursorRow(params.canonicalize_1(1)),
+                'H' | 'f' => {
+                    let (row, column) = params.canonicalize_2(1, 1);
+                    CursorPosition(row, column)
+                }
+                'n' if params.canonicalize_1(0) == 6 => CursorPositionQuery,
+                'r' => {
+                    let (top, bottom) = params.canonicalize_2(1, 0);
+                    SetScrollRegion(top, bottom)
+                }
+                'b' if self.last_printed.is_some() => RepeatLastChar(params.canonicalize_1(1)),
+                '@' => InsertBlank(params.canonicalize_1(1)),
+                'P' => DeleteChars(params.canonicalize_1(1)),
+                'g' => ClearTabStops(params.canonicalize_1(0)),
+                'm' => SetStyle(params.iter().map(<[u16]>::to_vec).collect()),
+                _ => ignored_csi(params, intermediates, c, self.keep_ignored_raw),
+            };
+            (self.callback)(action);
+        } else if intermediates == b"?" {
+            // Private modes, which can be set several at a time
+            let action = match (params.len(), params.canonicalize_1(0), c) {
+                (1, 25, 'h') => ShowCursor,
+                (1, 25, 'l') => HideCursor,
+                (1, mode @ (1000 | 1002 | 1003 | 1006), 'h') => SetMouseMode(mode, true),
+                (1, mode @ (1000 | 1002 | 1003 | 1006), 'l') => SetMouseMode(mode, false),
+                _ => ignored_csi(params, intermediates, c, self.keep_ignored_raw),
             };
-            self.actions.push(action);
+            (self.callback)(action);
+        } else {
+            (self.callback)(ignored_csi(params, intermediates, c, self.keep_ignored_raw));
         }
     }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if intermediates.is_empty() {
+            let action = match byte {
+                b'D' => Index,
+                b'M' => ReverseIndex,
+                b'H' => SetTabStop,
+                // The string terminator (ST), whose string has already been dispatched
+                b'\\' => return,
+                _ => ignored_esc(intermediates, byte, self.keep_ignored_raw),
+            };
+            (self.callback)(action);
+        } else {
+            (self.callback)(ignored_esc(intermediates, byte, self.keep_ignored_raw));
+        }
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        let mut header = format_params(params).into_bytes();
+        header.extend(intermediates);
+        let mut action_bytes = [0; 4];
+        header.extend(action.encode_utf8(&mut action_bytes).as_bytes());
+        (self.callback)(DcsStart(header));
+    }
+
+    fn put(&mut self, byte: u8) {
+        (self.callback)(DcsData(byte));
+    }
+
+    fn unhook(&mut self) {
+        (self.callback)(DcsEnd);
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        if let [b"0" | b"2", title @ ..] = params {
+            // The title itself may have contained semicolons
+            let title = String::from_utf8_lossy(&title.join(&b';')).into_owned();
+            (self.callback)(SetTitle(title));
+        } else {
+            let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+            (self.callback)(Ignored {
+                kind: IgnoredKind::Osc,
+                raw: self.keep_ignored_raw.then(|| {
+                    format!(
+                        "\x1b]{}{terminator}",
+                        String::from_utf8_lossy(&params.join(&b';'))
+                    )
+                }),
+            });
+        }
+    }
+}
+
+/// Formats parameters the way they'd have been sent, eg `1;2:3`.
+fn format_params(params: &Params) -> String {
+    // No parameters at all shows up as a single 0, which means the same thing, but keep things the
+    // same as they came in where possible
+    if params.len() == 1 && params.iter().next() == Some(&[0][..]) {
+        return String::new();
+    }
+    params
+        .iter()
+        .map(|param| {
+            let subparams = param.iter().map(u16::to_string).collect::<Vec<_>>();
+            subparams.join(":")
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn ignored_csi(params: &Params, intermediates: &[u8], c: char, keep_raw: bool) -> VteAction {
+    let raw = keep_raw.then(|| {
+        // Private markers (eg the `?` in `CSI ? 25 h`) come before the parameters, while other
+        // intermediates come after
+        let (private, intermediates): (Vec<u8>, Vec<u8>) = intermediates
+            .iter()
+            .partition(|byte| (0x3c..=0x3f).contains(*byte));
+        format!(
+            "\x1b[{}{}{}{c}",
+            String::from_utf8_lossy(&private),
+            format_params(params),
+            String::from_utf8_lossy(&intermediates)
+        )
+    });
+    Ignored {
+        kind: IgnoredKind::Csi,
+        raw,
+    }
+}
+
+fn ignored_esc(intermediates: &[u8], byte: u8, keep_raw: bool) -> VteAction {
+    Ignored {
+        kind: IgnoredKind::Esc,
+        raw: keep_raw.then(|| {
+            format!(
+                "\x1b{}{}",
+                String::from_utf8_lossy(intermediates),
+                byte as char
+            )
+        }),
+    }
 }
 
 trait ParamsCanonicalize {
     fn canonicalize_1(&self, default: u16) -> u16;
+    fn canonicalize_2(&self, default1: u16, default2: u16) -> (u16, u16);
 }
 
 impl ParamsCanonicalize for Params {
@@ -101,4 +360,333 @@ impl ParamsCanonicalize for Params {
             .filter(|x| *x != 0)
             .unwrap_or(default)
     }
+
+    fn canonicalize_2(&self, default1: u16, default2: u16) -> (u16, u16) {
+        let mut iter = self.iter();
+        let mut next = |default| {
+            iter.next()
+                .and_then(|x| x.first().copied())
+                .filter(|x| *x != 0)
+                .unwrap_or(default)
+        };
+        let first = next(default1);
+        (first, next(default2))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parser_keeping_raw() -> VteActionParser {
+        let mut parser = VteActionParser::new();
+        parser.set_keep_ignored_raw(true);
+        parser
+    }
+
+    #[test]
+    fn parses_sequences_split_across_calls() {
+        let mut parser = VteActionParser::new();
+        assert_eq!(parser.parse_bytes(b"a\x1b["), vec![Text('a')]);
+        assert_eq!(parser.parse_bytes(b"5A"), vec![CursorUp(5)]);
+    }
+
+    #[test]
+    fn reset_drops_partial_sequences() {
+        let mut parser = VteActionParser::new();
+        assert_eq!(parser.parse_bytes(b"a\x1b[5"), vec![Text('a')]);
+        parser.reset();
+        assert_eq!(
+            parser.parse_bytes(b"A\r\n\x1b[2C"),
+            vec![Text('A'), CarriageReturn, LineFeed, CursorForward(2)]
+        );
+    }
+
+    #[test]
+    fn resyncs_at_line_sequence_or_character_starts() {
+        assert_eq!(resync_point(b"\x1b[35mtail\x1b[0m\nnext", 3), 14);
+        assert_eq!(resync_point(b"\x1b[35mtail\x1b[0m", 3), 9);
+        assert_eq!(resync_point("t\u{e9}t\u{e9}".as_bytes(), 2), 3);
+        assert_eq!(resync_point("\u{e9}".as_bytes(), 1), 2);
+        // Cutting at the start of a line doesn't drop the line
+        assert_eq!(resync_point(b"one\ntwo\n", 4), 4);
+        assert_eq!(resync_point(b"one\ntwo\n", 0), 0);
+    }
+
+    #[test]
+    fn streaming_matches_collected() {
+        let bytes = b"ab\tc\r\n\x1b[3D\x1b]0;title\x07\x1b[E";
+        let mut streamed = Vec::new();
+        VteActionParser::new().parse_bytes_streaming(bytes, |action| streamed.push(action));
+        assert_eq!(streamed, VteActionParser::new().parse_bytes(bytes));
+    }
+
+    #[test]
+    fn parses_absolute_cursor_movement() {
+        let mut parser = VteActionParser::new();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[G\x1b[12G\x1b[3`\x1b[0d\x1b[7d"),
+            vec![
+                CursorColumn(1),
+                CursorColumn(12),
+                CursorColumn(3),
+                CursorRow(1),
+                CursorRow(7)
+            ]
+        );
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[f\x1b[5;9f\x1b[;4f\x1b[3H\x1b[0;0H"),
+            vec![
+                CursorPosition(1, 1),
+                CursorPosition(5, 9),
+                CursorPosition(1, 4),
+                CursorPosition(3, 1),
+                CursorPosition(1, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_escape_sequences() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[2;20r\x1bD\x1bM\x1bH\x1b[g\x1b[3g\x1b[r\x1b[5r\x1b#D"),
+            vec![
+                SetScrollRegion(2, 20),
+                Index,
+                ReverseIndex,
+                SetTabStop,
+                ClearTabStops(0),
+                ClearTabStops(3),
+                SetScrollRegion(1, 0),
+                SetScrollRegion(5, 0),
+                Ignored {
+                    kind: IgnoredKind::Esc,
+                    raw: Some("\x1b#D".into())
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_repeats_and_character_edits() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"a\x1b[3b\x1b[b\x1b[2@\x1b[P"),
+            vec![
+                Text('a'),
+                RepeatLastChar(3),
+                RepeatLastChar(1),
+                InsertBlank(2),
+                DeleteChars(1)
+            ]
+        );
+        // The last character is remembered across calls, but not past other control characters
+        assert_eq!(parser.parse_bytes(b"\x1b[2b"), vec![RepeatLastChar(2)]);
+        assert_eq!(
+            parser.parse_bytes(b"\r\x1b[2b"),
+            vec![
+                CarriageReturn,
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[2b".into())
+                }
+            ]
+        );
+        parser.parse_bytes(b"a");
+        parser.reset();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[b"),
+            vec![Ignored {
+                kind: IgnoredKind::Csi,
+                raw: Some("\x1b[b".into())
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_cursor_position_queries() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[6n\x1b[5n\x1b[?6n"),
+            vec![
+                CursorPositionQuery,
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[5n".into())
+                },
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[?6n".into())
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_device_control_strings() {
+        let mut parser = VteActionParser::new();
+        assert_eq!(
+            parser.parse_bytes(b"\x1bP1;2$qm\x1b\\a"),
+            vec![
+                DcsStart(b"1;2$q".to_vec()),
+                DcsData(b'm'),
+                DcsEnd,
+                Text('a')
+            ]
+        );
+        // Split across calls
+        assert_eq!(
+            parser.parse_bytes(b"\x1bPtmux;"),
+            vec![
+                DcsStart(b"t".to_vec()),
+                DcsData(b'm'),
+                DcsData(b'u'),
+                DcsData(b'x'),
+                DcsData(b';')
+            ]
+        );
+        // The string ends as soon as the ESC of the terminator arrives
+        assert_eq!(parser.parse_bytes(b"a\x1b"), vec![DcsData(b'a'), DcsEnd]);
+        assert_eq!(parser.parse_bytes(b"\\b"), vec![Text('b')]);
+    }
+
+    #[test]
+    fn parses_cursor_visibility() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[?25l\x1b[?25h\x1b[25l\x1b[?25;1049l"),
+            vec![
+                HideCursor,
+                ShowCursor,
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[25l".into())
+                },
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[?25;1049l".into())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_mouse_modes() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[?1002h\x1b[?1006h\x1b[?1002l\x1b[?1005h"),
+            vec![
+                SetMouseMode(1002, true),
+                SetMouseMode(1006, true),
+                SetMouseMode(1002, false),
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[?1005h".into())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_styles() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[1;31ma\x1b[38:2:1:2:3m\x1b[m\x1b[>4;2m"),
+            vec![
+                SetStyle(vec![vec![1], vec![31]]),
+                Text('a'),
+                SetStyle(vec![vec![38, 2, 1, 2, 3]]),
+                SetStyle(vec![vec![0]]),
+                Ignored {
+                    kind: IgnoredKind::Csi,
+                    raw: Some("\x1b[>4;2m".into())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_ignored_sequences() {
+        let mut parser = parser_keeping_raw();
+        let actions = parser.parse_bytes(
+            b"\x1b[22;0ta\x1b[?1049h\x1b[2J\x1b[>4;2m\x1b[>1u\x1b[1 q\x1b(B\x1b=\
+              \x1b]8;;https://example.com\x1b\\b\x1b]133;A\x07\x1b[J\x1b[2A",
+        );
+        let (ignored, known): (Vec<_>, Vec<_>) = actions
+            .into_iter()
+            .partition(|action| matches!(action, Ignored { .. }));
+        assert_eq!(known, vec![Text('a'), Text('b'), CursorUp(2)]);
+        assert_eq!(
+            ignored,
+            [
+                (IgnoredKind::Csi, "\x1b[22;0t"),
+                (IgnoredKind::Csi, "\x1b[?1049h"),
+                (IgnoredKind::Csi, "\x1b[2J"),
+                (IgnoredKind::Csi, "\x1b[>4;2m"),
+                (IgnoredKind::Csi, "\x1b[>1u"),
+                (IgnoredKind::Csi, "\x1b[1 q"),
+                (IgnoredKind::Esc, "\x1b(B"),
+                (IgnoredKind::Esc, "\x1b="),
+                (IgnoredKind::Osc, "\x1b]8;;https://example.com\x1b\\"),
+                (IgnoredKind::Osc, "\x1b]133;A\x07"),
+                (IgnoredKind::Csi, "\x1b[J"),
+            ]
+            .into_iter()
+            .map(|(kind, raw)| Ignored {
+                kind,
+                raw: Some(raw.into())
+            })
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn leaves_out_ignored_sequences_unless_asked() {
+        let mut parser = VteActionParser::new();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[2J\x1b(B\x1b]1;icon\x07"),
+            [IgnoredKind::Csi, IgnoredKind::Esc, IgnoredKind::Osc]
+                .into_iter()
+                .map(|kind| Ignored { kind, raw: None })
+                .collect::<Vec<_>>()
+        );
+        // Resetting keeps the setting
+        parser.set_keep_ignored_raw(true).reset();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b[2J"),
+            vec![Ignored {
+                kind: IgnoredKind::Csi,
+                raw: Some("\x1b[2J".into())
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_titles() {
+        let mut parser = parser_keeping_raw();
+        assert_eq!(
+            parser.parse_bytes(b"\x1b]0;bell;terminated\x07\x1b]2;string terminated\x1b\\"),
+            vec![
+                SetTitle("bell;terminated".into()),
+                SetTitle("string terminated".into())
+            ]
+        );
+        assert_eq!(parser.parse_bytes(b"\x1b]2;sp"), vec![]);
+        assert_eq!(
+            parser.parse_bytes(b"lit\x07"),
+            vec![SetTitle("split".into())]
+        );
+        // Only the title is pulled out of other OSC sequences
+        assert_eq!(
+            parser.parse_bytes(b"\x1b]1;icon\x07a"),
+            vec![
+                Ignored {
+                    kind: IgnoredKind::Osc,
+                    raw: Some("\x1b]1;icon\x07".into())
+                },
+                Text('a')
+            ]
+        );
+    }
 }
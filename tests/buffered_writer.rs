@@ -0,0 +1,82 @@
+use multi_output_viewer::State;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+/// A file in the temp directory which is cleaned up when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        Self(
+            std::env::temp_dir().join(format!("multi_output_viewer_{}_{name}", std::process::id())),
+        )
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        std::fs::read(&self.0).unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Some primary output, a couple of secondary outputs with one expanded, rendered a few times.
+fn drive<W: std::io::Write>(state: &mut State<W>, renders: &mut impl FnMut(&mut State<W>)) {
+    // Real time passes in these tests, so keep it out of the output
+    state.set_show_elapsed(false);
+    let first = state.new_secondary_output("first".into());
+    let second = state.new_secondary_output("second".into());
+    state
+        .handle_primary_bytes(b"primary line\r\n")
+        .handle_secondary_bytes(&first, b"first output\r\n")
+        .unwrap()
+        .handle_secondary_bytes(&second, b"second output\r\n")
+        .unwrap()
+        .toggle_current_selection_expanded();
+    renders(state);
+    state
+        .handle_primary_bytes(b"more primary")
+        .move_cursor_down()
+        .toggle_current_selection_expanded();
+    renders(state);
+    renders(state);
+}
+
+#[test]
+fn render_flushes_buffered_writer() {
+    let file = TempFile::new("render_flushes_buffered_writer");
+    let mut writer = BufWriter::new(File::create(&file.0).unwrap());
+    let mut expected: Vec<u8> = Vec::new();
+    {
+        let mut expected_state = State::new(&mut expected, 3);
+        drive(&mut expected_state, &mut |state| state.render().unwrap());
+    }
+
+    let mut state = State::new(&mut writer, 3);
+    let mut rendered_len = 0;
+    drive(&mut state, &mut |state| {
+        state.render().unwrap();
+        // Everything rendered so far should have made it to the file, without needing to drop
+        // the writer.
+        let contents = file.contents();
+        assert!(contents.len() > rendered_len);
+        rendered_len = contents.len();
+    });
+    assert_eq!(file.contents(), expected);
+}
+
+#[test]
+fn render_without_changes_is_still_flushed() {
+    let file = TempFile::new("render_without_changes_is_still_flushed");
+    let mut writer = BufWriter::new(File::create(&file.0).unwrap());
+    let mut state = State::new(&mut writer, 3);
+    state.render().unwrap();
+    assert!(file.contents().is_empty());
+    state.handle_primary_bytes(b"hello").render().unwrap();
+    state.render().unwrap();
+    assert_eq!(file.contents(), b"hello");
+}
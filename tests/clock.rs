@@ -0,0 +1,21 @@
+use multi_output_viewer::StateBuilder;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[test]
+fn injected_clock_controls_elapsed_time() {
+    let start = Instant::now();
+    let offset = Arc::new(Mutex::new(Duration::ZERO));
+    let mut output = Vec::new();
+    {
+        let clock_offset = offset.clone();
+        let mut state = StateBuilder::new()
+            .clock(move || start + *clock_offset.lock().unwrap())
+            .build(&mut output);
+        state.new_secondary_output("first".into());
+        *offset.lock().unwrap() = Duration::from_millis(5500);
+        state.render().unwrap();
+    }
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("  5s first"), "{output:?}");
+}